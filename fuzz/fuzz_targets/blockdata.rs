@@ -0,0 +1,19 @@
+// getblock responses, through everything the parser does with a block
+// short of talking to ghostd or the DB.
+#![no_main]
+
+use ghostd_parser::console::BlockData;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut blockdata) = serde_json::from_slice::<BlockData>(data) {
+        let _ = blockdata.stakeaddress();
+        let _ = blockdata.read_vote();
+        let _ = blockdata.staker();
+        let _ = blockdata.vote_record();
+        for tx in &blockdata.tx {
+            let _ = tx.reward(blockdata.stakekernelvalue);
+        }
+        let _ = serde_json::to_value(&blockdata);
+    }
+});
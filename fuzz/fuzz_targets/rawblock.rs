@@ -0,0 +1,14 @@
+// Raw blocks and transactions, as getblock with verbosity 0 returns them.
+#![no_main]
+
+use ghostd_parser::{
+    decode::{self, HeaderInfo},
+    network::Network,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decode::transaction(data, Network::Mainnet);
+    let info = HeaderInfo::default();
+    let _ = decode::block(data, info, Network::Mainnet);
+});
@@ -0,0 +1,14 @@
+// tallyvotes answers, as JSON and as the per option strings inside them.
+#![no_main]
+
+use ghostd_parser::console::{parse_tallies, parse_tally};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(raw) = std::str::from_utf8(data) {
+        let _ = parse_tally(raw);
+    }
+    if let Ok(context) = serde_json::from_slice(data) {
+        let _ = parse_tallies(context);
+    }
+});
@@ -0,0 +1,11 @@
+// Vote strings from a coinstake's data output.
+#![no_main]
+
+use ghostd_parser::console::parse_vote;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(content) = std::str::from_utf8(data) {
+        let _ = parse_vote(content);
+    }
+});
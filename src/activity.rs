@@ -0,0 +1,112 @@
+// Pool activity derived from the blocks pools actually staked.
+use crate::{db, pools::Registry};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error};
+use surrealdb::{engine::any::Any, Surreal};
+
+const DAY: u64 = 86400;
+// Window for recent blocks and stake share, compared against the window before it.
+const ACTIVITY_WINDOW_DAYS: u64 = 7;
+// Pools silent for longer than this are inactive rather than dormant.
+const DORMANT_AFTER_DAYS: u64 = 90;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PoolStatus {
+    Active,
+    Dormant,
+    Inactive,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PoolActivity {
+    pub status: PoolStatus,
+    pub last_height: Option<u64>,
+    pub last_time: Option<u64>,
+    pub recent_blocks: u64,
+    pub share: f64,
+    pub previous_share: f64,
+    pub trend: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyLastStaked {
+    pub pubkey: String,
+    pub last_height: u64,
+    pub last_time: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyStatus {
+    pub pubkey: String,
+    pub status: PoolStatus,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyCount {
+    pub pubkey: String,
+    pub blocks: u64,
+}
+
+// Recompute activity of every registered pool relative to the chain tip
+// and relabel stakeaddresses of pools whose status flipped.
+pub async fn update(db: &Surreal<Any>, registry: &mut Registry) -> Result<(), Box<dyn Error>> {
+    let tip = match db::gettoptime(db).await? {
+        Some(time) => time,
+        None => return Ok(()),
+    };
+    debug!("Deriving pool activity ...");
+    let window = ACTIVITY_WINDOW_DAYS * DAY;
+    let since = tip.saturating_sub(window);
+    let before = since.saturating_sub(window);
+    let last: HashMap<String, KeyLastStaked> = db::getlaststaked(db)
+        .await?
+        .into_iter()
+        .map(|key| (key.pubkey.clone(), key))
+        .collect();
+    let (recent, recent_total) = db::getstakedbetween(db, since, tip).await?;
+    let (previous, previous_total) = db::getstakedbetween(db, before, since).await?;
+
+    let mut statuses: HashMap<String, PoolStatus> = HashMap::new();
+    for entry in registry.pools.iter() {
+        let last_staked = last.get(&entry.pubkey);
+        let recent_blocks = recent.get(&entry.pubkey).copied().unwrap_or(0);
+        let previous_blocks = previous.get(&entry.pubkey).copied().unwrap_or(0);
+        let status = match last_staked {
+            _ if recent_blocks > 0 => PoolStatus::Active,
+            Some(key) if tip.saturating_sub(key.last_time) <= DORMANT_AFTER_DAYS * DAY => {
+                PoolStatus::Dormant
+            }
+            _ => PoolStatus::Inactive,
+        };
+        let share = ratio(recent_blocks, recent_total);
+        let previous_share = ratio(previous_blocks, previous_total);
+        let activity = PoolActivity {
+            status,
+            last_height: last_staked.map(|key| key.last_height),
+            last_time: last_staked.map(|key| key.last_time),
+            recent_blocks,
+            share,
+            previous_share,
+            trend: share - previous_share,
+        };
+        db::regpoolactivity(db, &entry.pubkey, &activity).await?;
+        statuses.insert(entry.pubkey.clone(), status);
+    }
+    for pubkey in registry.set_statuses(statuses) {
+        info!(
+            "Pool {} is now {:?}.",
+            pubkey,
+            registry.status(&pubkey).unwrap()
+        );
+        db::relabelstakeaddresses(db, &registry.label(&pubkey)).await?;
+    }
+    Ok(())
+}
+
+fn ratio(part: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => part as f64 / total as f64,
+    }
+}
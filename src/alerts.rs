@@ -0,0 +1,391 @@
+// Alert rules evaluated against the live feed and delivered to webhooks.
+use crate::{
+    console::{BlockData, Vout},
+    db,
+    feed::{self, Event},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error,
+    fs,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use surrealdb::{engine::any::Any, Surreal};
+use tokio::sync::broadcast::{error::TryRecvError, Receiver};
+
+const DEFAULT_RETRIES: u32 = 5;
+// First retry waits this long, every next one twice as long.
+const BACKOFF_BASE: Duration = Duration::from_secs(2);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Condition {
+    NewProposal,
+    // Fires on reorgs rolling back more than `min_depth` blocks.
+    Reorg {
+        min_depth: u64,
+    },
+    // Share of the last `window` blocks staked by the pool.
+    PoolShare {
+        pool: String,
+        threshold: f64,
+        window: u64,
+    },
+    WatchedAddress {
+        address: String,
+    },
+    NoBlock {
+        minutes: u64,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    pub webhook: String,
+    #[serde(flatten)]
+    pub condition: Condition,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RulesFile {
+    retries: Option<u32>,
+    #[serde(rename = "rule", default)]
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StakeKey {
+    pub height: u64,
+    // None for solo stakes.
+    pub pubkey: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub rule: String,
+    pub kind: String,
+    pub message: String,
+    pub details: serde_json::Value,
+    pub time: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    pub rule: String,
+    pub webhook: String,
+    pub alert: Alert,
+    pub attempts: u32,
+    pub delivered: bool,
+    pub error: Option<String>,
+    pub time: u64,
+}
+
+pub struct Alerts {
+    rules: Vec<Rule>,
+    retries: u32,
+    events: Receiver<Event>,
+    last_block: Instant,
+    // No-block rules that fired since the last block.
+    stalled: HashSet<usize>,
+    // Recent stake keys per pool share rule, newest last.
+    windows: HashMap<usize, VecDeque<StakeKey>>,
+    above: HashMap<usize, bool>,
+}
+
+impl Alerts {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        info!("Loading alert rules from {} ...", path);
+        let file: RulesFile = toml::from_str(&fs::read_to_string(path)?)?;
+        Ok(Alerts {
+            rules: file.rules,
+            retries: file.retries.unwrap_or(DEFAULT_RETRIES),
+            events: feed::subscribe(),
+            last_block: Instant::now(),
+            stalled: HashSet::new(),
+            windows: HashMap::new(),
+            above: HashMap::new(),
+        })
+    }
+
+    // Events published before listening started are history, not news.
+    pub fn skip_backlog(&mut self) {
+        self.events = self.events.resubscribe();
+        self.last_block = Instant::now();
+    }
+
+    // Check everything the engine published since the last call.
+    pub async fn evaluate(&mut self, db: &Surreal<Any>) -> Result<(), Box<dyn Error>> {
+        loop {
+            let event = match self.events.try_recv() {
+                Ok(event) => event,
+                Err(TryRecvError::Lagged(missed)) => {
+                    warn!("Alerts missed {} events.", missed);
+                    continue;
+                }
+                Err(_) => break,
+            };
+            match &event {
+                Event::Block(_) => {
+                    self.last_block = Instant::now();
+                    self.stalled.clear();
+                }
+                // Orphaned blocks no longer count towards pool shares.
+                Event::Reorg { fork_height, .. } => {
+                    for keys in self.windows.values_mut() {
+                        keys.retain(|key| key.height <= *fork_height);
+                    }
+                }
+                _ => {}
+            }
+            for index in 0..self.rules.len() {
+                if let Some(alert) = self.check(index, &event, db).await? {
+                    self.deliver(index, alert, db);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn tick(&mut self, db: &Surreal<Any>) {
+        self.stall(self.last_block.elapsed(), db);
+    }
+
+    // Fire every no-block rule due after `silent` without a block, once
+    // per stall.
+    pub fn stall(&mut self, silent: Duration, db: &Surreal<Any>) {
+        for index in 0..self.rules.len() {
+            if let Condition::NoBlock { minutes } = self.rules[index].condition {
+                if silent >= Duration::from_secs(minutes * 60) && self.stalled.insert(index) {
+                    let alert = self.alert(
+                        index,
+                        "no_block",
+                        format!("No block arrived in {} minutes.", minutes),
+                        serde_json::json!({ "minutes": minutes }),
+                    );
+                    self.deliver(index, alert, db);
+                }
+            }
+        }
+    }
+
+    async fn check(
+        &mut self,
+        index: usize,
+        event: &Event,
+        db: &Surreal<Any>,
+    ) -> Result<Option<Alert>, Box<dyn Error>> {
+        let condition = self.rules[index].condition.clone();
+        let alert = match (condition, event) {
+            (Condition::NewProposal, Event::Proposal(proposal)) => Some(self.alert(
+                index,
+                "new_proposal",
+                format!("New governance proposal {}.", proposal.proposal_id),
+                serde_json::json!(proposal),
+            )),
+            (
+                Condition::Reorg { min_depth },
+                Event::Reorg {
+                    fork_height, depth, ..
+                },
+            ) if *depth > min_depth => Some(self.alert(
+                index,
+                "reorg",
+                format!("Reorg of {} blocks above {}.", depth, fork_height),
+                serde_json::json!(event),
+            )),
+            (Condition::WatchedAddress { address }, Event::Block(blockdata)) => {
+                let received = received(blockdata, &address);
+                match received > 0.0 {
+                    true => Some(self.alert(
+                        index,
+                        "watched_address",
+                        format!(
+                            "{} received {} GHOST in block {}.",
+                            address, received, blockdata.height
+                        ),
+                        serde_json::json!({
+                            "address": address,
+                            "amount": received,
+                            "height": blockdata.height,
+                            "hash": blockdata.hash,
+                        }),
+                    )),
+                    false => None,
+                }
+            }
+            (
+                Condition::PoolShare {
+                    pool,
+                    threshold,
+                    window,
+                },
+                Event::Block(blockdata),
+            ) => {
+                let share = self.share(index, blockdata, window, &pool, db).await?;
+                let above = share > threshold;
+                let was_above = self.above.insert(index, above).unwrap_or(false);
+                // Only crossing the threshold is news.
+                match above && !was_above {
+                    true => Some(self.alert(
+                        index,
+                        "pool_share",
+                        format!(
+                            "Pool {} staked {:.1}% of the last {} blocks.",
+                            pool,
+                            share * 100.0,
+                            window
+                        ),
+                        serde_json::json!({
+                            "pool": pool,
+                            "share": share,
+                            "window": window,
+                            "height": blockdata.height,
+                        }),
+                    )),
+                    false => None,
+                }
+            }
+            _ => None,
+        };
+        Ok(alert)
+    }
+
+    async fn share(
+        &mut self,
+        index: usize,
+        blockdata: &BlockData,
+        window: u64,
+        pool: &str,
+        db: &Surreal<Any>,
+    ) -> Result<f64, Box<dyn Error>> {
+        let key = StakeKey {
+            height: blockdata.height,
+            pubkey: blockdata.coldstaking.as_ref().map(|p| p.pubkey.clone()),
+        };
+        match self.windows.get_mut(&index) {
+            Some(keys) => keys.push_back(key),
+            None => {
+                // The block is committed already, so the seed includes it.
+                let from = blockdata.height.saturating_sub(window);
+                let keys = db::getstakekeys(db, from, blockdata.height).await?;
+                self.windows.insert(index, keys.into());
+            }
+        }
+        let keys = self.windows.get_mut(&index).unwrap();
+        while keys.len() as u64 > window {
+            keys.pop_front();
+        }
+        let staked = keys
+            .iter()
+            .filter(|key| key.pubkey.as_deref() == Some(pool))
+            .count();
+        Ok(staked as f64 / keys.len().max(1) as f64)
+    }
+
+    fn alert(
+        &self,
+        index: usize,
+        kind: &str,
+        message: String,
+        details: serde_json::Value,
+    ) -> Alert {
+        Alert {
+            rule: self.rules[index].name.clone(),
+            kind: kind.to_string(),
+            message,
+            details,
+            time: now(),
+        }
+    }
+
+    // Post the alert in the background, retrying with exponential backoff,
+    // and record the outcome in the deliveries table.
+    fn deliver(&self, index: usize, alert: Alert, db: &Surreal<Any>) {
+        info!("Alert: {}", alert.message);
+        let rule = self.rules[index].clone();
+        let retries = self.retries;
+        let db = db.clone();
+        tokio::spawn(async move {
+            let mut delivery = Delivery {
+                rule: rule.name.clone(),
+                webhook: rule.webhook.clone(),
+                alert,
+                attempts: 0,
+                delivered: false,
+                error: None,
+                time: now(),
+            };
+            while delivery.attempts <= retries {
+                if delivery.attempts > 0 {
+                    tokio::time::sleep(BACKOFF_BASE * 2u32.pow(delivery.attempts - 1)).await;
+                }
+                delivery.attempts += 1;
+                let url = rule.webhook.clone();
+                let payload = serde_json::json!(delivery.alert);
+                let result = tokio::task::spawn_blocking(move || {
+                    ureq::post(&url)
+                        .timeout(WEBHOOK_TIMEOUT)
+                        .send_json(payload)
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .await;
+                match result {
+                    Ok(Ok(())) => {
+                        delivery.delivered = true;
+                        delivery.error = None;
+                        break;
+                    }
+                    Ok(Err(e)) => delivery.error = Some(e),
+                    Err(e) => delivery.error = Some(e.to_string()),
+                }
+                warn!(
+                    "Webhook delivery of '{}' failed (attempt {}): {}",
+                    delivery.rule,
+                    delivery.attempts,
+                    delivery.error.as_deref().unwrap_or_default()
+                );
+            }
+            delivery.time = now();
+            if let Err(e) = db::regdelivery(&db, &delivery).await {
+                error!("Recording webhook delivery failed: {}", e);
+            }
+        });
+    }
+}
+
+// Sum of standard outputs in the block paying to the address.
+fn received(blockdata: &BlockData, address: &str) -> f64 {
+    blockdata
+        .tx
+        .iter()
+        .flat_map(|tx| tx.vout.iter())
+        .filter_map(|vout| match vout {
+            Vout::Standard {
+                value,
+                scriptpubkey,
+                ..
+            } => {
+                let pays = [&scriptpubkey.addresses, &scriptpubkey.stakeaddresses]
+                    .iter()
+                    .any(|addresses| {
+                        addresses
+                            .as_ref()
+                            .is_some_and(|list| list.iter().any(|a| a == address))
+                    });
+                pays.then_some(*value)
+            }
+            _ => None,
+        })
+        .sum()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
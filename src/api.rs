@@ -0,0 +1,409 @@
+// HTTP server exposing the indexed data as JSON.
+use crate::{
+    console::{BlockData, Proposal, Stakeaddress, Transaction, VoteChange},
+    db,
+    engine::{self, Phase},
+    feed::{self, Event},
+    graphql,
+    mempool::{self, MempoolStats},
+    metrics,
+    pools::Labels,
+    poolstats::{Period, PoolStats},
+    rpc::{self, RPCURL},
+};
+use async_graphql::http::GraphiQLSource;
+use async_graphql_axum::GraphQL;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::StatusCode,
+    response::{
+        sse::{KeepAlive, Sse},
+        Html, IntoResponse, Response,
+    },
+    routing::get,
+    Json, Router,
+};
+use futures_util::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use std::{convert::Infallible, error::Error};
+use surrealdb::{engine::any::Any, Surreal};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+const DEFAULT_LIMIT: u64 = 50;
+const MAX_LIMIT: u64 = 1000;
+
+#[derive(Clone)]
+pub struct ApiState {
+    pub db: Surreal<Any>,
+    pub rpcurl: Option<RPCURL>,
+    // Blocks the index may trail the node by and still be ready.
+    pub ready_lag: u64,
+}
+
+pub async fn serve(addr: String, state: ApiState) -> Result<(), Box<dyn Error>> {
+    let schema = graphql::schema(state.db.clone());
+    let app = Router::new()
+        .route("/graphql", get(graphiql).post_service(GraphQL::new(schema)))
+        .route("/blocks", get(blocks))
+        .route("/blocks/:id", get(block))
+        .route("/tx/:txid", get(tx))
+        .route("/address/:address", get(address))
+        .route("/pools", get(pools))
+        .route("/pools/:pubkey/stats", get(pool_stats))
+        .route("/proposals/:id", get(proposal))
+        .route("/proposals/:id/changes", get(vote_changes))
+        .route("/mempool", get(mempool_stats))
+        .route("/status", get(status))
+        .route("/metrics", get(prometheus))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/feed/ws", get(feed_ws))
+        .route("/feed/sse", get(feed_sse))
+        .with_state(state);
+    info!("Serving API on {} ...", addr);
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn graphiql() -> impl IntoResponse {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+pub enum ApiError {
+    NotFound,
+    BadRequest(String),
+    Internal(String),
+}
+
+impl From<Box<dyn Error>> for ApiError {
+    fn from(e: Box<dyn Error>) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (code, message) = match self {
+            ApiError::NotFound => (StatusCode::NOT_FOUND, "Not found".to_string()),
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            // The detail is for the log, not for clients.
+            ApiError::Internal(message) => {
+                error!("API: {}", message);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal error".to_string(),
+                )
+            }
+        };
+        (code, Json(serde_json::json!({ "error": message }))).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+fn found<T>(item: Option<T>) -> ApiResult<T> {
+    item.map(Json).ok_or(ApiError::NotFound)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Pagination {
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+impl Pagination {
+    pub fn limit(&self) -> u64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT)
+    }
+    pub fn offset(&self) -> u64 {
+        self.offset.unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub limit: u64,
+    pub offset: u64,
+}
+
+impl<T> Page<T> {
+    fn new(items: Vec<T>, pagination: &Pagination) -> Self {
+        Page {
+            items,
+            limit: pagination.limit(),
+            offset: pagination.offset(),
+        }
+    }
+}
+
+// Newest blocks first.
+async fn blocks(
+    State(state): State<ApiState>,
+    Query(pagination): Query<Pagination>,
+) -> ApiResult<Page<BlockData>> {
+    let mut blocks = db::getblocks(&state.db, pagination.limit(), pagination.offset()).await?;
+    let labels = Labels::load(&state.db).await?;
+    for block in blocks.iter_mut() {
+        relabel(block, &labels);
+    }
+    Ok(Json(Page::new(blocks, &pagination)))
+}
+
+// Accepts either a height or a block hash.
+async fn block(State(state): State<ApiState>, Path(id): Path<String>) -> ApiResult<BlockData> {
+    let mut block = match id.parse::<u64>() {
+        Ok(height) => db::getblockbyheight(&state.db, height).await?,
+        Err(_) => db::getblockbyhash(&state.db, &id).await?,
+    };
+    if let Some(block) = block.as_mut() {
+        relabel(block, &Labels::load(&state.db).await?);
+    }
+    found(block)
+}
+
+// Stored labels are as of indexing, serve the pool as it is labelled now.
+fn relabel(block: &mut BlockData, labels: &Labels) {
+    if let Some(pool) = block.coldstaking.as_mut() {
+        labels.relabel(pool);
+    }
+}
+
+async fn tx(State(state): State<ApiState>, Path(txid): Path<String>) -> ApiResult<Transaction> {
+    found(db::gettx(&state.db, &txid).await?)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AddressInfo {
+    pub address: String,
+    pub stakeaddress: Option<Stakeaddress>,
+    pub heights: Page<u64>,
+}
+
+// Stakeaddress label, if any, and the heights of blocks paying to the address.
+async fn address(
+    State(state): State<ApiState>,
+    Path(address): Path<String>,
+    Query(pagination): Query<Pagination>,
+) -> ApiResult<AddressInfo> {
+    let mut stakeaddress = db::getstakeaddress(&state.db, &address).await?;
+    if let Some(stakeaddress) = stakeaddress.as_mut() {
+        Labels::load(&state.db)
+            .await?
+            .relabel(&mut stakeaddress.pool);
+    }
+    let heights =
+        db::getaddressheights(&state.db, &address, pagination.limit(), pagination.offset()).await?;
+    Ok(Json(AddressInfo {
+        address,
+        stakeaddress,
+        heights: Page::new(heights, &pagination),
+    }))
+}
+
+async fn pools(State(state): State<ApiState>) -> ApiResult<Vec<db::PoolRecord>> {
+    Ok(Json(db::getpools(&state.db).await?))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatsQuery {
+    pub period: Option<Period>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+// Newest buckets first.
+async fn pool_stats(
+    State(state): State<ApiState>,
+    Path(pubkey): Path<String>,
+    Query(query): Query<StatsQuery>,
+) -> ApiResult<Page<PoolStats>> {
+    let pagination = Pagination {
+        limit: query.limit,
+        offset: query.offset,
+    };
+    let period = query.period.unwrap_or(Period::Day);
+    let stats = db::getpoolstats(
+        &state.db,
+        &pubkey,
+        period,
+        pagination.limit(),
+        pagination.offset(),
+    )
+    .await?;
+    Ok(Json(Page::new(stats, &pagination)))
+}
+
+async fn proposal(State(state): State<ApiState>, Path(id): Path<String>) -> ApiResult<Proposal> {
+    let id = id
+        .parse::<u64>()
+        .map_err(|_| ApiError::BadRequest(format!("Invalid proposal ID '{}'", id)))?;
+    found(db::getproposal(&state.db, id).await?)
+}
+
+async fn vote_changes(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+) -> ApiResult<Vec<VoteChange>> {
+    let id = id
+        .parse::<u64>()
+        .map_err(|_| ApiError::BadRequest(format!("Invalid proposal ID '{}'", id)))?;
+    Ok(Json(db::getvotechanges(&state.db, id).await?))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub version: &'static str,
+    pub indexed_height: Option<u64>,
+    pub node_height: Option<u64>,
+}
+
+async fn mempool_stats(State(state): State<ApiState>) -> ApiResult<MempoolStats> {
+    Ok(Json(mempool::stats(&state.db).await?))
+}
+
+async fn status(State(state): State<ApiState>) -> ApiResult<Status> {
+    let indexed_height = db::gettip(&state.db).await?;
+    let node_height = match &state.rpcurl {
+        Some(rpcurl) => nodeheight(rpcurl).await,
+        None => None,
+    };
+    Ok(Json(Status {
+        version: env!("CARGO_PKG_VERSION"),
+        indexed_height,
+        node_height,
+    }))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Health {
+    pub ok: bool,
+    pub phase: Phase,
+    pub database: bool,
+    // None when the API runs without a node to ask.
+    pub node: Option<bool>,
+    pub indexed_height: Option<u64>,
+    pub node_height: Option<u64>,
+    pub lag: Option<u64>,
+}
+
+async fn health(state: &ApiState) -> Health {
+    let database = state.db.health().await.is_ok();
+    let indexed_height = match database {
+        true => db::gettip(&state.db).await.ok().flatten(),
+        false => None,
+    };
+    let node_height = match &state.rpcurl {
+        Some(rpcurl) => nodeheight(rpcurl).await,
+        None => None,
+    };
+    let node = state.rpcurl.as_ref().map(|_| node_height.is_some());
+    let lag = match (indexed_height, node_height) {
+        (Some(indexed), Some(node)) => Some(node.saturating_sub(indexed)),
+        _ => None,
+    };
+    let phase = engine::phase();
+    Health {
+        ok: phase != Phase::Stopped && database && node != Some(false),
+        phase,
+        database,
+        node,
+        indexed_height,
+        node_height,
+        lag,
+    }
+}
+
+// ghostd's height, asked once so that checks answer while it is down.
+async fn nodeheight(rpcurl: &RPCURL) -> Option<u64> {
+    let height = rpc::probe("getblockcount", rpcurl).await.ok()?;
+    serde_json::from_value(height).ok()
+}
+
+fn checked(health: Health) -> (StatusCode, Json<Health>) {
+    match health.ok {
+        true => (StatusCode::OK, Json(health)),
+        false => (StatusCode::SERVICE_UNAVAILABLE, Json(health)),
+    }
+}
+
+// Alive as long as the engine runs and both the DB and ghostd answer.
+async fn healthz(State(state): State<ApiState>) -> (StatusCode, Json<Health>) {
+    checked(health(&state).await)
+}
+
+// Ready once caught up with ghostd and listening for new blocks,
+// or right away when only serving.
+async fn readyz(State(state): State<ApiState>) -> (StatusCode, Json<Health>) {
+    let mut health = health(&state).await;
+    health.ok &= match health.phase {
+        Phase::Listening => health.lag.is_some_and(|lag| lag <= state.ready_lag),
+        // Nothing to catch up with when only serving.
+        Phase::Serving => true,
+        _ => false,
+    };
+    checked(health)
+}
+
+// Heights are refreshed on every scrape, the rest is recorded as it happens.
+async fn prometheus(State(state): State<ApiState>) -> Result<String, ApiError> {
+    let indexed_height = db::gettip(&state.db).await?;
+    let node_height = match &state.rpcurl {
+        Some(rpcurl) => nodeheight(rpcurl).await,
+        None => None,
+    };
+    metrics::heights(indexed_height, node_height);
+    Ok(metrics::render()?)
+}
+
+// Next event for a subscriber, skipping over whatever it lagged behind on.
+async fn next_event(events: &mut Receiver<Event>) -> Option<Event> {
+    loop {
+        match events.recv().await {
+            Ok(event) => return Some(event),
+            Err(RecvError::Lagged(missed)) => {
+                warn!("Feed subscriber lagged behind by {} events.", missed);
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+async fn feed_ws(upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(|mut socket: WebSocket| async move {
+        let mut events = feed::subscribe();
+        while let Some(event) = next_event(&mut events).await {
+            let text = match serde_json::to_string(&event) {
+                Ok(text) => text,
+                Err(e) => {
+                    error!("Feed: {}", e);
+                    continue;
+                }
+            };
+            if socket.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+async fn feed_sse() -> Sse<impl Stream<Item = Result<axum::response::sse::Event, Infallible>>> {
+    let events = stream::unfold(feed::subscribe(), |mut events| async move {
+        let event = next_event(&mut events).await?;
+        let name = match &event {
+            Event::Block(_) => "block",
+            Event::Reorg { .. } => "reorg",
+            Event::Proposal(_) => "proposal",
+        };
+        let sse = axum::response::sse::Event::default()
+            .event(name)
+            .json_data(&event)
+            .unwrap_or_default();
+        Some((Ok(sse), events))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
@@ -1,38 +1,235 @@
-use clap::{command, Arg, ArgMatches};
+use crate::export::{FORMATS, TABLES};
+use clap::{command, value_parser, Arg, ArgAction, ArgMatches, Command};
 
 pub fn args() -> ArgMatches {
-    let args = command!()
+    command!()
+        .about("Indexes the Ghost blockchain into SurrealDB. Runs `sync` unless told otherwise")
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .help("Config file, 'ghostd-parser.toml' in the working directory if present. Environment variables (GHOSTD_PARSER_RPC_IP, ...) override it, flags override both")
+                .global(true),
+        )
+        .arg(
+            Arg::new("network")
+                .long("network")
+                .help("Chain to index, mainnet by default")
+                .value_parser(["mainnet", "testnet", "regtest"])
+                .global(true),
+        )
         .arg(
             Arg::new("Ghostd IP")
                 .long("rpc-ip")
-                .help("Ghost RPC IP address to connect to, for example '127.0.0.1:51725'")
-                .required(true),
+                .help("Ghost RPC IP address to connect to, for example '127.0.0.1:51725'. The port defaults to the network's")
+                .global(true),
+        )
+        .arg(
+            Arg::new("fallback nodes")
+                .long("rpc-fallback")
+                .help("Comma separated ghostd nodes to fail over to, in order, with the same credentials")
+                .global(true),
+        )
+        .arg(
+            Arg::new("crosscheck")
+                .long("rpc-crosscheck")
+                .help("Check every block against the other nodes before indexing it")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("raw blocks")
+                .long("rpc-rawblocks")
+                .help("Decode blocks from raw hex instead of verbose getblock")
+                .action(ArgAction::SetTrue)
+                .global(true),
         )
         .arg(
             Arg::new("user")
                 .long("rpc-user")
                 .help("Username for RPC authentication")
-                .required(true),
+                .global(true),
         )
         .arg(
             Arg::new("password")
                 .long("rpc-password")
                 .help("Password for RPC authentication")
-                .required(true),
+                .global(true),
         )
         .arg(
             Arg::new("stage")
                 .long("stage")
                 .help("Database name, for example 'prod'")
-                .required(true),
+                .global(true),
         )
         .arg(
             Arg::new("SurrealDB IP")
                 .long("surrealdb-ip")
                 .help("IP address of the SurrealDB instance")
-                .required(true),
+                .global(true),
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .help("Times a failed RPC call or DB write is retried with backoff before giving up, 5 by default")
+                .value_parser(value_parser!(u32))
+                .global(true),
+        )
+        .arg(
+            Arg::new("pool registry")
+                .long("pool-registry")
+                .help("TOML or JSON file listing known pools, reloaded on change. Defaults to the built-in registry")
+                .global(true),
+        )
+        .arg(
+            Arg::new("API address")
+                .long("api-listen")
+                .help("Serve the indexed data over HTTP on this address, for example '127.0.0.1:8080'")
+                .global(true),
+        )
+        .arg(
+            Arg::new("ready lag")
+                .long("ready-lag")
+                .help("Blocks the index may trail ghostd by and still report ready on /readyz, 3 by default")
+                .value_parser(value_parser!(u64))
+                .global(true),
         )
-        .get_matches();
+        .arg(
+            Arg::new("alert rules")
+                .long("alert-rules")
+                .help("TOML file with alert rules delivered to webhooks while listening")
+                .global(true),
+        )
+        .arg(
+            Arg::new("log level")
+                .long("log-level")
+                .help("Log level of the parser: off, error, warn, info, debug or trace")
+                .global(true),
+        )
+        .arg(
+            Arg::new("log file")
+                .long("log-file")
+                .help("File to log to besides the console, 'debug.log' by default. Empty to disable")
+                .global(true),
+        )
+        .arg(
+            Arg::new("log format")
+                .long("log-format")
+                .help("Log as plain text or as JSON lines")
+                .value_parser(["text", "json"])
+                .global(true),
+        )
+        .subcommand(Command::new("sync").about("Catch up with ghostd and keep indexing new blocks"))
+        .subcommand(
+            Command::new("verify")
+                .about("Check the indexed chain for gaps and compare it against ghostd")
+                .arg(height("from").help("First height to check, 0 by default"))
+                .arg(height("to").help("Last height to check, the indexed tip by default")),
+        )
+        .subcommand(
+            Command::new("rollback")
+                .about("Delete every indexed block above the given height")
+                .arg(height("to").help("Last height to keep").required(true)),
+        )
+        .subcommand(
+            Command::new("reindex")
+                .about("Index the blocks from the given height again and catch up")
+                .arg(height("from").help("First height to index again").required(true)),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Load blocks from ghostd's blk*.dat files, then catch up and listen")
+                .arg(
+                    Arg::new("blocksdir")
+                        .help("ghostd's blocks directory, the node stopped or copied")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("no-sync")
+                        .long("no-sync")
+                        .help("Stop once the files are imported")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("query")
+                .about("Print an indexed record as JSON")
+                .arg(
+                    Arg::new("kind")
+                        .help("What to look up")
+                        .value_parser(["block", "tx", "address", "pool", "proposal"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("id")
+                        .help("Height or hash, txid, address, pool pubkey or proposal ID")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Write a table out as JSON lines, CSV or Parquet")
+                .arg(
+                    Arg::new("table")
+                        .help("Table to export")
+                        .value_parser(TABLES)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("File to write to, stdout by default"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .short('f')
+                        .help("Output format, told by the file extension by default")
+                        .value_parser(FORMATS),
+                )
+                .arg(height("from").help("First height to export from height-keyed tables"))
+                .arg(height("to").help("Last height to export from height-keyed tables")),
+        )
+        .subcommand(
+            Command::new("record-fixture")
+                .about("Print ghostd's raw getblock response for a block, to add to the test fixtures")
+                .arg(
+                    Arg::new("height")
+                        .help("Height of the block")
+                        .value_parser(value_parser!(u64))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("File to write to, stdout by default"),
+                )
+                .arg(
+                    Arg::new("raw")
+                        .long("raw")
+                        .help("Record the block's bytes as hex, getblock verbosity 0")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("serve").about("Only serve the API over the indexed data, without indexing"),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Inspect the configuration")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("print")
+                        .about("Print the effective configuration with secrets redacted"),
+                ),
+        )
+        .get_matches()
+}
 
-    return args;
+fn height(id: &'static str) -> Arg {
+    Arg::new(id)
+        .long(id)
+        .value_name("HEIGHT")
+        .value_parser(value_parser!(u64))
 }
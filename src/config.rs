@@ -0,0 +1,306 @@
+// Settings merged from the config file, GHOSTD_PARSER_* environment
+// variables and command line flags, later ones taking precedence.
+use crate::network::Network;
+use clap::{parser::ValueSource, ArgMatches};
+use serde::{Deserialize, Serialize};
+use std::{env, error::Error, fs, path::Path};
+
+pub const DEFAULT_PATH: &str = "ghostd-parser.toml";
+const ENV_PREFIX: &str = "GHOSTD_PARSER_";
+const ENV_CONFIG: &str = "GHOSTD_PARSER_CONFIG";
+const REDACTED: &str = "<redacted>";
+
+// Command line flags and the settings they override.
+const FLAGS: [(&str, &str); 17] = [
+    ("network", "network"),
+    ("Ghostd IP", "rpc.ip"),
+    ("user", "rpc.user"),
+    ("password", "rpc.password"),
+    ("fallback nodes", "rpc.fallback"),
+    ("crosscheck", "rpc.crosscheck"),
+    ("raw blocks", "rpc.rawblocks"),
+    ("stage", "db.stage"),
+    ("SurrealDB IP", "db.address"),
+    ("retries", "retry.retries"),
+    ("pool registry", "pools.registry"),
+    ("API address", "api.listen"),
+    ("ready lag", "api.ready_lag"),
+    ("alert rules", "alerts.rules"),
+    ("log level", "logging.level"),
+    ("log file", "logging.file"),
+    ("log format", "logging.format"),
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub network: Network,
+    pub rpc: Rpc,
+    pub zmq: Zmq,
+    pub db: Db,
+    pub retry: Retry,
+    pub logging: Logging,
+    pub pools: Pools,
+    pub api: Api,
+    pub alerts: Alerts,
+    pub workers: Workers,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rpc {
+    // For example '127.0.0.1:51725', the network's RPC port unless given.
+    pub ip: Option<String>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    // Further nodes to fail over to, in order, with the same credentials.
+    pub fallback: Vec<String>,
+    // Compare block hashes across the nodes before indexing.
+    pub crosscheck: bool,
+    // Decode blocks from getblock's raw hex instead of its verbose JSON.
+    pub rawblocks: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Zmq {
+    pub address: String,
+    // zmqpubhashtx endpoint, tracks the mempool when set.
+    pub hashtx: Option<String>,
+}
+
+impl Default for Zmq {
+    fn default() -> Self {
+        Zmq {
+            address: "tcp://127.0.0.1:28332".to_string(),
+            hashtx: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Db {
+    // SurrealDB instance, for example '127.0.0.1:8000', or 'mem://'.
+    pub address: Option<String>,
+    // Namespace and database name, for example 'prod'.
+    pub stage: Option<String>,
+}
+
+// Backoff for RPC calls and DB writes that fail transiently.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retry {
+    // Attempts after the first failure, 0 to give up right away.
+    pub retries: u32,
+    // Milliseconds before the first retry, doubling up to max_ms.
+    pub initial_ms: u64,
+    pub max_ms: u64,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            retries: 5,
+            initial_ms: 500,
+            max_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Logging {
+    pub level: String,
+    // Per module levels, for example 'ghostd_parser::rpc=info,surrealdb=warn'.
+    pub filters: String,
+    pub format: LogFormat,
+    // Empty to log to the console only.
+    pub file: String,
+    pub rotation: Rotation,
+    // Megabytes a log file may grow to with size rotation.
+    pub max_size: u64,
+    // Rotated files kept next to the current one.
+    pub keep: usize,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging {
+            level: "trace".to_string(),
+            filters: String::new(),
+            format: LogFormat::Text,
+            file: "debug.log".to_string(),
+            rotation: Rotation::Never,
+            max_size: 100,
+            keep: 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    // JSON lines, for Loki or ELK.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Never,
+    Daily,
+    Size,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pools {
+    // Built-in registry unless set.
+    pub registry: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Api {
+    pub listen: Option<String>,
+    pub ready_lag: u64,
+}
+
+impl Default for Api {
+    fn default() -> Self {
+        Api {
+            listen: None,
+            ready_lag: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Alerts {
+    pub rules: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Workers {
+    // Async runtime threads, one per core unless set.
+    pub threads: Option<usize>,
+    // Threads for blocking work such as webhook deliveries.
+    pub blocking: Option<usize>,
+}
+
+impl Config {
+    pub fn load(args: &ArgMatches) -> Result<Self, Box<dyn Error>> {
+        let path = args
+            .get_one::<String>("config")
+            .cloned()
+            .or_else(|| env::var(ENV_CONFIG).ok());
+        let mut config = match path {
+            Some(path) => Config::read(&path)?,
+            None if Path::new(DEFAULT_PATH).exists() => Config::read(DEFAULT_PATH)?,
+            None => Config::default(),
+        };
+        for (name, value) in env::vars() {
+            if name == ENV_CONFIG {
+                continue;
+            }
+            if let Some(name) = name.strip_prefix(ENV_PREFIX) {
+                // Sections have no underscores, keys may.
+                let key = name.to_lowercase().replacen("_", ".", 1);
+                config
+                    .set(&key, &value)
+                    .map_err(|e| format!("{}{}: {}", ENV_PREFIX, name, e))?;
+            }
+        }
+        for (id, key) in FLAGS {
+            if args.value_source(id) != Some(ValueSource::CommandLine) {
+                continue;
+            }
+            if let Some(value) = args.get_raw(id).and_then(|mut values| values.next()) {
+                let value = value.to_str().ok_or("Flags must be valid UTF-8")?;
+                config.set(key, value)?;
+            }
+        }
+        Ok(config)
+    }
+
+    fn read(path: &str) -> Result<Self, Box<dyn Error>> {
+        let config =
+            toml::from_str(&fs::read_to_string(path)?).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        let text = Some(value.to_string());
+        match key {
+            "network" => self.network = choice(value)?,
+            "rpc.ip" => self.rpc.ip = text,
+            "rpc.user" => self.rpc.user = text,
+            "rpc.password" => self.rpc.password = text,
+            "rpc.fallback" => {
+                self.rpc.fallback = value
+                    .split(",")
+                    .map(|ip| ip.trim().to_string())
+                    .filter(|ip| !ip.is_empty())
+                    .collect()
+            }
+            "rpc.crosscheck" => self.rpc.crosscheck = value.parse()?,
+            "rpc.rawblocks" => self.rpc.rawblocks = value.parse()?,
+            "zmq.address" => self.zmq.address = value.to_string(),
+            "zmq.hashtx" => self.zmq.hashtx = text,
+            "db.address" => self.db.address = text,
+            "db.stage" => self.db.stage = text,
+            "retry.retries" => self.retry.retries = value.parse()?,
+            "retry.initial_ms" => self.retry.initial_ms = value.parse()?,
+            "retry.max_ms" => self.retry.max_ms = value.parse()?,
+            "logging.level" => self.logging.level = value.to_string(),
+            "logging.filters" => self.logging.filters = value.to_string(),
+            "logging.format" => self.logging.format = choice(value)?,
+            "logging.file" => self.logging.file = value.to_string(),
+            "logging.rotation" => self.logging.rotation = choice(value)?,
+            "logging.max_size" => self.logging.max_size = value.parse()?,
+            "logging.keep" => self.logging.keep = value.parse()?,
+            "pools.registry" => self.pools.registry = text,
+            "api.listen" => self.api.listen = text,
+            "api.ready_lag" => self.api.ready_lag = value.parse()?,
+            "alerts.rules" => self.alerts.rules = text,
+            "workers.threads" => self.workers.threads = Some(value.parse()?),
+            "workers.blocking" => self.workers.blocking = Some(value.parse()?),
+            _ => return Err(format!("Unknown setting '{}'", key).into()),
+        }
+        Ok(())
+    }
+
+    // Effective settings as TOML, safe to paste into an issue.
+    pub fn print(&self) -> Result<String, Box<dyn Error>> {
+        let mut config = self.clone();
+        if config.rpc.password.is_some() {
+            config.rpc.password = Some(REDACTED.to_string());
+        }
+        Ok(toml::to_string(&config)?)
+    }
+}
+
+// Enum settings are spelled as in the config file.
+fn choice<T: for<'de> Deserialize<'de>>(value: &str) -> Result<T, Box<dyn Error>> {
+    Ok(T::deserialize(toml::Value::String(value.to_string()))?)
+}
+
+// A setting the command can't do without.
+pub fn required<'a>(value: &'a Option<String>, key: &str) -> &'a String {
+    match value {
+        Some(value) => value,
+        None => {
+            error!(
+                "{} is not set. Set it in the config file, as {}{} or on the command line.",
+                key,
+                ENV_PREFIX,
+                key.replace(".", "_").to_uppercase()
+            );
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,13 +1,15 @@
 use crate::{
     console::Vout::Data,
-    db,
-    pools::{Pool, POOLS},
-    rpc::{call, RPCURL},
+    decode::{self, HeaderInfo},
+    metrics,
+    network::Network,
+    pools::{Pool, Registry},
+    rpc::{call, call_each, RpcError, RPCURL},
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{collections::HashMap, error::Error};
-use surrealdb::{engine::remote::ws::Client, Surreal};
+use std::{collections::HashMap, error::Error, fmt};
+use surrealdb::{engine::any::Any, Surreal};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockData {
@@ -44,126 +46,74 @@ pub struct BlockData {
 impl BlockData {
     async fn determine_coldstaking(
         &mut self,
-        db: &Surreal<Client>,
+        db: &Surreal<Any>,
         rpcurl: &RPCURL,
+        registry: &mut Registry,
     ) -> Result<(), Box<dyn Error>> {
-        let hasstakeaddress: Option<Vec<String>> = match self.tx[0].vout[1].clone() {
-            Vout::Standard {
-                n: _,
-                vout_type: _,
-                value: _,
-                valuesat: _,
-                scriptpubkey,
-            } => scriptpubkey.stakeaddresses,
-            _ => {
-                error!("Unexpected type of vout when validating address.");
-                std::process::exit(1);
-            }
-        };
-        match hasstakeaddress {
-            Some(unchecked_raw_stakeaddresses) => {
-                let coldstaking =
-                    check_stakeaddress_in_db(&unchecked_raw_stakeaddresses[0], db, rpcurl).await?;
-                self.coldstaking = Some(coldstaking);
-                Ok(())
-            }
-            None => {
-                self.coldstaking = None;
-                Ok(())
-            }
-        }
-    }
-    fn read_vote(&mut self) {
-        let vout = self.tx[0].vout[0].clone();
-        match vout {
-            Data {
-                n: _,
-                data_hex: _,
-                smsgdifficulty: _,
-                smsgfeerate: _,
-                treasury_fund_cfwd: _,
-                vout_type: _,
-                vote,
-            } => match vote {
-                Some(content) => {
-                    let parsed: Vec<u64> = content
-                        .split(", ")
-                        .map(|x| x.parse::<u64>().unwrap())
-                        .collect();
-                    if parsed.len() != 2 {
-                        error!("Sanity checks for parsed vote stats failed.");
-                        std::process::exit(1);
-                    }
-                    self.voting_info = Some(Vote {
-                        proposal_id: parsed[0],
-                        voted_for_option: parsed[1],
-                    });
+        self.coldstaking = match self.stakeaddress()? {
+            Some(stakeaddress) => match registry.stakeaddress(db, &stakeaddress).await? {
+                Some(pool) => {
+                    trace!("Known stakeaddress found. Skipping address validation.");
+                    Some(pool)
                 }
                 None => {
-                    self.voting_info = None;
+                    let pubkey = validateaddress(&stakeaddress, rpcurl).await?;
+                    Some(registry.remember(db, &stakeaddress, &pubkey).await?)
                 }
             },
-            _ => {
-                self.voting_info = None;
-            }
+            None => None,
+        };
+        Ok(())
+    }
+    // Stakeaddress the coinstake delegates to, if the block was coldstaked.
+    pub fn stakeaddress(&self) -> Result<Option<String>, ParseError> {
+        let coinstake = self
+            .tx
+            .first()
+            .ok_or_else(|| ParseError::Block("no coinstake transaction".to_string()))?;
+        match coinstake.vout.get(1) {
+            Some(Vout::Standard { scriptpubkey, .. }) => Ok(scriptpubkey
+                .stakeaddresses
+                .as_ref()
+                .and_then(|stakeaddresses| stakeaddresses.first().cloned())),
+            _ => Err(ParseError::Block(
+                "the coinstake does not pay out to a standard output".to_string(),
+            )),
         }
     }
-}
-
-async fn check_stakeaddress_in_db(
-    unchecked_raw_stakeaddress: &String,
-    db: &Surreal<Client>,
-    rpcurl: &RPCURL,
-) -> Result<Pool, Box<dyn Error>> {
-    let known_stakeaddresses = db::getstakeaddresses(db).await?;
-    trace!("Checking for known stakeaddresses ...");
-    let mut coldstaking = Pool::default();
-    // Loop through known addresses and return if there is one
-    for known_stakeaddress in known_stakeaddresses.iter() {
-        if &known_stakeaddress.raw == unchecked_raw_stakeaddress {
-            trace!("Known stakeaddress found. Skipping address validation.");
-            coldstaking = known_stakeaddress.pool.clone();
-            return Ok(coldstaking);
+    pub fn read_vote(&mut self) -> Result<(), ParseError> {
+        self.voting_info = None;
+        if let Some(Data {
+            vote: Some(content),
+            ..
+        }) = self.tx.first().and_then(|tx| tx.vout.first())
+        {
+            self.voting_info = Some(parse_vote(content)?);
         }
+        Ok(())
+    }
+    pub fn staker(&self) -> Option<String> {
+        self.tx.first()?.staker()
+    }
+    pub fn vote_record(&self) -> Option<VoteRecord> {
+        let vote = self.voting_info.as_ref()?;
+        Some(VoteRecord {
+            height: self.height,
+            proposal: vote.proposal_id,
+            option: vote.voted_for_option,
+            stakeaddress: self.staker(),
+            pool: self.coldstaking.as_ref().map(|pool| pool.pubkey.clone()),
+        })
     }
-    trace!("No known stakeaddresses found.");
-    let coldstaking = validateaddress(unchecked_raw_stakeaddress, db, rpcurl).await?;
-    Ok(coldstaking)
 }
-async fn validateaddress(
-    stakeaddress: &str,
-    db: &Surreal<Client>,
-    rpcurl: &RPCURL,
-) -> Result<Pool, Box<dyn Error>> {
+
+// Stake-only pubkey a stakeaddress delegates to, as ghostd derives it.
+async fn validateaddress(stakeaddress: &str, rpcurl: &RPCURL) -> Result<String, Box<dyn Error>> {
     info!("Validating address ...");
     let arg = format!("validateaddress {} true", stakeaddress);
-    let value = call(&arg, rpcurl)?;
-    let poolkey: String = serde_json::from_value(value["stakeonly_address"].clone()).unwrap();
-    // Default is no pool.
-    let mut coldstaking = Pool {
-        pubkey: poolkey.clone(),
-        url: None,
-        pool_is_active: None,
-    };
-    for known_pool in POOLS {
-        if &poolkey == known_pool.pubkey {
-            trace!("Stakeaddress belongs to a known pool.");
-            coldstaking = known_pool.getpool();
-            let stakeaddr_for_db = Stakeaddress {
-                raw: stakeaddress.to_string(),
-                pool: coldstaking.clone(),
-            };
-            db::regstakeaddress(db, &stakeaddr_for_db).await?;
-            return Ok(coldstaking);
-        }
-    }
-    trace!("Stakeaddress is of an unknown origin.");
-    let stakeaddr_for_db = Stakeaddress {
-        raw: stakeaddress.to_string(),
-        pool: coldstaking.clone(),
-    };
-    db::regstakeaddress(db, &stakeaddr_for_db).await?;
-    Ok(coldstaking)
+    let value = call(&arg, rpcurl).await?;
+    let poolkey: String = serde_json::from_value(value["stakeonly_address"].clone())?;
+    Ok(poolkey)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -181,13 +131,45 @@ pub struct Transaction {
     pub vsize: u64,
     pub weight: u64,
     pub locktime: u64,
+    // Left empty for transactions decoded from raw blocks.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub hex: String,
     pub vin: Vec<Vin>,
     pub vout: Vec<Vout>,
 }
 
+impl Transaction {
+    // Address a coinstake pays to: the delegated stakeaddress
+    // for coldstaking blocks, the plain address otherwise.
+    pub fn staker(&self) -> Option<String> {
+        match self.vout.get(1)? {
+            Vout::Standard { scriptpubkey, .. } => scriptpubkey.owner(),
+            _ => None,
+        }
+    }
+    // Estimated staking reward of a coinstake: what it pays back
+    // to the staker beyond the value of the staked kernel.
+    pub fn reward(&self, stakekernelvalue: Option<f64>) -> Option<f64> {
+        let staker = self.staker()?;
+        let paid: f64 = self
+            .vout
+            .iter()
+            .filter_map(|vout| match vout {
+                Vout::Standard {
+                    value,
+                    scriptpubkey,
+                    ..
+                } if scriptpubkey.owner().as_ref() == Some(&staker) => Some(*value),
+                _ => None,
+            })
+            .sum();
+        Some((paid - stakekernelvalue?).max(0.0))
+    }
+}
+
+// Blind and anon outputs carry data_hex too, so only the type tells them apart.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(untagged)]
+#[serde(tag = "type", rename_all = "lowercase")]
 pub enum Vout {
     Data {
         n: u64,
@@ -195,14 +177,10 @@ pub enum Vout {
         smsgdifficulty: Option<String>,
         smsgfeerate: Option<f64>,
         treasury_fund_cfwd: Option<f64>,
-        #[serde(rename(deserialize = "type", serialize = "type"))]
-        vout_type: String,
         vote: Option<String>,
     },
     Standard {
         n: u64,
-        #[serde(rename(deserialize = "type", serialize = "type"))]
-        vout_type: String,
         value: f64,
         #[serde(rename(deserialize = "valueSat", serialize = "valueSat"))]
         valuesat: u64,
@@ -211,9 +189,9 @@ pub enum Vout {
     },
     Blind {
         n: u64,
-        #[serde(rename(deserialize = "type", serialize = "type"))]
-        vout_type: String,
         pubkey: Option<String>,
+        #[serde(rename(deserialize = "scriptPubKey", serialize = "scriptPubKey"))]
+        scriptpubkey: Option<ScriptPubKey>,
         #[serde(rename(deserialize = "valueCommitment", serialize = "valueCommitment"))]
         value_commitment: String,
         data_hex: String,
@@ -221,8 +199,6 @@ pub enum Vout {
     },
     Anon {
         n: u64,
-        #[serde(rename(deserialize = "type", serialize = "type"))]
-        vout_type: String,
         pubkey: Option<String>,
         #[serde(rename(deserialize = "valueCommitment", serialize = "valueCommitment"))]
         value_commitment: String,
@@ -231,6 +207,18 @@ pub enum Vout {
     },
 }
 
+impl Vout {
+    // Output type as ghostd names it.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Vout::Data { .. } => "data",
+            Vout::Standard { .. } => "standard",
+            Vout::Blind { .. } => "blind",
+            Vout::Anon { .. } => "anon",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScriptPubKey {
     pub addresses: Option<Vec<String>>,
@@ -243,6 +231,15 @@ pub struct ScriptPubKey {
     pub staking_type: String,
 }
 
+impl ScriptPubKey {
+    pub fn owner(&self) -> Option<String> {
+        self.stakeaddresses
+            .as_ref()
+            .or(self.addresses.as_ref())
+            .and_then(|addresses| addresses.first().cloned())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Vin {
@@ -280,21 +277,81 @@ pub struct Vote {
     pub voted_for_option: u64,
 }
 
+// One row of the votes table, linking a block's vote to whoever staked it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoteRecord {
+    pub height: u64,
+    pub proposal: u64,
+    pub option: u64,
+    pub stakeaddress: Option<String>,
+    pub pool: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolVote {
+    pub option: u64,
+    pub blocks: u64,
+}
+
+// Stakeaddress whose votes on a proposal switched between options,
+// listed as (height, option) at every switch point.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoteChange {
+    pub stakeaddress: String,
+    pub pool: Option<String>,
+    pub history: Vec<(u64, u64)>,
+}
+
 impl Vote {
-    pub async fn gen_proposal(&self, rpcurl: &RPCURL) -> Result<Proposal, Box<dyn Error>> {
+    pub async fn gen_proposal(
+        &self,
+        rpcurl: &RPCURL,
+        network: Network,
+    ) -> Result<Proposal, Box<dyn Error>> {
         Ok(Proposal {
             proposal_id: self.proposal_id,
-            stats: self.count_stats(rpcurl).await?,
+            stats: self.count_stats(rpcurl, network).await?,
         })
     }
     async fn count_stats(
         &self,
         rpcurl: &RPCURL,
+        network: Network,
     ) -> Result<HashMap<String, (u64, f64)>, Box<dyn Error>> {
-        Ok(tallyvotes(*&self.proposal_id, rpcurl).await?)
+        tallyvotes(self.proposal_id, network.params().governance_start, rpcurl).await
     }
 }
 
+// Node output the parser can't make sense of.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    Block(String),
+    Vote(String),
+    Tally(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Block(reason) => write!(f, "Malformed block: {}", reason),
+            ParseError::Vote(vote) => write!(f, "Malformed vote '{}'", vote),
+            ParseError::Tally(tally) => write!(f, "Malformed vote tally '{}'", tally),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+// A vote as the coinstake's data output spells it, '<proposal>, <option>'.
+pub fn parse_vote(content: &str) -> Result<Vote, ParseError> {
+    let malformed = || ParseError::Vote(content.to_string());
+    let (proposal, option) = content.split_once(", ").ok_or_else(malformed)?;
+    Ok(Vote {
+        proposal_id: proposal.parse().map_err(|_| malformed())?,
+        voted_for_option: option.parse().map_err(|_| malformed())?,
+    })
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Proposal {
     pub proposal_id: u64,
@@ -303,73 +360,142 @@ pub struct Proposal {
 
 async fn tallyvotes(
     proposal_id: u64,
+    start: u64,
     rpcurl: &RPCURL,
 ) -> Result<HashMap<String, (u64, f64)>, Box<dyn Error>> {
-    let arg = format!("tallyvotes {} 710800 {}", proposal_id, i32::MAX);
-    let context = call(&arg, rpcurl)?;
-    let rawmap: HashMap<String, Value> = serde_json::from_value(context)?;
-    let mut hmap: HashMap<String, (u64, f64)> = rawmap
-        .iter()
-        .map(|val| {
-            (
-                val.0.to_string().replace('"', ""),
-                parse_tallyvotes_ratios(val.1.to_string().replace('"', "")),
-            )
-        })
-        .collect();
-    hmap.remove_entry("proposal").unwrap();
-    hmap.remove_entry("blocks_counted").unwrap();
-    hmap.remove_entry("height_start").unwrap();
-    hmap.remove_entry("height_end").unwrap();
-    Ok(hmap)
+    let arg = format!("tallyvotes {} {} {}", proposal_id, start, i32::MAX);
+    let context = call(&arg, rpcurl).await?;
+    Ok(parse_tallies(context)?)
 }
 
-fn parse_tallyvotes_ratios(raw: String) -> (u64, f64) {
-    let vote_stats_iterator = raw.split(", ");
-    let mut index = 0;
-    let mut vote_args_tuple: (u64, f64) = (0, 0.0);
-    for vote_stat in vote_stats_iterator {
-        if index == 0 {
-            vote_args_tuple.0 = vote_stat.replace("%", "").trim().parse::<u64>().unwrap();
-        } else if index == 1 {
-            vote_args_tuple.1 = vote_stat.replace("%", "").trim().parse::<f64>().unwrap();
+// Fields of a tallyvotes answer that describe the tally rather than an option.
+const TALLY_FIELDS: [&str; 4] = ["proposal", "blocks_counted", "height_start", "height_end"];
+
+// Options of a tallyvotes answer with their votes and share of the blocks.
+pub fn parse_tallies(context: Value) -> Result<HashMap<String, (u64, f64)>, ParseError> {
+    let rawmap = match context {
+        Value::Object(rawmap) => rawmap,
+        other => return Err(ParseError::Tally(other.to_string())),
+    };
+    let mut hmap: HashMap<String, (u64, f64)> = HashMap::new();
+    for (option, value) in rawmap {
+        if TALLY_FIELDS.contains(&option.as_str()) {
+            continue;
         }
-        index += 1;
+        let raw = value
+            .as_str()
+            .ok_or_else(|| ParseError::Tally(value.to_string()))?;
+        hmap.insert(option, parse_tally(raw)?);
     }
-    return vote_args_tuple;
+    Ok(hmap)
+}
+
+// One option's tally, for example '2, 66.67%'.
+pub fn parse_tally(raw: &str) -> Result<(u64, f64), ParseError> {
+    let malformed = || ParseError::Tally(raw.to_string());
+    let (votes, share) = raw.split_once(", ").ok_or_else(malformed)?;
+    let votes = votes.trim().parse::<u64>().map_err(|_| malformed())?;
+    let share = share
+        .trim()
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .map_err(|_| malformed())?;
+    Ok((votes, share))
 }
 
 pub async fn getblockhash(height: u64, rpcurl: &RPCURL) -> Result<String, Box<dyn Error>> {
     let arg = format!("getblockhash {}", height);
-    let raw = call(&arg, rpcurl)?;
+    let raw = call(&arg, rpcurl).await?;
     let hash: String = serde_json::from_value(raw)?;
     Ok(hash)
 }
 
+pub async fn getblockcount(rpcurl: &RPCURL) -> Result<u64, Box<dyn Error>> {
+    let raw = call("getblockcount", rpcurl).await?;
+    let height: u64 = serde_json::from_value(raw)?;
+    Ok(height)
+}
+
 pub async fn getblock(
     blockhash: impl Into<String>,
-    db: &Surreal<Client>,
+    db: &Surreal<Any>,
     rpcurl: &RPCURL,
+    registry: &mut Registry,
 ) -> Result<BlockData, Box<dyn Error>> {
-    let arg = format!("getblock {} 2 true", blockhash.into());
-    let value = call(&arg, rpcurl)?;
-    let mut blockdata: BlockData = serde_json::from_value(value)?;
-    blockdata.determine_coldstaking(db, rpcurl).await?;
-    blockdata.read_vote();
+    let blockhash = blockhash.into();
+    let mut blockdata = match rpcurl.decoding() {
+        Some(network) => rawblock(&blockhash, network, rpcurl).await?,
+        None => {
+            let arg = format!("getblock {} 2 true", blockhash);
+            serde_json::from_value(call(&arg, rpcurl).await?)?
+        }
+    };
+    if rpcurl.crosschecking() {
+        crosscheck(&blockdata, rpcurl).await?;
+    }
+    blockdata
+        .determine_coldstaking(db, rpcurl, registry)
+        .await?;
+    // A garbled vote costs the block its vote, not the indexer its run.
+    if let Err(e) = blockdata.read_vote() {
+        warn!("Block {}: {}", blockdata.height, e);
+    }
     Ok(blockdata)
 }
 
+// The block's bytes decoded here, its place in the chain taken from its header.
+async fn rawblock(
+    blockhash: &str,
+    network: Network,
+    rpcurl: &RPCURL,
+) -> Result<BlockData, Box<dyn Error>> {
+    let raw: String =
+        serde_json::from_value(call(&format!("getblock {} 0", blockhash), rpcurl).await?)?;
+    // The transaction ids verbosity 1 adds are little to pay for the
+    // stake kernel fields no header has.
+    let arg = format!("getblock {} 1", blockhash);
+    let info: HeaderInfo = serde_json::from_value(call(&arg, rpcurl).await?)?;
+    Ok(decode::block(&decode::unhex(&raw)?, info, network)?)
+}
+
+// Nodes that have a block at this height must have this one. A node
+// behind the others is no disagreement, an unreachable one is failed over.
+async fn crosscheck(blockdata: &BlockData, rpcurl: &RPCURL) -> Result<(), Box<dyn Error>> {
+    let arg = format!("getblockhash {}", blockdata.height);
+    let others: Vec<String> = call_each(&arg, rpcurl)
+        .await?
+        .into_iter()
+        .filter_map(|(node, answer)| match answer {
+            Ok(hash) if hash == Value::String(blockdata.hash.clone()) => None,
+            Ok(hash) => Some(format!("{} has {}", node, hash)),
+            Err(_) => None,
+        })
+        .collect();
+    if others.is_empty() {
+        return Ok(());
+    }
+    metrics::get().node_disagreements.inc();
+    Err(RpcError::Disagreement(format!(
+        "block {} is {}, but {}",
+        blockdata.height,
+        blockdata.hash,
+        others.join(", ")
+    ))
+    .into())
+}
+
 pub async fn getnewproposal(
     blockdata: &BlockData,
-    proposal_ids: &Vec<u64>,
+    proposal_ids: &[u64],
     rpcurl: &RPCURL,
+    network: Network,
 ) -> Result<Option<Proposal>, Box<dyn Error>> {
-    if blockdata.height > 710800 {
+    if blockdata.height > network.params().governance_start {
         match blockdata.voting_info.clone() {
             Some(vote) => {
-                let existsyet = proposal_ids.iter().any(|&x| x == vote.proposal_id);
+                let existsyet = proposal_ids.contains(&vote.proposal_id);
                 if !existsyet {
-                    let proposal = vote.gen_proposal(rpcurl).await?;
+                    let proposal = vote.gen_proposal(rpcurl, network).await?;
                     Ok(Some(proposal))
                 } else {
                     Ok(None)
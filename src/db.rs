@@ -1,34 +1,86 @@
 use crate::{
-    console::{BlockData, Proposal, Stakeaddress},
+    activity::{KeyCount, KeyLastStaked, KeyStatus, PoolActivity, PoolStatus},
+    alerts::{Delivery, StakeKey},
+    config::{self, Config},
+    console::{
+        BlockData, PoolVote, Proposal, Stakeaddress, Transaction, VoteChange, VoteRecord, Vout,
+    },
+    discovery::{KeyBlocks, KeyStakers, PoolCandidate},
     engine::ProcessedBlocks,
+    mempool::{Confirmation, MempoolTx},
+    metrics,
+    network::Network,
+    pools::{Pool, PoolEntry},
+    poolstats::{NetworkStats, Period, PoolStats, StakedBlock},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
 };
-use clap::ArgMatches;
-use std::error::Error;
 use surrealdb::{
-    engine::remote::ws::{Client, Ws},
+    engine::any::{self, Any},
+    error::Api,
     Surreal,
 };
 
-pub async fn init(args: &ArgMatches) -> surrealdb::Result<Surreal<Client>> {
-    let stage = args.get_one::<String>("stage").unwrap();
-    let is_ip: Option<String> = args.get_one::<String>("SurrealDB IP").cloned();
-    match is_ip {
-        Some(ip) => {
-            info!("Connecting {} ...", &ip);
-            let db = Surreal::new::<Ws>(ip).await?;
-            db.use_ns(stage).use_db(stage).await?;
-            return Ok(db);
-        }
-        None => {
-            // Can't be None, because CLAP won't let the program run without this value.
-            unreachable!();
+// Trouble reaching the DB, as opposed to a query it rejects.
+pub fn transient(e: &(dyn Error + 'static)) -> bool {
+    matches!(
+        e.downcast_ref::<surrealdb::Error>(),
+        Some(surrealdb::Error::Api(
+            Api::Http(_) | Api::Ws(_) | Api::ConnectionUninitialised | Api::InternalError(_)
+        ))
+    )
+}
+
+pub async fn init(config: &Config) -> surrealdb::Result<Surreal<Any>> {
+    let stage = config::required(&config.db.stage, "db.stage");
+    let address = config::required(&config.db.address, "db.address");
+    // A plain host:port is a SurrealDB server, 'mem://' keeps everything in memory.
+    let endpoint = match address.contains("://") {
+        true => address.clone(),
+        false => format!("ws://{}", address),
+    };
+    info!("Connecting {} ...", endpoint);
+    let db = any::connect(endpoint).await?;
+    db.use_ns(stage).use_db(stage).await?;
+    defineindexes(&db).await?;
+    Ok(db)
+}
+
+// Indexes the queries rely on, as name, table and fields.
+const INDEXES: [(&str, &str, &str); 6] = [
+    ("blocks_time", "blocks", "time"),
+    ("blocks_hash", "blocks", "hash"),
+    ("blocks_pool", "blocks", "coldstaking.pubkey"),
+    ("txids_height", "txids", "height"),
+    ("addresses_address", "addresses", "address"),
+    ("addresses_height", "addresses", "height"),
+];
+
+// Defining an index again rebuilds it, so only missing ones are defined.
+async fn defineindexes(db: &Surreal<Any>) -> surrealdb::Result<()> {
+    for (name, table, fields) in INDEXES {
+        let mut response = db.query(format!("INFO FOR TABLE {}", table)).await?;
+        let info: Option<serde_json::Value> = response.take(0)?;
+        if info.is_some_and(|info| info["indexes"].get(name).is_some()) {
+            continue;
         }
+        debug!("Defining index {} on {} ...", name, table);
+        db.query(format!(
+            "DEFINE INDEX {} ON {} FIELDS {}",
+            name, table, fields
+        ))
+        .await?
+        .check()?;
     }
+    Ok(())
 }
 
 // Sum heights from bottom to top both
 // mathematically and via SQL to ensure data consistency
-pub async fn toprec(db: &Surreal<Client>) -> Result<Option<u64>, Box<dyn Error>> {
+pub async fn toprec(db: &Surreal<Any>) -> Result<Option<u64>, Box<dyn Error>> {
     debug!("Database sanity check ...");
     trace!("Running a set of queries ...");
     let mut response = db
@@ -47,7 +99,7 @@ pub async fn toprec(db: &Surreal<Client>) -> Result<Option<u64>, Box<dyn Error>>
                     min_height
                 );
             }
-            let fold: u64 = (min_height..=top_height).fold(0, |acc, x| acc + x);
+            let fold: u64 = (min_height..=top_height).sum();
             let dbfold: u64 = response.take::<Option<u64>>(3)?.unwrap();
             if fold != dbfold {
                 error!(
@@ -65,16 +117,14 @@ pub async fn toprec(db: &Surreal<Client>) -> Result<Option<u64>, Box<dyn Error>>
     }
 }
 
-pub async fn getproposalids(db: &Surreal<Client>) -> Result<Vec<u64>, Box<dyn Error>> {
+pub async fn getproposalids(db: &Surreal<Any>) -> Result<Vec<u64>, Box<dyn Error>> {
     trace!("Querying proposals ...");
     let mut response = db.query("SELECT VALUE proposal_id FROM proposals").await?;
     let proposal_ids: Vec<u64> = response.take(0)?;
     Ok(proposal_ids)
 }
 
-pub async fn gettrackedzmq(
-    db: &Surreal<Client>,
-) -> Result<Option<ProcessedBlocks>, Box<dyn Error>> {
+pub async fn gettrackedzmq(db: &Surreal<Any>) -> Result<Option<ProcessedBlocks>, Box<dyn Error>> {
     trace!("Querying last 1000 ZMQ processed blocks ...");
     let mut response = db.query("SELECT * FROM zmq").await?;
     let zmqueue: Option<ProcessedBlocks> = response.take(0)?;
@@ -82,29 +132,163 @@ pub async fn gettrackedzmq(
 }
 
 pub async fn regtrackedzmq(
-    db: &Surreal<Client>,
+    db: &Surreal<Any>,
     queue: &ProcessedBlocks,
 ) -> Result<(), Box<dyn Error>> {
     trace!("Recording ZMQ queue for later use ...");
+    let _timer = metrics::db_write("regtrackedzmq");
     let _ = db.query("DELETE zmq").await?;
     let _: Vec<ProcessedBlocks> = db.create("zmq").content(queue).await?;
     Ok(())
 }
 
-pub async fn regblock(db: &Surreal<Client>, blockdata: &BlockData) -> Result<(), Box<dyn Error>> {
-    info!("Registering block {} into DB ...", blockdata.height);
-    let _: Option<BlockData> = db
-        .create(("blocks", blockdata.height))
-        .content(blockdata)
-        .await?;
+pub async fn regblock(db: &Surreal<Any>, blockdata: &BlockData) -> Result<(), Box<dyn Error>> {
+    regblocks(db, std::slice::from_ref(blockdata), &[]).await
+}
+
+// Lookup of the block a transaction is in.
+#[derive(Debug, Serialize)]
+struct TxLink {
+    txid: String,
+    height: u64,
+}
+
+// Lookup of a block with outputs paying to an address.
+#[derive(Debug, Serialize)]
+struct AddressLink {
+    address: String,
+    height: u64,
+}
+
+// Txids and addresses paid to of the blocks, by height.
+fn links(blocks: &[BlockData]) -> (Vec<TxLink>, Vec<AddressLink>) {
+    let mut txids = Vec::new();
+    let mut addresses = Vec::new();
+    for block in blocks {
+        let mut paid = BTreeSet::new();
+        for tx in block.tx.iter() {
+            txids.push(TxLink {
+                txid: tx.txid.clone(),
+                height: block.height,
+            });
+            for vout in tx.vout.iter() {
+                let scriptpubkey = match vout {
+                    Vout::Standard { scriptpubkey, .. } => scriptpubkey,
+                    Vout::Blind {
+                        scriptpubkey: Some(scriptpubkey),
+                        ..
+                    } => scriptpubkey,
+                    _ => continue,
+                };
+                for list in [&scriptpubkey.addresses, &scriptpubkey.stakeaddresses] {
+                    paid.extend(list.iter().flatten().cloned());
+                }
+            }
+        }
+        addresses.extend(paid.into_iter().map(|address| AddressLink {
+            address,
+            height: block.height,
+        }));
+    }
+    (txids, addresses)
+}
+
+// Record a run of blocks and their votes in one transaction.
+pub async fn regblocks(
+    db: &Surreal<Any>,
+    blocks: &[BlockData],
+    votes: &[VoteRecord],
+) -> Result<(), Box<dyn Error>> {
+    let (Some(first), Some(last)) = (blocks.first(), blocks.last()) else {
+        return Ok(());
+    };
+    info!(
+        "Registering blocks {} to {} into DB ...",
+        first.height, last.height
+    );
+    let _timer = metrics::db_write("regblocks");
+    let (txids, addresses) = links(blocks);
+    let _ = db
+        .query("BEGIN TRANSACTION")
+        .query(
+            "FOR $block IN $blocks { CREATE type::thing('blocks', $block.height) CONTENT $block }",
+        )
+        .query("FOR $vote IN $votes { CREATE type::thing('votes', $vote.height) CONTENT $vote }")
+        .query(LINK_TXIDS)
+        .query(LINK_ADDRESSES)
+        .query("UPDATE meta:tip SET height = $top")
+        .query("COMMIT TRANSACTION")
+        .bind(("top", last.height))
+        .bind(("blocks", blocks))
+        .bind(("votes", votes))
+        .bind(("txids", txids))
+        .bind(("addresses", addresses))
+        .await?
+        .check()?;
     Ok(())
 }
 
-pub async fn regproposal(db: &Surreal<Client>, proposal: &Proposal) -> Result<(), Box<dyn Error>> {
+const LINK_TXIDS: &str =
+    "FOR $link IN $txids { UPDATE type::thing('txids', $link.txid) CONTENT $link }";
+const LINK_ADDRESSES: &str = "FOR $link IN $addresses { UPDATE type::thing('addresses', [$link.address, $link.height]) CONTENT $link }";
+
+// Fill the txid and address lookups of blocks recorded already.
+pub async fn reglinks(db: &Surreal<Any>, blocks: &[BlockData]) -> Result<(), Box<dyn Error>> {
+    let (Some(first), Some(last)) = (blocks.first(), blocks.last()) else {
+        return Ok(());
+    };
+    debug!(
+        "Recording lookups of blocks {} to {} into DB ...",
+        first.height, last.height
+    );
+    let (txids, addresses) = links(blocks);
+    let _ = db
+        .query(LINK_TXIDS)
+        .query(LINK_ADDRESSES)
+        .bind(("txids", txids))
+        .bind(("addresses", addresses))
+        .await?
+        .check()?;
+    Ok(())
+}
+
+// Whether the lookups cover every recorded block.
+pub async fn getlinked(db: &Surreal<Any>) -> Result<bool, Box<dyn Error>> {
+    trace!("Querying whether lookups are filled ...");
+    let mut response = db.query("SELECT VALUE linked FROM meta:links").await?;
+    let linked: Option<bool> = response.take(0)?;
+    Ok(linked.unwrap_or(false))
+}
+
+pub async fn reglinked(db: &Surreal<Any>) -> Result<(), Box<dyn Error>> {
+    debug!("Marking lookups as filled ...");
+    let _ = db
+        .query("UPDATE meta:links SET linked = true")
+        .await?
+        .check()?;
+    Ok(())
+}
+
+// Blocks in [from, to], read by record id.
+pub async fn getblockrange(
+    db: &Surreal<Any>,
+    from: u64,
+    to: u64,
+) -> Result<Vec<BlockData>, Box<dyn Error>> {
+    trace!("Querying blocks {} to {} ...", from, to);
+    let mut response = db
+        .query(format!("SELECT * FROM blocks:{}..={}", from, to))
+        .await?;
+    let blocks: Vec<BlockData> = response.take(0)?;
+    Ok(blocks)
+}
+
+pub async fn regproposal(db: &Surreal<Any>, proposal: &Proposal) -> Result<(), Box<dyn Error>> {
     info!(
         "Registering proposal ID {} into DB ...",
         proposal.proposal_id
     );
+    let _timer = metrics::db_write("regproposal");
     let _: Option<Proposal> = db
         .create(("proposals", proposal.proposal_id))
         .content(proposal)
@@ -112,20 +296,744 @@ pub async fn regproposal(db: &Surreal<Client>, proposal: &Proposal) -> Result<()
     Ok(())
 }
 
-pub async fn getstakeaddresses(db: &Surreal<Client>) -> Result<Vec<Stakeaddress>, Box<dyn Error>> {
+pub async fn getstakeaddresses(db: &Surreal<Any>) -> Result<Vec<Stakeaddress>, Box<dyn Error>> {
     trace!("Querying validated stakeaddresses ...");
     let stakeaddresses: Vec<Stakeaddress> = db.select("stakeaddresses").await?;
     Ok(stakeaddresses)
 }
 
 pub async fn regstakeaddress(
-    db: &Surreal<Client>,
+    db: &Surreal<Any>,
     stakeaddr: &Stakeaddress,
 ) -> Result<(), Box<dyn Error>> {
     trace!("Recording new stakeaddress into DB ...");
+    let _timer = metrics::db_write("regstakeaddress");
     let _: Option<Stakeaddress> = db
         .create(("stakeaddresses", stakeaddr.raw.clone()))
         .content(stakeaddr)
         .await?;
     Ok(())
 }
+
+pub async fn regvote(db: &Surreal<Any>, vote: &VoteRecord) -> Result<(), Box<dyn Error>> {
+    trace!("Recording vote of block {} into DB ...", vote.height);
+    let _timer = metrics::db_write("regvote");
+    let _: Option<VoteRecord> = db.create(("votes", vote.height)).content(vote).await?;
+    Ok(())
+}
+
+// Populate the votes table from blocks indexed before it existed.
+pub async fn backfillvotes(db: &Surreal<Any>) -> Result<(), Box<dyn Error>> {
+    let mut response = db.query("SELECT VALUE height FROM votes LIMIT 1").await?;
+    let recorded: Vec<u64> = response.take(0)?;
+    if !recorded.is_empty() {
+        return Ok(());
+    }
+    debug!("Backfilling votes from indexed blocks ...");
+    let mut cursor: i64 = -1;
+    loop {
+        let mut response = db
+            .query("SELECT * FROM blocks WHERE voting_info != NONE AND height > $cursor ORDER BY height LIMIT 1000")
+            .bind(("cursor", cursor))
+            .await?;
+        let blocks: Vec<BlockData> = response.take(0)?;
+        match blocks.last() {
+            Some(last) => cursor = last.height as i64,
+            None => break,
+        }
+        for vote in blocks.iter().filter_map(|block| block.vote_record()) {
+            regvote(db, &vote).await?;
+        }
+    }
+    Ok(())
+}
+
+// Proposals voted on above the height governance starts at.
+pub async fn getvotedproposalids(db: &Surreal<Any>, from: u64) -> Result<Vec<u64>, Box<dyn Error>> {
+    trace!("Querying proposals voted on above {} ...", from);
+    let mut response = db
+        .query("array::distinct((SELECT VALUE proposal FROM votes WHERE height > $from))")
+        .bind(("from", from))
+        .await?;
+    let ids: Option<Vec<u64>> = response.take(0)?;
+    Ok(ids.unwrap_or_default())
+}
+
+// How a pool's blocks voted on a proposal, one entry per option.
+pub async fn getpoolvotes(
+    db: &Surreal<Any>,
+    pool: &str,
+    proposal_id: u64,
+) -> Result<Vec<PoolVote>, Box<dyn Error>> {
+    trace!(
+        "Querying votes of pool {} on proposal {} ...",
+        pool,
+        proposal_id
+    );
+    let mut response = db
+        .query("SELECT option, count() AS blocks FROM votes WHERE pool = $pool AND proposal = $proposal GROUP BY option")
+        .bind(("pool", pool))
+        .bind(("proposal", proposal_id))
+        .await?;
+    let votes: Vec<PoolVote> = response.take(0)?;
+    Ok(votes)
+}
+
+pub async fn getvotechanges(
+    db: &Surreal<Any>,
+    proposal_id: u64,
+) -> Result<Vec<VoteChange>, Box<dyn Error>> {
+    trace!("Querying vote changes on proposal {} ...", proposal_id);
+    let mut response = db
+        .query("SELECT * FROM votes WHERE proposal = $proposal AND stakeaddress != NONE ORDER BY height")
+        .bind(("proposal", proposal_id))
+        .await?;
+    let votes: Vec<VoteRecord> = response.take(0)?;
+    let mut histories: HashMap<String, VoteChange> = HashMap::new();
+    for vote in votes {
+        let Some(stakeaddress) = vote.stakeaddress else {
+            continue;
+        };
+        let entry = histories
+            .entry(stakeaddress.clone())
+            .or_insert_with(|| VoteChange {
+                stakeaddress,
+                pool: vote.pool.clone(),
+                history: Vec::new(),
+            });
+        if entry.history.last().map(|last| last.1) != Some(vote.option) {
+            entry.history.push((vote.height, vote.option));
+        }
+    }
+    let mut changes: Vec<VoteChange> = histories
+        .into_values()
+        .filter(|change| change.history.len() > 1)
+        .collect();
+    changes.sort_by_key(|change| change.history[0].0);
+    Ok(changes)
+}
+
+pub async fn regpool(db: &Surreal<Any>, entry: &PoolEntry) -> Result<(), Box<dyn Error>> {
+    trace!("Recording pool {} into DB ...", entry.pubkey);
+    let _: Option<PoolEntry> = db
+        .update(("pools", entry.pubkey.clone()))
+        .merge(entry)
+        .await?;
+    Ok(())
+}
+
+// Point every stakeaddress delegating to the pubkey at the given label.
+pub async fn relabelstakeaddresses(db: &Surreal<Any>, pool: &Pool) -> Result<(), Box<dyn Error>> {
+    trace!("Relabelling stakeaddresses of {} ...", pool.pubkey);
+    let _ = db
+        .query(
+            "UPDATE stakeaddresses SET pool = $pool WHERE pool.pubkey = $pubkey AND pool != $pool",
+        )
+        .bind(("pool", pool))
+        .bind(("pubkey", &pool.pubkey))
+        .await?
+        .check()?;
+    Ok(())
+}
+
+// Drop pools that left the registry and strip their labels from stakeaddresses.
+pub async fn forgetpools(db: &Surreal<Any>, registered: &[String]) -> Result<(), Box<dyn Error>> {
+    trace!("Forgetting unregistered pools ...");
+    let _ = db
+        .query("DELETE pools WHERE pubkey NOTINSIDE $registered")
+        .query("UPDATE stakeaddresses SET pool = { pubkey: pool.pubkey } WHERE pool.pubkey NOTINSIDE $registered AND (pool.name != NONE OR pool.url != NONE OR pool.pool_is_active != NONE OR pool.status != NONE)")
+        .bind(("registered", registered))
+        .await?
+        .check()?;
+    Ok(())
+}
+
+// Distinct stakeaddresses per stake-only pubkey missing from the registry.
+pub async fn getunknownstakers(db: &Surreal<Any>) -> Result<Vec<KeyStakers>, Box<dyn Error>> {
+    trace!("Querying stakers of unknown stake keys ...");
+    let mut response = db
+        .query("LET $registered = (SELECT VALUE pubkey FROM pools)")
+        .query("SELECT pool.pubkey AS pubkey, count() AS stakers FROM stakeaddresses WHERE pool.pubkey NOTINSIDE $registered GROUP BY pubkey")
+        .await?;
+    let stakers: Vec<KeyStakers> = response.take(1)?;
+    Ok(stakers)
+}
+
+pub async fn getunknownblocks(db: &Surreal<Any>) -> Result<Vec<KeyBlocks>, Box<dyn Error>> {
+    trace!("Querying blocks staked by unknown stake keys ...");
+    let mut response = db
+        .query("LET $registered = (SELECT VALUE pubkey FROM pools)")
+        .query("SELECT coldstaking.pubkey AS pubkey, count() AS blocks, math::max(height) AS last_height FROM blocks WHERE coldstaking != NONE AND coldstaking.pubkey NOTINSIDE $registered GROUP BY pubkey")
+        .await?;
+    let blocks: Vec<KeyBlocks> = response.take(1)?;
+    Ok(blocks)
+}
+
+pub async fn getpoolcandidates(db: &Surreal<Any>) -> Result<Vec<PoolCandidate>, Box<dyn Error>> {
+    trace!("Querying pool candidates ...");
+    let mut response = db
+        .query("SELECT * FROM pool_candidates ORDER BY stakers DESC, blocks DESC")
+        .await?;
+    let candidates: Vec<PoolCandidate> = response.take(0)?;
+    Ok(candidates)
+}
+
+pub async fn regpoolcandidates(
+    db: &Surreal<Any>,
+    candidates: &[PoolCandidate],
+) -> Result<(), Box<dyn Error>> {
+    trace!("Recording {} pool candidates into DB ...", candidates.len());
+    let _ = db
+        .query("DELETE pool_candidates")
+        .query("INSERT INTO pool_candidates $candidates")
+        .bind(("candidates", candidates))
+        .await?
+        .check()?;
+    Ok(())
+}
+
+pub async fn gettopheight(db: &Surreal<Any>) -> Result<Option<u64>, Box<dyn Error>> {
+    trace!("Querying top height ...");
+    let mut response = db
+        .query("math::max((SELECT VALUE height FROM blocks))")
+        .await?;
+    let height: Option<u64> = response.take(0)?;
+    Ok(height)
+}
+
+// Height of the top block as recorded with every write, without a scan.
+// DBs written before the tip was recorded fall back to the scan.
+pub async fn gettip(db: &Surreal<Any>) -> Result<Option<u64>, Box<dyn Error>> {
+    trace!("Querying indexed tip ...");
+    let mut response = db.query("SELECT VALUE height FROM meta:tip").await?;
+    match response.take::<Option<u64>>(0)? {
+        Some(height) => Ok(Some(height)),
+        None => gettopheight(db).await,
+    }
+}
+
+// Ordering needs the field selected, so the height comes along.
+pub async fn gettoptime(db: &Surreal<Any>) -> Result<Option<u64>, Box<dyn Error>> {
+    trace!("Querying time of the top block ...");
+    let mut response = db
+        .query("SELECT time, height FROM blocks ORDER BY height DESC LIMIT 1")
+        .await?;
+    let time: Option<u64> = response.take((0, "time"))?;
+    Ok(time)
+}
+
+pub async fn getlaststaked(db: &Surreal<Any>) -> Result<Vec<KeyLastStaked>, Box<dyn Error>> {
+    trace!("Querying last blocks staked per stake key ...");
+    let mut response = db
+        .query("SELECT coldstaking.pubkey AS pubkey, math::max(height) AS last_height, math::max(time) AS last_time FROM blocks WHERE coldstaking != NONE GROUP BY pubkey")
+        .await?;
+    let last: Vec<KeyLastStaked> = response.take(0)?;
+    Ok(last)
+}
+
+// Blocks per stake key with time in (from, to], along with all blocks in that
+// span, so a window ending at the tip counts the tip.
+pub async fn getstakedbetween(
+    db: &Surreal<Any>,
+    from: u64,
+    to: u64,
+) -> Result<(HashMap<String, u64>, u64), Box<dyn Error>> {
+    trace!("Querying blocks staked between {} and {} ...", from, to);
+    let mut response = db
+        .query("SELECT coldstaking.pubkey AS pubkey, count() AS blocks FROM blocks WHERE time > $from AND time <= $to AND coldstaking != NONE GROUP BY pubkey")
+        .query("SELECT count() AS blocks FROM blocks WHERE time > $from AND time <= $to GROUP ALL")
+        .bind(("from", from))
+        .bind(("to", to))
+        .await?;
+    let keys: Vec<KeyCount> = response.take(0)?;
+    let total: Option<u64> = response.take((1, "blocks"))?;
+    let keys = keys
+        .into_iter()
+        .map(|key| (key.pubkey, key.blocks))
+        .collect();
+    Ok((keys, total.unwrap_or(0)))
+}
+
+pub async fn regpoolactivity(
+    db: &Surreal<Any>,
+    pubkey: &str,
+    activity: &PoolActivity,
+) -> Result<(), Box<dyn Error>> {
+    trace!("Recording activity of pool {} into DB ...", pubkey);
+    let _ = db
+        .query("UPDATE type::thing('pools', $pubkey) MERGE { activity: $activity }")
+        .bind(("pubkey", pubkey))
+        .bind(("activity", activity))
+        .await?
+        .check()?;
+    Ok(())
+}
+
+pub async fn getpoolstatuses(
+    db: &Surreal<Any>,
+) -> Result<HashMap<String, PoolStatus>, Box<dyn Error>> {
+    trace!("Querying derived pool statuses ...");
+    let mut response = db
+        .query("SELECT pubkey, activity.status AS status FROM pools WHERE activity != NONE")
+        .await?;
+    let statuses: Vec<KeyStatus> = response.take(0)?;
+    Ok(statuses
+        .into_iter()
+        .map(|key| (key.pubkey, key.status))
+        .collect())
+}
+
+pub async fn getbottomtime(db: &Surreal<Any>) -> Result<Option<u64>, Box<dyn Error>> {
+    trace!("Querying time of the bottom block ...");
+    let mut response = db
+        .query("SELECT time, height FROM blocks ORDER BY height LIMIT 1")
+        .await?;
+    let time: Option<u64> = response.take((0, "time"))?;
+    Ok(time)
+}
+
+pub async fn getlaststatsbucket(
+    db: &Surreal<Any>,
+    period: Period,
+) -> Result<Option<u64>, Box<dyn Error>> {
+    trace!("Querying last {:?} statistics bucket ...", period);
+    let mut response = db
+        .query("math::max((SELECT VALUE bucket FROM network_stats WHERE period = $period))")
+        .bind(("period", period))
+        .await?;
+    let bucket: Option<u64> = response.take(0)?;
+    Ok(bucket)
+}
+
+pub async fn getstakedblocks(
+    db: &Surreal<Any>,
+    from: u64,
+    to: u64,
+) -> Result<Vec<StakedBlock>, Box<dyn Error>> {
+    trace!("Querying staked blocks between {} and {} ...", from, to);
+    let mut response = db
+        .query("SELECT height, time, coldstaking, stakekernelvalue, tx[0] AS coinstake FROM blocks WHERE time >= $from AND time < $to")
+        .bind(("from", from))
+        .bind(("to", to))
+        .await?;
+    let blocks: Vec<StakedBlock> = response.take(0)?;
+    Ok(blocks)
+}
+
+pub async fn regstats(
+    db: &Surreal<Any>,
+    network: &NetworkStats,
+    pools: &[PoolStats],
+) -> Result<(), Box<dyn Error>> {
+    trace!(
+        "Recording {:?} statistics of bucket {} into DB ...",
+        network.period,
+        network.bucket
+    );
+    let _ = db
+        .query("UPDATE type::thing('network_stats', [$stats.period, $stats.bucket]) CONTENT $stats")
+        .bind(("stats", network))
+        .await?
+        .check()?;
+    for stats in pools {
+        let _ = db
+            .query("UPDATE type::thing('pool_stats', [$stats.period, $stats.bucket, $stats.pubkey]) CONTENT $stats")
+            .bind(("stats", stats))
+            .await?
+            .check()?;
+    }
+    Ok(())
+}
+
+pub async fn getblocks(
+    db: &Surreal<Any>,
+    limit: u64,
+    offset: u64,
+) -> Result<Vec<BlockData>, Box<dyn Error>> {
+    trace!("Querying {} blocks from offset {} ...", limit, offset);
+    let mut response = db
+        .query("SELECT * FROM blocks ORDER BY height DESC LIMIT $limit START $offset")
+        .bind(("limit", limit))
+        .bind(("offset", offset))
+        .await?;
+    let blocks: Vec<BlockData> = response.take(0)?;
+    Ok(blocks)
+}
+
+pub async fn getblockbyheight(
+    db: &Surreal<Any>,
+    height: u64,
+) -> Result<Option<BlockData>, Box<dyn Error>> {
+    trace!("Querying block {} ...", height);
+    let block: Option<BlockData> = db.select(("blocks", height)).await?;
+    Ok(block)
+}
+
+pub async fn getblockbyhash(
+    db: &Surreal<Any>,
+    hash: &str,
+) -> Result<Option<BlockData>, Box<dyn Error>> {
+    trace!("Querying block {} ...", hash);
+    let mut response = db
+        .query("SELECT * FROM blocks WHERE hash = $hash LIMIT 1")
+        .bind(("hash", hash))
+        .await?;
+    let block: Option<BlockData> = response.take(0)?;
+    Ok(block)
+}
+
+pub async fn gettx(db: &Surreal<Any>, txid: &str) -> Result<Option<Transaction>, Box<dyn Error>> {
+    trace!("Querying transaction {} ...", txid);
+    let mut response = db
+        .query("SELECT VALUE height FROM type::thing('txids', $txid)")
+        .bind(("txid", txid))
+        .await?;
+    let Some(height): Option<u64> = response.take(0)? else {
+        return Ok(None);
+    };
+    let mut response = db
+        .query("SELECT VALUE tx[WHERE txid = $txid][0] FROM type::thing('blocks', $height)")
+        .bind(("txid", txid))
+        .bind(("height", height))
+        .await?;
+    let tx: Option<Transaction> = response.take(0)?;
+    Ok(tx)
+}
+
+pub async fn getstakeaddress(
+    db: &Surreal<Any>,
+    raw: &str,
+) -> Result<Option<Stakeaddress>, Box<dyn Error>> {
+    trace!("Querying stakeaddress {} ...", raw);
+    let stakeaddress: Option<Stakeaddress> = db.select(("stakeaddresses", raw)).await?;
+    Ok(stakeaddress)
+}
+
+// Newest first, heights of blocks with outputs paying to the address.
+pub async fn getaddressheights(
+    db: &Surreal<Any>,
+    address: &str,
+    limit: u64,
+    offset: u64,
+) -> Result<Vec<u64>, Box<dyn Error>> {
+    trace!("Querying blocks paying to {} ...", address);
+    let mut response = db
+        .query("SELECT height FROM addresses WHERE address = $address ORDER BY height DESC LIMIT $limit START $offset")
+        .bind(("address", address))
+        .bind(("limit", limit))
+        .bind(("offset", offset))
+        .await?;
+    let heights: Vec<u64> = response.take((0, "height"))?;
+    Ok(heights)
+}
+
+// Registry entry as stored in the pools table, with its derived activity.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolRecord {
+    #[serde(flatten)]
+    pub entry: PoolEntry,
+    pub activity: Option<PoolActivity>,
+}
+
+impl PoolRecord {
+    // Label as the registry and the derived activity currently have it.
+    pub fn label(&self) -> Pool {
+        self.entry
+            .label(self.activity.as_ref().map(|activity| activity.status))
+    }
+}
+
+pub async fn getpools(db: &Surreal<Any>) -> Result<Vec<PoolRecord>, Box<dyn Error>> {
+    trace!("Querying pools ...");
+    let mut response = db.query("SELECT * FROM pools ORDER BY name").await?;
+    let pools: Vec<PoolRecord> = response.take(0)?;
+    Ok(pools)
+}
+
+pub async fn getpool(
+    db: &Surreal<Any>,
+    pubkey: &str,
+) -> Result<Option<PoolRecord>, Box<dyn Error>> {
+    trace!("Querying pool {} ...", pubkey);
+    let pool: Option<PoolRecord> = db.select(("pools", pubkey)).await?;
+    Ok(pool)
+}
+
+// Newest first, blocks coldstaked to the given stake key.
+pub async fn getblocksbypool(
+    db: &Surreal<Any>,
+    pubkey: &str,
+    limit: u64,
+    offset: u64,
+) -> Result<Vec<BlockData>, Box<dyn Error>> {
+    trace!("Querying blocks staked by {} ...", pubkey);
+    let mut response = db
+        .query("SELECT * FROM blocks WHERE coldstaking.pubkey = $pubkey ORDER BY height DESC LIMIT $limit START $offset")
+        .bind(("pubkey", pubkey))
+        .bind(("limit", limit))
+        .bind(("offset", offset))
+        .await?;
+    let blocks: Vec<BlockData> = response.take(0)?;
+    Ok(blocks)
+}
+
+pub async fn getpoolstats(
+    db: &Surreal<Any>,
+    pubkey: &str,
+    period: Period,
+    limit: u64,
+    offset: u64,
+) -> Result<Vec<PoolStats>, Box<dyn Error>> {
+    trace!("Querying {:?} statistics of pool {} ...", period, pubkey);
+    let mut response = db
+        .query("SELECT * FROM pool_stats WHERE pubkey = $pubkey AND period = $period ORDER BY bucket DESC LIMIT $limit START $offset")
+        .bind(("pubkey", pubkey))
+        .bind(("period", period))
+        .bind(("limit", limit))
+        .bind(("offset", offset))
+        .await?;
+    let stats: Vec<PoolStats> = response.take(0)?;
+    Ok(stats)
+}
+
+pub async fn getproposal(
+    db: &Surreal<Any>,
+    proposal_id: u64,
+) -> Result<Option<Proposal>, Box<dyn Error>> {
+    trace!("Querying proposal {} ...", proposal_id);
+    let proposal: Option<Proposal> = db.select(("proposals", proposal_id)).await?;
+    Ok(proposal)
+}
+
+pub async fn gethash(db: &Surreal<Any>, height: u64) -> Result<Option<String>, Box<dyn Error>> {
+    trace!("Querying hash of block {} ...", height);
+    let mut response = db
+        .query("SELECT VALUE hash FROM type::thing('blocks', $height)")
+        .bind(("height", height))
+        .await?;
+    let hash: Option<String> = response.take(0)?;
+    Ok(hash)
+}
+
+// Remove every block from the given height up, and proposals no remaining
+// block voted on, returning the removed hashes.
+pub async fn rollback(db: &Surreal<Any>, from: u64) -> Result<Vec<String>, Box<dyn Error>> {
+    debug!("Rolling back blocks from {} up ...", from);
+    let _timer = metrics::db_write("rollback");
+    // Half a rollback would leave lookups and votes of missing blocks behind.
+    let mut response = db
+        .query("BEGIN TRANSACTION")
+        .query("SELECT height, hash FROM blocks WHERE height >= $from ORDER BY height")
+        .query("DELETE blocks WHERE height >= $from")
+        .query("DELETE votes WHERE height >= $from")
+        .query("DELETE txids WHERE height >= $from")
+        .query("DELETE addresses WHERE height >= $from")
+        .query("DELETE proposals WHERE proposal_id NOTINSIDE array::distinct((SELECT VALUE proposal FROM votes))")
+        .query("DELETE mempool_confirmations WHERE height >= $from")
+        .query("UPDATE meta:tip SET height = (SELECT VALUE height FROM blocks ORDER BY height DESC LIMIT 1)[0]")
+        .query("COMMIT TRANSACTION")
+        .bind(("from", from))
+        .await?;
+    let orphaned: Vec<String> = response.take((0, "hash"))?;
+    response.check()?;
+    Ok(orphaned)
+}
+
+// Stake keys of blocks in (from, to], oldest first.
+pub async fn getstakekeys(
+    db: &Surreal<Any>,
+    from: u64,
+    to: u64,
+) -> Result<Vec<StakeKey>, Box<dyn Error>> {
+    trace!(
+        "Querying stake keys of blocks after {} up to {} ...",
+        from,
+        to
+    );
+    let mut response = db
+        .query("SELECT height, coldstaking.pubkey AS pubkey FROM blocks WHERE height > $from AND height <= $to ORDER BY height")
+        .bind(("from", from))
+        .bind(("to", to))
+        .await?;
+    let keys: Vec<StakeKey> = response.take(0)?;
+    Ok(keys)
+}
+
+pub async fn regdelivery(db: &Surreal<Any>, delivery: &Delivery) -> Result<(), Box<dyn Error>> {
+    trace!(
+        "Recording webhook delivery of '{}' into DB ...",
+        delivery.rule
+    );
+    let _: Vec<Delivery> = db.create("deliveries").content(delivery).await?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChainLink {
+    pub height: u64,
+    pub hash: String,
+    pub previousblockhash: Option<String>,
+}
+
+pub async fn getchain(
+    db: &Surreal<Any>,
+    from: u64,
+    to: u64,
+) -> Result<Vec<ChainLink>, Box<dyn Error>> {
+    trace!("Querying chain links of blocks {} to {} ...", from, to);
+    let mut response = db
+        .query("SELECT height, hash, previousblockhash FROM blocks WHERE height >= $from AND height <= $to ORDER BY height")
+        .bind(("from", from))
+        .bind(("to", to))
+        .await?;
+    let links: Vec<ChainLink> = response.take(0)?;
+    Ok(links)
+}
+
+// Stats from this bucket on are materialized again on the next run.
+pub async fn forgetstats(db: &Surreal<Any>, from: u64) -> Result<(), Box<dyn Error>> {
+    debug!("Forgetting statistics from bucket {} on ...", from);
+    let _ = db
+        .query("DELETE network_stats WHERE bucket >= $from")
+        .query("DELETE pool_stats WHERE bucket >= $from")
+        .bind(("from", from))
+        .await?
+        .check()?;
+    Ok(())
+}
+
+pub async fn gettime(db: &Surreal<Any>, height: u64) -> Result<Option<u64>, Box<dyn Error>> {
+    trace!("Querying time of block {} ...", height);
+    let mut response = db
+        .query("SELECT VALUE time FROM type::thing('blocks', $height)")
+        .bind(("height", height))
+        .await?;
+    let time: Option<u64> = response.take(0)?;
+    Ok(time)
+}
+
+pub async fn getrecords(
+    db: &Surreal<Any>,
+    table: &str,
+) -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
+    trace!("Querying records of {} ...", table);
+    let mut response = db
+        .query("SELECT * FROM type::table($table)")
+        .bind(("table", table))
+        .await?;
+    let records: Vec<serde_json::Value> = response.take(0)?;
+    Ok(records)
+}
+
+// Records of a table keyed by height with ids in [from, to], read by record
+// id rather than through a filter. The table name is the caller's own.
+pub async fn getrecordrange(
+    db: &Surreal<Any>,
+    table: &str,
+    from: u64,
+    to: u64,
+) -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
+    trace!(
+        "Querying records of {} from height {} to {} ...",
+        table,
+        from,
+        to
+    );
+    let mut response = db
+        .query(format!("SELECT * FROM {}:{}..={}", table, from, to))
+        .await?;
+    let records: Vec<serde_json::Value> = response.take(0)?;
+    Ok(records)
+}
+
+// Network the DB was created for.
+pub async fn getnetwork(db: &Surreal<Any>) -> Result<Option<Network>, Box<dyn Error>> {
+    trace!("Querying network of the DB ...");
+    let mut response = db.query("SELECT VALUE network FROM meta:network").await?;
+    let network: Option<Network> = response.take(0)?;
+    Ok(network)
+}
+
+pub async fn regnetwork(db: &Surreal<Any>, network: Network) -> Result<(), Box<dyn Error>> {
+    info!("Marking the DB as {} ...", network);
+    let _ = db
+        .query("UPDATE meta:network SET network = $network")
+        .bind(("network", network))
+        .await?
+        .check()?;
+    Ok(())
+}
+
+pub async fn regmempooltx(db: &Surreal<Any>, tx: &MempoolTx) -> Result<(), Box<dyn Error>> {
+    trace!("Recording mempool transaction {} into DB ...", tx.txid);
+    let _timer = metrics::db_write("regmempooltx");
+    let _: Option<MempoolTx> = db.update(("mempool", tx.txid.clone())).content(tx).await?;
+    Ok(())
+}
+
+pub async fn getmempool(db: &Surreal<Any>) -> Result<Vec<MempoolTx>, Box<dyn Error>> {
+    trace!("Querying mempool ...");
+    let mut response = db.query("SELECT * FROM mempool").await?;
+    let txs: Vec<MempoolTx> = response.take(0)?;
+    Ok(txs)
+}
+
+pub async fn getmempoolids(db: &Surreal<Any>) -> Result<Vec<String>, Box<dyn Error>> {
+    trace!("Querying mempool txids ...");
+    let mut response = db.query("SELECT VALUE txid FROM mempool").await?;
+    let txids: Vec<String> = response.take(0)?;
+    Ok(txids)
+}
+
+// Transactions tracked and their total virtual size.
+pub async fn getmempoolsize(db: &Surreal<Any>) -> Result<(u64, u64), Box<dyn Error>> {
+    trace!("Querying mempool size ...");
+    let mut response = db
+        .query("SELECT count() AS size, math::sum(vsize) AS vbytes FROM mempool GROUP ALL")
+        .await?;
+    let size: Option<u64> = response.take((0, "size"))?;
+    let vbytes: Option<u64> = response.take((0, "vbytes"))?;
+    Ok((size.unwrap_or(0), vbytes.unwrap_or(0)))
+}
+
+// Drop transactions from the mempool, handing back those that were tracked.
+pub async fn forgetmempooltxs(
+    db: &Surreal<Any>,
+    txids: &[&str],
+) -> Result<Vec<MempoolTx>, Box<dyn Error>> {
+    trace!("Forgetting {} mempool transactions ...", txids.len());
+    let _timer = metrics::db_write("forgetmempooltxs");
+    let mut response = db
+        .query("DELETE mempool WHERE txid INSIDE $txids RETURN BEFORE")
+        .bind(("txids", txids))
+        .await?;
+    let forgotten: Vec<MempoolTx> = response.take(0)?;
+    Ok(forgotten)
+}
+
+pub async fn regconfirmation(
+    db: &Surreal<Any>,
+    confirmation: &Confirmation,
+) -> Result<(), Box<dyn Error>> {
+    trace!(
+        "Recording confirmation of {} into DB ...",
+        confirmation.txid
+    );
+    let _timer = metrics::db_write("regconfirmation");
+    let _: Option<Confirmation> = db
+        .update(("mempool_confirmations", confirmation.txid.clone()))
+        .content(confirmation)
+        .await?;
+    Ok(())
+}
+
+// Confirmation latencies of blocks from the given unix time on.
+pub async fn getlatencies(db: &Surreal<Any>, since: u64) -> Result<Vec<u64>, Box<dyn Error>> {
+    trace!("Querying confirmation latencies since {} ...", since);
+    let mut response = db
+        .query("SELECT VALUE latency FROM mempool_confirmations WHERE confirmed >= $since")
+        .bind(("since", since))
+        .await?;
+    let latencies: Vec<u64> = response.take(0)?;
+    Ok(latencies)
+}
@@ -0,0 +1,669 @@
+// Blocks and transactions decoded from Ghost's raw serialization, as
+// `getblock <hash> 0` and ZMQ rawblock carry them, into the same model
+// verbose getblock fills.
+use crate::{
+    console::{BlockData, ParseError, ScriptPubKey, ScriptSig, Transaction, Vin, Vout},
+    network::{Network, Params},
+};
+use bech32::{ToBase32, Variant};
+use bitcoin_hashes::{hash160, sha256d, Hash};
+use serde::Deserialize;
+
+const COIN: f64 = 100_000_000.0;
+// Transactions whose first byte is at least this use Ghost's layout.
+const GHOST_TXN_VERSION: u8 = 0xa0;
+// Output index marking an input as spending anon outputs.
+const ANON_MARKER: u32 = 0xffff_ffa0;
+
+const OUTPUT_STANDARD: u8 = 1;
+const OUTPUT_CT: u8 = 2;
+const OUTPUT_RINGCT: u8 = 3;
+const OUTPUT_DATA: u8 = 4;
+
+// Tagged fields of a data output, following the height in its first 4 bytes.
+const DO_VOTE: u8 = 5;
+const DO_TREASURY_FUND_CFWD: u8 = 7;
+const DO_SMSG_FEE: u8 = 9;
+const DO_SMSG_DIFFICULTY: u8 = 10;
+
+const OP_IF: u8 = 0x63;
+const OP_ELSE: u8 = 0x67;
+const OP_ENDIF: u8 = 0x68;
+const OP_ISCOINSTAKE: u8 = 0xb8;
+
+// What the block itself doesn't say about its place in the chain and
+// the kernel it staked, as `getblock <hash> 1` reports it. Block files
+// have no stake kernel fields, leaving rewards unknown.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HeaderInfo {
+    pub hash: String,
+    pub height: u64,
+    pub mediantime: u64,
+    pub chainwork: String,
+    pub difficulty: f64,
+    pub hashproofofstake: Option<String>,
+    pub prevstakemodifier: Option<String>,
+    pub stakekernelblockhash: Option<String>,
+    pub stakekernelscript: Option<String>,
+    pub stakekernelvalue: Option<f64>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ParseError> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| ParseError::Block(format!("truncated at byte {}", self.pos)))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+    fn u8(&mut self) -> Result<u8, ParseError> {
+        Ok(self.take(1)?[0])
+    }
+    fn u32(&mut self) -> Result<u32, ParseError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn i64(&mut self) -> Result<i64, ParseError> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    fn compact(&mut self) -> Result<u64, ParseError> {
+        Ok(match self.u8()? {
+            0xfd => u16::from_le_bytes(self.take(2)?.try_into().unwrap()) as u64,
+            0xfe => self.u32()? as u64,
+            0xff => u64::from_le_bytes(self.take(8)?.try_into().unwrap()),
+            n => n as u64,
+        })
+    }
+    // A length prefixed byte string.
+    fn var(&mut self) -> Result<&'a [u8], ParseError> {
+        let n = self.compact()?;
+        self.take(usize::try_from(n).unwrap_or(usize::MAX))
+    }
+    // A count of items, each at least a byte long.
+    fn count(&mut self) -> Result<usize, ParseError> {
+        let n = self.compact()?;
+        match usize::try_from(n) {
+            Ok(n) if n <= self.bytes.len() - self.pos => Ok(n),
+            _ => Err(ParseError::Block(format!(
+                "{} items can't fit in what is left at byte {}",
+                n, self.pos
+            ))),
+        }
+    }
+    fn since(&self, start: usize) -> &'a [u8] {
+        &self.bytes[start..self.pos]
+    }
+}
+
+pub fn block(bytes: &[u8], info: HeaderInfo, network: Network) -> Result<BlockData, ParseError> {
+    let mut reader = Reader::new(bytes);
+    let version = reader.u32()?;
+    let previous = reader.take(32)?;
+    let merkleroot = reader.take(32)?;
+    let witnessmerkleroot = reader.take(32)?;
+    let time = reader.u32()?;
+    let bits = reader.u32()?;
+    let nonce = reader.u32()?;
+    let hash = display(&sha256d::Hash::hash(reader.since(0)).to_byte_array());
+    if hash != info.hash {
+        return Err(ParseError::Block(format!(
+            "decodes to {} rather than {}",
+            hash, info.hash
+        )));
+    }
+    let n_tx = reader.count()?;
+    let mut tx = Vec::with_capacity(n_tx);
+    let mut witness = 0;
+    for _ in 0..n_tx {
+        let (transaction, witness_len) = read_transaction(&mut reader, network)?;
+        witness += witness_len;
+        tx.push(transaction);
+    }
+    let blocksig = reader.var()?;
+    if reader.pos != bytes.len() {
+        return Err(ParseError::Block(format!(
+            "{} bytes left over",
+            bytes.len() - reader.pos
+        )));
+    }
+    let size = bytes.len() as u64;
+    let strippedsize = size - witness as u64;
+    Ok(BlockData {
+        bits: format!("{:08x}", bits),
+        blocksig: (!blocksig.is_empty()).then(|| hex(blocksig)),
+        chainwork: info.chainwork,
+        difficulty: info.difficulty,
+        hash,
+        hashproofofstake: info.hashproofofstake,
+        height: info.height,
+        mediantime: info.mediantime,
+        merkleroot: display(merkleroot),
+        n_tx: n_tx as u64,
+        nonce: nonce as u64,
+        previousblockhash: previous.iter().any(|b| *b != 0).then(|| display(previous)),
+        prevstakemodifier: info.prevstakemodifier,
+        size,
+        stakekernelblockhash: info.stakekernelblockhash,
+        stakekernelscript: info.stakekernelscript,
+        stakekernelvalue: info.stakekernelvalue,
+        strippedsize,
+        time: time as u64,
+        tx,
+        version: version as u64,
+        version_hex: format!("{:08x}", version),
+        weight: strippedsize * 3 + size,
+        witnessmerkleroot: display(witnessmerkleroot),
+        coldstaking: None,
+        voting_info: None,
+    })
+}
+
+pub fn transaction(bytes: &[u8], network: Network) -> Result<Transaction, ParseError> {
+    let mut reader = Reader::new(bytes);
+    let (tx, _) = read_transaction(&mut reader, network)?;
+    if reader.pos != bytes.len() {
+        return Err(ParseError::Block(format!(
+            "{} bytes left over after transaction {}",
+            bytes.len() - reader.pos,
+            tx.txid
+        )));
+    }
+    Ok(tx)
+}
+
+// A transaction and the length of its witness data.
+fn read_transaction(
+    reader: &mut Reader,
+    network: Network,
+) -> Result<(Transaction, usize), ParseError> {
+    let start = reader.pos;
+    let version = reader.u8()?;
+    if version < GHOST_TXN_VERSION {
+        return Err(ParseError::Block(format!(
+            "transaction version {} at byte {} predates Ghost's layout",
+            version, start
+        )));
+    }
+    let kind = reader.u8()?;
+    let locktime = reader.u32()?;
+
+    let n_vin = reader.count()?;
+    let mut inputs = Vec::with_capacity(n_vin);
+    for _ in 0..n_vin {
+        let prevout = reader.take(32)?;
+        let n = reader.u32()?;
+        let script = reader.var()?;
+        let sequence = reader.u32()?;
+        // Anon inputs carry their key images in a stack of their own,
+        // serialized with the input rather than with the witnesses.
+        if n == ANON_MARKER {
+            stack(reader)?;
+        }
+        inputs.push((prevout, n, script, sequence));
+    }
+    let n_vout = reader.count()?;
+    let mut vout = Vec::with_capacity(n_vout);
+    for n in 0..n_vout as u64 {
+        vout.push(read_output(reader, n, network.params())?);
+    }
+    let stripped = reader.since(start);
+
+    let witness_start = reader.pos;
+    let mut witnesses = Vec::with_capacity(n_vin);
+    for _ in 0..n_vin {
+        witnesses.push(stack(reader)?);
+    }
+    let witness_len = reader.pos - witness_start;
+    let whole = reader.since(start);
+
+    let vin = inputs
+        .into_iter()
+        .zip(witnesses)
+        .map(|((prevout, n, script, sequence), txinwitness)| match n {
+            // The ring's shape is packed into the otherwise unused prevout.
+            ANON_MARKER => Vin::Anon {
+                input_type: "anon".to_string(),
+                num_inputs: u32::from_le_bytes(prevout[0..4].try_into().unwrap()) as u64,
+                ring_size: u32::from_le_bytes(prevout[4..8].try_into().unwrap()) as u64,
+                txinwitness,
+                sequence: sequence as u64,
+            },
+            _ => Vin::Standard {
+                txid: display(prevout),
+                vout: n as u64,
+                script_sig: ScriptSig {
+                    asm: asm(script),
+                    hex: hex(script),
+                },
+            },
+        })
+        .collect();
+
+    let size = whole.len() as u64;
+    let weight = stripped.len() as u64 * 3 + size;
+    let tx = Transaction {
+        txid: display(&sha256d::Hash::hash(stripped).to_byte_array()),
+        hash: display(&sha256d::Hash::hash(whole).to_byte_array()),
+        version: (version as u64) | (kind as u64) << 8,
+        size,
+        vsize: weight.div_ceil(4),
+        weight,
+        locktime: locktime as u64,
+        // The raw bytes are ghostd's to keep.
+        hex: String::new(),
+        vin,
+        vout,
+    };
+    Ok((tx, witness_len))
+}
+
+// A count of items, each a length prefixed byte string.
+fn stack(reader: &mut Reader) -> Result<Vec<String>, ParseError> {
+    let items = reader.count()?;
+    let mut stack = Vec::with_capacity(items);
+    for _ in 0..items {
+        stack.push(hex(reader.var()?));
+    }
+    Ok(stack)
+}
+
+fn read_output(reader: &mut Reader, n: u64, params: &Params) -> Result<Vout, ParseError> {
+    let kind = reader.u8()?;
+    Ok(match kind {
+        OUTPUT_STANDARD => {
+            let valuesat = reader.i64()?;
+            let script = reader.var()?;
+            Vout::Standard {
+                n,
+                value: valuesat as f64 / COIN,
+                valuesat: valuesat as u64,
+                scriptpubkey: script_pubkey(script, params),
+            }
+        }
+        OUTPUT_CT => {
+            let commitment = reader.take(33)?;
+            let data = reader.var()?;
+            let script = reader.var()?;
+            let rangeproof = reader.var()?;
+            Vout::Blind {
+                n,
+                pubkey: None,
+                scriptpubkey: Some(script_pubkey(script, params)),
+                value_commitment: hex(commitment),
+                data_hex: hex(data),
+                rangeproof: hex(rangeproof),
+            }
+        }
+        OUTPUT_RINGCT => {
+            let pubkey = reader.take(33)?;
+            let commitment = reader.take(33)?;
+            let data = reader.var()?;
+            let rangeproof = reader.var()?;
+            Vout::Anon {
+                n,
+                pubkey: Some(hex(pubkey)),
+                value_commitment: hex(commitment),
+                data_hex: hex(data),
+                rangeproof: hex(rangeproof),
+            }
+        }
+        OUTPUT_DATA => data_output(n, reader.var()?),
+        _ => {
+            return Err(ParseError::Block(format!(
+                "unknown output type {} at byte {}",
+                kind,
+                reader.pos - 1
+            )))
+        }
+    })
+}
+
+// Coinstake data outputs carry the height, then whichever tagged fields
+// the staker set. Unknown tags end the fields, as they do for ghostd.
+fn data_output(n: u64, data: &[u8]) -> Vout {
+    let mut smsgdifficulty = None;
+    let mut smsgfeerate = None;
+    let mut treasury_fund_cfwd = None;
+    let mut vote = None;
+    let mut reader = Reader::new(data);
+    reader.pos = 4;
+    while let Ok(tag) = reader.u8() {
+        match tag {
+            DO_VOTE => match reader.u32() {
+                Ok(token) => vote = Some(format!("{}, {}", token & 0xffff, token >> 16)),
+                Err(_) => break,
+            },
+            DO_SMSG_DIFFICULTY => match reader.u32() {
+                Ok(difficulty) => smsgdifficulty = Some(format!("{:08x}", difficulty)),
+                Err(_) => break,
+            },
+            DO_SMSG_FEE => match varint(&mut reader) {
+                Some(fee) => smsgfeerate = Some(fee as f64 / COIN),
+                None => break,
+            },
+            DO_TREASURY_FUND_CFWD => match varint(&mut reader) {
+                Some(cfwd) => treasury_fund_cfwd = Some(cfwd as f64 / COIN),
+                None => break,
+            },
+            _ => break,
+        }
+    }
+    Vout::Data {
+        n,
+        data_hex: hex(data),
+        smsgdifficulty,
+        smsgfeerate,
+        treasury_fund_cfwd,
+        vote,
+    }
+}
+
+// Seven bits a byte, least significant first, high bit set on all but the last.
+fn varint(reader: &mut Reader) -> Option<u64> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = reader.u8().ok()?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn script_pubkey(script: &[u8], params: &Params) -> ScriptPubKey {
+    // OP_ISCOINSTAKE OP_IF <stake script> OP_ELSE <spend script> OP_ENDIF,
+    // the stake script being a 25 byte pay to pubkey hash.
+    let coldstake = script.len() > 29
+        && script[..2] == [OP_ISCOINSTAKE, OP_IF]
+        && script[27] == OP_ELSE
+        && script[script.len() - 1] == OP_ENDIF;
+    let (spend, stakeaddresses) = match coldstake {
+        true => (
+            &script[28..script.len() - 1],
+            destination(&script[2..27], params).1,
+        ),
+        false => (script, None),
+    };
+    let (kind, addresses) = destination(spend, params);
+    ScriptPubKey {
+        req_sigs: addresses.as_ref().map(|_| 1),
+        addresses,
+        stakeaddresses,
+        asm: asm(script),
+        hex: hex(script),
+        staking_type: kind.to_string(),
+    }
+}
+
+// Script type as ghostd names it, and the address it pays to.
+fn destination(script: &[u8], params: &Params) -> (&'static str, Option<Vec<String>>) {
+    let address = |prefix: u8, hash: &[u8]| Some(vec![base58check(prefix, hash)]);
+    match script {
+        [0x76, 0xa9, 20, hash @ .., 0x88, 0xac] if hash.len() == 20 => {
+            ("pubkeyhash", address(params.pubkey_address, hash))
+        }
+        [0x76, 0xa8, 32, hash @ .., 0x88, 0xac] if hash.len() == 32 => {
+            ("pubkeyhash256", address(params.pubkey_address_256, hash))
+        }
+        [0xa9, 20, hash @ .., 0x87] if hash.len() == 20 => {
+            ("scripthash", address(params.script_address, hash))
+        }
+        [0xaa, 32, hash @ .., 0x87] if hash.len() == 32 => {
+            ("scripthash256", address(params.script_address_256, hash))
+        }
+        [len @ (33 | 65), pubkey @ .., 0xac] if pubkey.len() == *len as usize => {
+            let hash = hash160::Hash::hash(pubkey).to_byte_array();
+            ("pubkey", address(params.pubkey_address, &hash))
+        }
+        [0x6a, ..] => ("nulldata", None),
+        _ => ("nonstandard", None),
+    }
+}
+
+const ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+// The stake-only pubkey of a stakeaddress, as validateaddress reports it:
+// the same key hash, bech32 encoded for the network.
+pub fn stakeonly(stakeaddress: &str, params: &Params) -> Option<String> {
+    let payload = unbase58check(stakeaddress)?;
+    let (prefix, hash) = payload.split_first()?;
+    if *prefix != params.pubkey_address || hash.len() != 20 {
+        return None;
+    }
+    bech32::encode(params.stake_hrp, hash.to_base32(), Variant::Bech32).ok()
+}
+
+fn unbase58check(address: &str) -> Option<Vec<u8>> {
+    // Bytes, least significant first.
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in address.bytes() {
+        let mut carry = ALPHABET.iter().position(|a| *a == c)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = address.bytes().take_while(|c| *c == b'1').count();
+    let mut payload = vec![0; zeros];
+    payload.extend(bytes.iter().rev());
+    if payload.len() < 4 {
+        return None;
+    }
+    let (payload, checksum) = payload.split_at(payload.len() - 4);
+    (sha256d::Hash::hash(payload).to_byte_array()[..4] == *checksum).then(|| payload.to_vec())
+}
+
+fn base58check(prefix: u8, hash: &[u8]) -> String {
+    let mut payload = vec![prefix];
+    payload.extend_from_slice(hash);
+    let checksum = sha256d::Hash::hash(&payload).to_byte_array();
+    payload.extend_from_slice(&checksum[..4]);
+    // Base 58 digits, least significant first.
+    let mut digits: Vec<u8> = Vec::new();
+    for byte in &payload {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let zeros = payload.iter().take_while(|byte| **byte == 0).count();
+    std::iter::repeat_n('1', zeros)
+        .chain(
+            digits
+                .iter()
+                .rev()
+                .map(|digit| ALPHABET[*digit as usize] as char),
+        )
+        .collect()
+}
+
+// Script as ghostd disassembles it: small pushes as numbers, others as hex.
+pub fn asm(script: &[u8]) -> String {
+    let mut reader = Reader::new(script);
+    let mut words = Vec::new();
+    while let Ok(opcode) = reader.u8() {
+        let push = match opcode {
+            0x00..=0x4b => reader.take(opcode as usize),
+            0x4c => reader.u8().and_then(|n| reader.take(n as usize)),
+            0x4d => reader
+                .take(2)
+                .and_then(|n| reader.take(u16::from_le_bytes([n[0], n[1]]) as usize)),
+            0x4e => reader.u32().and_then(|n| reader.take(n as usize)),
+            _ => {
+                words.push(opname(opcode));
+                continue;
+            }
+        };
+        match push {
+            Ok(data) if data.len() <= 4 => words.push(scriptnum(data).to_string()),
+            Ok(data) => words.push(hex(data)),
+            Err(_) => {
+                words.push("[error]".to_string());
+                break;
+            }
+        }
+    }
+    words.join(" ")
+}
+
+// Little endian, the top bit of the last byte being the sign.
+fn scriptnum(data: &[u8]) -> i64 {
+    let Some(last) = data.last() else {
+        return 0;
+    };
+    let mut value = data
+        .iter()
+        .enumerate()
+        .fold(0i64, |value, (i, byte)| value | (*byte as i64) << (8 * i));
+    if last & 0x80 != 0 {
+        value &= !(0x80i64 << (8 * (data.len() - 1)));
+        value = -value;
+    }
+    value
+}
+
+fn opname(opcode: u8) -> String {
+    let name = match opcode {
+        0x4f => return "-1".to_string(),
+        0x51..=0x60 => return (opcode - 0x50).to_string(),
+        0x50 => "RESERVED",
+        0x61 => "NOP",
+        0x62 => "VER",
+        0x63 => "IF",
+        0x64 => "NOTIF",
+        0x65 => "VERIF",
+        0x66 => "VERNOTIF",
+        0x67 => "ELSE",
+        0x68 => "ENDIF",
+        0x69 => "VERIFY",
+        0x6a => "RETURN",
+        0x6b => "TOALTSTACK",
+        0x6c => "FROMALTSTACK",
+        0x6d => "2DROP",
+        0x6e => "2DUP",
+        0x6f => "3DUP",
+        0x70 => "2OVER",
+        0x71 => "2ROT",
+        0x72 => "2SWAP",
+        0x73 => "IFDUP",
+        0x74 => "DEPTH",
+        0x75 => "DROP",
+        0x76 => "DUP",
+        0x77 => "NIP",
+        0x78 => "OVER",
+        0x79 => "PICK",
+        0x7a => "ROLL",
+        0x7b => "ROT",
+        0x7c => "SWAP",
+        0x7d => "TUCK",
+        0x7e => "CAT",
+        0x7f => "SUBSTR",
+        0x80 => "LEFT",
+        0x81 => "RIGHT",
+        0x82 => "SIZE",
+        0x83 => "INVERT",
+        0x84 => "AND",
+        0x85 => "OR",
+        0x86 => "XOR",
+        0x87 => "EQUAL",
+        0x88 => "EQUALVERIFY",
+        0x89 => "RESERVED1",
+        0x8a => "RESERVED2",
+        0x8b => "1ADD",
+        0x8c => "1SUB",
+        0x8d => "2MUL",
+        0x8e => "2DIV",
+        0x8f => "NEGATE",
+        0x90 => "ABS",
+        0x91 => "NOT",
+        0x92 => "0NOTEQUAL",
+        0x93 => "ADD",
+        0x94 => "SUB",
+        0x95 => "MUL",
+        0x96 => "DIV",
+        0x97 => "MOD",
+        0x98 => "LSHIFT",
+        0x99 => "RSHIFT",
+        0x9a => "BOOLAND",
+        0x9b => "BOOLOR",
+        0x9c => "NUMEQUAL",
+        0x9d => "NUMEQUALVERIFY",
+        0x9e => "NUMNOTEQUAL",
+        0x9f => "LESSTHAN",
+        0xa0 => "GREATERTHAN",
+        0xa1 => "LESSTHANOREQUAL",
+        0xa2 => "GREATERTHANOREQUAL",
+        0xa3 => "MIN",
+        0xa4 => "MAX",
+        0xa5 => "WITHIN",
+        0xa6 => "RIPEMD160",
+        0xa7 => "SHA1",
+        0xa8 => "SHA256",
+        0xa9 => "HASH160",
+        0xaa => "HASH256",
+        0xab => "CODESEPARATOR",
+        0xac => "CHECKSIG",
+        0xad => "CHECKSIGVERIFY",
+        0xae => "CHECKMULTISIG",
+        0xaf => "CHECKMULTISIGVERIFY",
+        0xb0 => "NOP1",
+        0xb1 => "CHECKLOCKTIMEVERIFY",
+        0xb2 => "CHECKSEQUENCEVERIFY",
+        0xb3 => "NOP4",
+        0xb4 => "NOP5",
+        0xb5 => "NOP6",
+        0xb6 => "NOP7",
+        0xb7 => "NOP8",
+        OP_ISCOINSTAKE => "ISCOINSTAKE",
+        0xb9 => "NOP10",
+        _ => "UNKNOWN",
+    };
+    format!("OP_{}", name)
+}
+
+// Hashes are shown byte reversed.
+fn display(hash: &[u8]) -> String {
+    hash.iter()
+        .rev()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn unhex(hex: &str) -> Result<Vec<u8>, ParseError> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(ParseError::Block("odd or non ASCII hex".to_string()));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| ParseError::Block(format!("bad hex at {}", i)))
+        })
+        .collect()
+}
@@ -0,0 +1,65 @@
+// Ranking of stake-only pubkeys missing from the pool registry.
+use crate::db;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error};
+use surrealdb::{engine::any::Any, Surreal};
+
+// Distinct stakeaddresses sharing one stake key before it is flagged as a pool.
+const MIN_POOL_STAKERS: u64 = 5;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PoolCandidate {
+    pub pubkey: String,
+    pub stakers: u64,
+    pub blocks: u64,
+    pub last_height: Option<u64>,
+    pub likely_pool: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyStakers {
+    pub pubkey: String,
+    pub stakers: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyBlocks {
+    pub pubkey: String,
+    pub blocks: u64,
+    pub last_height: Option<u64>,
+}
+
+pub async fn discover(db: &Surreal<Any>) -> Result<Vec<PoolCandidate>, Box<dyn Error>> {
+    debug!("Looking for unknown pools ...");
+    let mut candidates: HashMap<String, PoolCandidate> = HashMap::new();
+    for key in db::getunknownstakers(db).await? {
+        let candidate = candidates.entry(key.pubkey.clone()).or_default();
+        candidate.pubkey = key.pubkey;
+        candidate.stakers = key.stakers;
+    }
+    for key in db::getunknownblocks(db).await? {
+        let candidate = candidates.entry(key.pubkey.clone()).or_default();
+        candidate.pubkey = key.pubkey;
+        candidate.blocks = key.blocks;
+        candidate.last_height = key.last_height;
+    }
+    let known: Vec<String> = db::getpoolcandidates(db)
+        .await?
+        .into_iter()
+        .filter(|candidate| candidate.likely_pool)
+        .map(|candidate| candidate.pubkey)
+        .collect();
+    let mut candidates: Vec<PoolCandidate> = candidates.into_values().collect();
+    for candidate in candidates.iter_mut() {
+        candidate.likely_pool = candidate.stakers >= MIN_POOL_STAKERS;
+        if candidate.likely_pool && !known.contains(&candidate.pubkey) {
+            info!(
+                "Stake key {} looks like a pool: {} stakers, {} blocks. Name it in the pool registry.",
+                candidate.pubkey, candidate.stakers, candidate.blocks
+            );
+        }
+    }
+    candidates.sort_by_key(|c| std::cmp::Reverse((c.stakers, c.blocks)));
+    db::regpoolcandidates(db, &candidates).await?;
+    Ok(candidates)
+}
@@ -1,72 +1,589 @@
-use crate::{console::*, db, rpc::RPCURL};
-use bitcoincore_zmq::{subscribe_single_async, Message, Message::HashBlock};
+use crate::{
+    activity,
+    alerts::Alerts,
+    api::{self, ApiState},
+    config::{self, Config, Retry, Zmq},
+    console::*,
+    db, discovery,
+    feed::{self, Event},
+    import, mempool, metrics,
+    network::Network,
+    pools::Registry,
+    poolstats::{self, Period},
+    retry::Backoff,
+    rpc::{self, RPCURL, RPC_INVALID_PARAMETER},
+    writer::Writer,
+};
+use bitcoincore_zmq::{subscribe_multi_async, Message};
 use clap::ArgMatches;
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
-use std::error::Error;
-use surrealdb::{engine::remote::ws::Client, Surreal};
-
-pub async fn run(args: &ArgMatches) {
-    let ipsplit: Vec<&str> = args
-        .get_one::<String>("Ghostd IP")
-        .unwrap()
-        .split(":")
-        .collect::<Vec<&str>>();
-    if ipsplit.len() != 2 {
-        error!("Ghostd IP parsing error.");
-        std::process::exit(1);
-    }
-    let rpcurl = RPCURL::default().target(
-        ipsplit[0],
-        ipsplit[1].parse::<u16>().unwrap(),
-        "",
-        &args.get_one::<String>("user").unwrap(),
-        &args.get_one::<String>("password").unwrap(),
+use std::{
+    error::Error,
+    path::Path,
+    sync::atomic::{AtomicU8, Ordering},
+    time::Duration,
+};
+use surrealdb::{engine::any::Any, Surreal};
+
+// What the engine is busy with, as reported by the health endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Starting,
+    Syncing,
+    Listening,
+    Serving,
+    Stopped,
+}
+
+static PHASE: AtomicU8 = AtomicU8::new(Phase::Starting as u8);
+
+pub fn phase() -> Phase {
+    match PHASE.load(Ordering::Relaxed) {
+        0 => Phase::Starting,
+        1 => Phase::Syncing,
+        2 => Phase::Listening,
+        3 => Phase::Serving,
+        _ => Phase::Stopped,
+    }
+}
+
+fn enter(phase: Phase) {
+    debug!("Engine phase: {:?}", phase);
+    PHASE.store(phase as u8, Ordering::Relaxed);
+}
+
+// Ghostd is optional to commands that only read the DB.
+pub fn rpcurl(config: &Config) -> Option<RPCURL> {
+    let ip = config.rpc.ip.as_ref()?;
+    let mut rpcurl = RPCURL::default();
+    for ip in std::iter::once(ip).chain(config.rpc.fallback.iter()) {
+        let port = match ip
+            .split_once(":")
+            .map(|(ip, port)| (ip, port.parse::<u16>()))
+        {
+            Some((ip, Ok(port))) => (ip, port),
+            None => (ip.as_str(), config.network.params().rpc_port),
+            _ => {
+                error!("Ghostd IP parsing error: {}", ip);
+                std::process::exit(1);
+            }
+        };
+        rpcurl = rpcurl.target(
+            port.0,
+            port.1,
+            "",
+            config.rpc.user.as_deref().unwrap_or_default(),
+            config.rpc.password.as_deref().unwrap_or_default(),
+        );
+    }
+    if config.rpc.crosscheck && config.rpc.fallback.is_empty() {
+        warn!("rpc.crosscheck has no other node to compare with, set rpc.fallback.");
+    }
+    let rawblocks = config.rpc.rawblocks.then_some(config.network);
+    Some(
+        rpcurl
+            .retry(config.retry)
+            .crosscheck(config.rpc.crosscheck)
+            .rawblocks(rawblocks),
+    )
+}
+
+pub fn needrpcurl(config: &Config) -> RPCURL {
+    config::required(&config.rpc.ip, "rpc.ip");
+    rpcurl(config).unwrap()
+}
+
+pub async fn connect(config: &Config) -> Surreal<Any> {
+    let mut backoff = Backoff::new(config.retry);
+    loop {
+        match db::init(config).await {
+            Ok(db) => return db,
+            Err(e) if db::transient(&e) => match backoff.next() {
+                Some(delay) => {
+                    warn!("{}, retrying in {:?} ...", e, delay);
+                    tokio::time::sleep(delay).await;
+                }
+                None => exit::<()>(Err(e.into())),
+            },
+            Err(e) => exit::<()>(Err(e.into())),
+        }
+    }
+}
+
+fn loadregistry(config: &Config) -> Registry {
+    match Registry::load(config.pools.registry.as_ref(), config.network) {
+        Ok(registry) => registry,
+        Err(e) => {
+            error!("Pool registry parsing error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn apistate(config: &Config, db: &Surreal<Any>, rpcurl: Option<RPCURL>) -> ApiState {
+    ApiState {
+        db: db.clone(),
+        rpcurl,
+        ready_lag: config.api.ready_lag,
+    }
+}
+
+// Refuse to mix blocks of different networks in one DB.
+pub async fn guard(db: &Surreal<Any>, network: Network) -> Result<(), Box<dyn Error>> {
+    match db::getnetwork(db).await? {
+        Some(indexed) if indexed == network => Ok(()),
+        Some(indexed) => Err(format!(
+            "The DB holds {} blocks, refusing to write {} ones into it.",
+            indexed, network
+        )
+        .into()),
+        // Earlier versions only ever indexed mainnet.
+        None if network != Network::Mainnet && db::gettopheight(db).await?.is_some() => {
+            Err(format!(
+                "The DB holds mainnet blocks, refusing to write {} ones into it.",
+                network
+            )
+            .into())
+        }
+        None => db::regnetwork(db, network).await,
+    }
+}
+
+// Blocks read at a time while filling the lookups.
+const BACKFILL_BATCH: u64 = 1000;
+
+// Fill the txid and address lookups of blocks indexed before there were any.
+async fn backfill(db: &Surreal<Any>) -> Result<(), Box<dyn Error>> {
+    if db::getlinked(db).await? {
+        return Ok(());
+    }
+    if let Some(top) = db::gettopheight(db).await? {
+        info!("Filling transaction and address lookups up to {} ...", top);
+        let mut from = 0;
+        while from <= top {
+            let to = top.min(from + BACKFILL_BATCH - 1);
+            db::reglinks(db, &db::getblockrange(db, from, to).await?).await?;
+            from = to + 1;
+        }
+    }
+    db::reglinked(db).await
+}
+
+fn exit<T>(result: Result<T, Box<dyn Error>>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Catch up with ghostd and keep indexing new blocks.
+pub async fn run(config: &Config) {
+    let db = connect(config).await;
+    sync(config, &db).await;
+}
+
+// Everything `run` does once connected, on a DB handle the caller owns.
+pub async fn sync(config: &Config, db: &Surreal<Any>) {
+    let rpcurl = needrpcurl(config);
+    let mut registry = loadregistry(config);
+    exit(guard(db, config.network).await);
+    exit(backfill(db).await);
+    // Subscribe before anything is indexed so no event slips by.
+    let mut alerts = match config.alerts.rules.as_ref().map(|path| Alerts::load(path)) {
+        Some(Ok(alerts)) => Some(alerts),
+        Some(Err(e)) => {
+            error!("Alert rules parsing error: {}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    if let Some(addr) = config.api.listen.clone() {
+        let state = apistate(config, db, Some(rpcurl.clone()));
+        tokio::spawn(async move {
+            if let Err(e) = api::serve(addr, state).await {
+                error!("API: {}", e);
+                std::process::exit(1);
+            }
+        });
+    }
+    enter(Phase::Syncing);
+    exit(registry.sync(db).await);
+    exit(catchup(db, &rpcurl, &mut registry, config.network, config.retry).await);
+    exit(
+        listen(
+            db,
+            &rpcurl,
+            &config.zmq,
+            &mut registry,
+            config.network,
+            config.retry,
+            &mut alerts,
+        )
+        .await,
     );
-    let db = db::init(args).await.unwrap();
-    if let Err(e) = catchup(&db, &rpcurl).await {
-        error!("{}", e);
-        std::process::exit(1);
+}
+
+// Serve the API over whatever is indexed, without touching the chain.
+pub async fn serve(config: &Config) {
+    let addr = config::required(&config.api.listen, "api.listen").clone();
+    let db = connect(config).await;
+    let state = apistate(config, &db, rpcurl(config));
+    enter(Phase::Serving);
+    exit(api::serve(addr, state).await);
+}
+
+pub async fn rollback_to(config: &Config, args: &ArgMatches) {
+    let to = *args.get_one::<u64>("to").unwrap();
+    let db = connect(config).await;
+    exit(guard(&db, config.network).await);
+    // Blocks above the height go, so there must be some.
+    let tip = exit(db::gettip(&db).await);
+    let from = match tip.filter(|tip| to < *tip).and_then(|_| to.checked_add(1)) {
+        Some(from) => from,
+        None => {
+            let tip = tip.map_or("empty".to_string(), |tip| format!("at {}", tip));
+            error!(
+                "Nothing to roll back above {}, the indexed chain is {}.",
+                to, tip
+            );
+            std::process::exit(1);
+        }
+    };
+    let orphaned = exit(truncate(&db, from).await);
+    info!("Rolled back {} blocks above {}.", orphaned.len(), to);
+}
+
+pub async fn reindex(config: &Config, args: &ArgMatches) {
+    let from = *args.get_one::<u64>("from").unwrap();
+    let rpcurl = needrpcurl(config);
+    let db = connect(config).await;
+    let mut registry = loadregistry(config);
+    exit(guard(&db, config.network).await);
+    exit(backfill(&db).await);
+    enter(Phase::Syncing);
+    exit(registry.sync(&db).await);
+    let orphaned = exit(truncate(&db, from).await);
+    info!("Indexing {} blocks from {} again ...", orphaned.len(), from);
+    exit(catchup(&db, &rpcurl, &mut registry, config.network, config.retry).await);
+}
+
+// Load the chain from ghostd's block files, then carry on as `run` does.
+pub async fn import(config: &Config, args: &ArgMatches) {
+    let dir = args.get_one::<String>("blocksdir").unwrap();
+    let db = connect(config).await;
+    let mut registry = loadregistry(config);
+    exit(guard(&db, config.network).await);
+    exit(backfill(&db).await);
+    enter(Phase::Syncing);
+    exit(registry.sync(&db).await);
+    exit(import::load(Path::new(dir), &db, &mut registry, config.network).await);
+    if args.get_flag("no-sync") {
+        exit(analyse(&db, &mut registry).await);
+        return;
+    }
+    sync(config, &db).await;
+}
+
+// Delete blocks from the height up, along with the proposals and
+// statistics they fed.
+async fn truncate(db: &Surreal<Any>, from: u64) -> Result<Vec<String>, Box<dyn Error>> {
+    let time = db::gettime(db, from).await?;
+    let orphaned = db::rollback(db, from).await?;
+    if let Some(time) = time {
+        // Day buckets never start before the week holding them.
+        db::forgetstats(db, Period::Week.bucket(time)).await?;
+    }
+    Ok(orphaned)
+}
+
+// Blocks of the index compared against ghostd at a time.
+const VERIFY_BATCH: u64 = 1000;
+
+// Look for missing heights and broken links in the indexed chain and,
+// given ghostd, blocks it no longer has on its active chain.
+pub async fn verify(config: &Config, args: &ArgMatches) {
+    let rpcurl = rpcurl(config);
+    let db = connect(config).await;
+    let from = args.get_one::<u64>("from").copied().unwrap_or(0);
+    let to = match args.get_one::<u64>("to") {
+        Some(to) => *to,
+        None => match exit(db::gettopheight(&db).await) {
+            Some(top) => top,
+            None => {
+                info!("Nothing is indexed yet.");
+                return;
+            }
+        },
+    };
+    if rpcurl.is_none() {
+        warn!("rpc.ip is not set, only checking the index itself.");
+    }
+    info!("Verifying blocks {} to {} ...", from, to);
+    let mut problems: u64 = 0;
+    let mut expected = from;
+    let mut previous: Option<String> = match from.checked_sub(1) {
+        Some(height) => exit(db::gethash(&db, height).await),
+        None => None,
+    };
+    while expected <= to {
+        let last = to.min(expected + VERIFY_BATCH - 1);
+        for link in exit(db::getchain(&db, expected, last).await) {
+            if link.height != expected {
+                error!("Blocks {} to {} are missing.", expected, link.height - 1);
+                problems += link.height - expected;
+                previous = None;
+            }
+            if previous.is_some() && link.previousblockhash != previous {
+                error!(
+                    "Block {} does not build on the indexed block {}.",
+                    link.height,
+                    link.height - 1
+                );
+                problems += 1;
+            }
+            if let Some(rpcurl) = &rpcurl {
+                let hash = exit(getblockhash(link.height, rpcurl).await);
+                if hash != link.hash {
+                    error!(
+                        "Block {} is {} in the index but {} on ghostd.",
+                        link.height, link.hash, hash
+                    );
+                    problems += 1;
+                }
+            }
+            expected = link.height + 1;
+            previous = Some(link.hash);
+        }
+        if expected <= last {
+            error!("Blocks {} to {} are missing.", expected, last);
+            problems += last + 1 - expected;
+            expected = last + 1;
+            previous = None;
+        }
+        debug!("Verified blocks up to {}.", last);
     }
-    if let Err(e) = listen(&db, &rpcurl).await {
-        error!("{}", e);
-        std::process::exit(1);
+    match problems {
+        0 => info!("Blocks {} to {} are consistent.", from, to),
+        _ => {
+            error!("Found {} problems between {} and {}.", problems, from, to);
+            std::process::exit(1);
+        }
     }
 }
 
 async fn scan(
     blockhash: &String,
-    proposal_ids: &mut Vec<u64>,
-    db: &Surreal<Client>,
+    writer: &mut Writer,
+    db: &Surreal<Any>,
+    rpcurl: &RPCURL,
+    registry: &mut Registry,
+    network: Network,
+) -> Result<u64, Box<dyn Error>> {
+    registry.refresh(db).await?;
+    let blockdata: BlockData = getblock(blockhash, db, rpcurl, registry).await?;
+    let height = blockdata.height;
+    if writer.extends(&blockdata) {
+        index(blockdata, writer, db, rpcurl, network).await?;
+        return Ok(height);
+    }
+    writer.flush(db).await?;
+    if db::gethash(db, height).await?.as_ref() == Some(&blockdata.hash) {
+        trace!("Block {} is indexed already.", height);
+        return Ok(height);
+    }
+    if let Some(fork) = findfork(&blockdata, db, rpcurl).await? {
+        rollback(fork, writer, db).await?;
+        // Bring in whatever lies between the fork and the new block from the node's active chain.
+        for height in fork + 1..height {
+            let blockhash = getblockhash(height, rpcurl).await?;
+            let blockdata = getblock(&blockhash, db, rpcurl, registry).await?;
+            index(blockdata, writer, db, rpcurl, network).await?;
+        }
+    }
+    index(blockdata, writer, db, rpcurl, network).await?;
+    Ok(height)
+}
+
+// Scan a block, retrying while the DB can't be reached or the nodes disagree
+// on it. RPC calls retry on their own.
+async fn rescan(
+    blockhash: &String,
+    writer: &mut Writer,
+    db: &Surreal<Any>,
+    rpcurl: &RPCURL,
+    registry: &mut Registry,
+    network: Network,
+    retry: Retry,
+) -> Result<u64, Box<dyn Error>> {
+    let mut backoff = Backoff::new(retry);
+    loop {
+        match scan(blockhash, writer, db, rpcurl, registry, network).await {
+            Err(e) if db::transient(e.as_ref()) || rpc::is_disagreement(e.as_ref()) => {
+                match backoff.next() {
+                    Some(delay) => {
+                        warn!("{}, retrying in {:?} ...", e, delay);
+                        // Blocks held back shouldn't wait on this one.
+                        if let Err(e) = writer.flush(db).await {
+                            debug!("Writing held back blocks failed: {}", e);
+                        }
+                        tokio::time::sleep(delay).await;
+                    }
+                    None => return Err(e),
+                }
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn index(
+    blockdata: BlockData,
+    writer: &mut Writer,
+    db: &Surreal<Any>,
     rpcurl: &RPCURL,
+    network: Network,
 ) -> Result<(), Box<dyn Error>> {
-    let blockdata: BlockData = getblock(blockhash, db, &rpcurl).await?;
-    if let Ok(Some(proposal)) = getnewproposal(&blockdata, &proposal_ids, &rpcurl).await {
-        db::regproposal(&db, &proposal).await?;
-        *proposal_ids = db::getproposalids(&db).await?;
+    if let Ok(Some(proposal)) =
+        getnewproposal(&blockdata, &writer.proposal_ids, rpcurl, network).await
+    {
+        db::regproposal(db, &proposal).await?;
+        writer.proposal_ids = db::getproposalids(db).await?;
+        feed::publish(Event::Proposal(proposal));
+    }
+    writer.write(db, blockdata).await
+}
+
+// Last height the indexed chain shares with the node's, unless the
+// new block simply extends the indexed chain.
+async fn findfork(
+    blockdata: &BlockData,
+    db: &Surreal<Any>,
+    rpcurl: &RPCURL,
+) -> Result<Option<u64>, Box<dyn Error>> {
+    let parent = match blockdata.height.checked_sub(1) {
+        Some(parent) => parent,
+        None => return Ok(None),
+    };
+    if db::gethash(db, parent).await? == blockdata.previousblockhash
+        && db::gethash(db, blockdata.height).await?.is_none()
+    {
+        return Ok(None);
+    }
+    let top = match db::gettopheight(db).await? {
+        Some(top) => top,
+        None => return Ok(None),
+    };
+    let mut height = top.min(parent);
+    loop {
+        match db::gethash(db, height).await? {
+            Some(indexed) if indexed != getblockhash(height, rpcurl).await? && height > 0 => {
+                height -= 1;
+            }
+            _ => return Ok(Some(height)),
+        }
+    }
+}
+
+async fn rollback(fork: u64, writer: &mut Writer, db: &Surreal<Any>) -> Result<(), Box<dyn Error>> {
+    let orphaned = truncate(db, fork + 1).await?;
+    // Proposals seen only in orphaned blocks are gone with them.
+    writer.proposal_ids = db::getproposalids(db).await?;
+    if !orphaned.is_empty() {
+        warn!(
+            "Chain reorganisation: rolled back {} blocks above {}.",
+            orphaned.len(),
+            fork
+        );
+        let metrics = metrics::get();
+        metrics.reorgs.inc();
+        metrics.reorged_blocks.observe(orphaned.len() as f64);
+        feed::publish(Event::Reorg {
+            fork_height: fork,
+            depth: orphaned.len() as u64,
+            orphaned,
+        });
     }
-    db::regblock(&db, &blockdata).await?;
     Ok(())
 }
 
-async fn catchup(db: &Surreal<Client>, rpcurl: &RPCURL) -> Result<(), Box<dyn Error>> {
-    let nextheight = match db::toprec(&db).await? {
+// Blocks written per transaction while catching up.
+const CATCHUP_BATCH: usize = 500;
+
+async fn catchup(
+    db: &Surreal<Any>,
+    rpcurl: &RPCURL,
+    registry: &mut Registry,
+    network: Network,
+    retry: Retry,
+) -> Result<(), Box<dyn Error>> {
+    let nextheight = match db::toprec(db).await? {
         Some(thing) => thing + 1,
         None => 0,
     };
-    let mut proposal_ids = db::getproposalids(&db).await?;
+    db::backfillvotes(db).await?;
+    backfillproposals(db, rpcurl, network).await?;
+    let mut writer = Writer::new(db, CATCHUP_BATCH).await?;
     for height in nextheight.. {
-        let blockhash_result = getblockhash(height, rpcurl).await;
-        match blockhash_result {
+        match getblockhash(height, rpcurl).await {
             Ok(blockhash) => {
-                scan(&blockhash, &mut proposal_ids, &db, &rpcurl).await?;
+                rescan(
+                    &blockhash,
+                    &mut writer,
+                    db,
+                    rpcurl,
+                    registry,
+                    network,
+                    retry,
+                )
+                .await?;
             }
-            Err(_) => {
+            // Only a height past ghostd's tip means there is nothing left to catch up on.
+            Err(e) if rpc::is_node_error(e.as_ref(), RPC_INVALID_PARAMETER) => {
                 info!("Caught up the blocks. Switching to listen mode ...");
                 break;
             }
+            Err(e) => return Err(e),
         }
     }
+    writer.flush(db).await?;
+    analyse(db, registry).await?;
+    Ok(())
+}
+
+// Tally proposals voted on in blocks indexed without asking ghostd,
+// as an import does.
+async fn backfillproposals(
+    db: &Surreal<Any>,
+    rpcurl: &RPCURL,
+    network: Network,
+) -> Result<(), Box<dyn Error>> {
+    let known = db::getproposalids(db).await?;
+    let voted = db::getvotedproposalids(db, network.params().governance_start).await?;
+    for proposal_id in voted.into_iter().filter(|id| !known.contains(id)) {
+        debug!("Tallying proposal {} ...", proposal_id);
+        let vote = Vote {
+            proposal_id,
+            voted_for_option: 0,
+        };
+        let proposal = vote.gen_proposal(rpcurl, network).await?;
+        db::regproposal(db, &proposal).await?;
+    }
+    Ok(())
+}
+
+// Roughly a day of blocks between two analytics runs while listening.
+const ANALYTICS_INTERVAL: u64 = 720;
+
+// Refresh everything derived from the indexed blocks as a whole.
+async fn analyse(db: &Surreal<Any>, registry: &mut Registry) -> Result<(), Box<dyn Error>> {
+    discovery::discover(db).await?;
+    activity::update(db, registry).await?;
+    poolstats::materialize(db, registry).await?;
     Ok(())
 }
 
@@ -88,39 +605,108 @@ impl ProcessedBlocks {
     }
 }
 
-async fn listen(db: &Surreal<Client>, rpcurl: &RPCURL) -> Result<(), Box<dyn Error>> {
-    let mut proposal_ids = db::getproposalids(&db).await?;
+// How often time based alert rules are looked at.
+const ALERT_TICK: Duration = Duration::from_secs(30);
+// How often the tracked mempool is reconciled with ghostd's.
+const MEMPOOL_SWEEP: Duration = Duration::from_secs(60);
+// How often the registry file is looked at while no blocks arrive.
+const REGISTRY_POLL: Duration = Duration::from_secs(10);
+
+async fn listen(
+    db: &Surreal<Any>,
+    rpcurl: &RPCURL,
+    zmq: &Zmq,
+    registry: &mut Registry,
+    network: Network,
+    retry: Retry,
+    alerts: &mut Option<Alerts>,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::new(db, 1).await?;
     let mut processed_blocks = ProcessedBlocks::default();
-    if let Some(blocks) = db::gettrackedzmq(&db).await? {
+    if let Some(blocks) = db::gettrackedzmq(db).await? {
         processed_blocks = blocks;
     }
-
-    let mut stream = subscribe_single_async("tcp://127.0.0.1:28332")?;
-    while let Some(msg) = stream.next().await {
-        let blockhash = gethash(msg);
-        if !processed_blocks.contains(&blockhash) {
-            scan(&blockhash, &mut proposal_ids, &db, rpcurl).await?;
-            processed_blocks.inject(blockhash);
-            db::regtrackedzmq(&db, &processed_blocks).await?;
-        }
+    if let Some(alerts) = alerts.as_mut() {
+        alerts.skip_backlog();
     }
-    Ok(())
-}
 
-fn gethash<E: Error + Sized>(msg: Result<Message, E>) -> String {
-    match msg {
-        Ok(msg) => match msg {
-            HashBlock(hash, _) => {
-                return hash.to_string();
+    // ghostd may publish both notifications on the same endpoint.
+    let mut endpoints = vec![zmq.address.as_str()];
+    if let Some(hashtx) = zmq
+        .hashtx
+        .as_deref()
+        .filter(|hashtx| *hashtx != zmq.address)
+    {
+        endpoints.push(hashtx);
+    }
+    let mempool = zmq.hashtx.is_some();
+    let tracker = mempool.then(|| mempool::tracker(db, rpcurl));
+    let mut stream = subscribe_multi_async(&endpoints)?;
+    let mut ticker = tokio::time::interval(ALERT_TICK);
+    let mut sweeper = tokio::time::interval(MEMPOOL_SWEEP);
+    let mut reloader = tokio::time::interval(REGISTRY_POLL);
+    enter(Phase::Listening);
+    loop {
+        tokio::select! {
+            msg = stream.next() => {
+                let Some(msg) = msg else {
+                    warn!("ZMQ stream ended.");
+                    break;
+                };
+                metrics::get().zmq_messages.inc();
+                let blockhash = match msg {
+                    Ok(Message::HashBlock(hash, _)) => hash.to_string(),
+                    // The mempool is extra, losing track of it stops nothing.
+                    Ok(Message::HashTx(txid, _)) => {
+                        if let Some(tracker) = tracker.as_ref() {
+                            if tracker.try_send(txid.to_string()).is_err() {
+                                debug!("Mempool queue full, leaving {} to the sweep.", txid);
+                            }
+                        }
+                        continue;
+                    }
+                    Ok(msg) => {
+                        debug!("Ignoring {} notification.", msg.topic_str());
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                if !processed_blocks.contains(&blockhash) {
+                    let height = rescan(
+                        &blockhash,
+                        &mut writer,
+                        db,
+                        rpcurl,
+                        registry,
+                        network,
+                        retry,
+                    )
+                    .await?;
+                    if height % ANALYTICS_INTERVAL == 0 {
+                        analyse(db, registry).await?;
+                    }
+                    processed_blocks.inject(blockhash);
+                    db::regtrackedzmq(db, &processed_blocks).await?;
+                }
+                if let Some(alerts) = alerts.as_mut() {
+                    alerts.evaluate(db).await?;
+                }
             }
-            _ => {
-                error!("Got unexpected value from ZMQ.");
-                std::process::exit(1);
+            _ = ticker.tick() => {
+                if let Some(alerts) = alerts.as_mut() {
+                    alerts.tick(db);
+                }
+            }
+            _ = sweeper.tick(), if mempool => {
+                if let Err(e) = mempool::sweep(db, rpcurl).await {
+                    warn!("Mempool: {}", e);
+                }
+            }
+            _ = reloader.tick() => {
+                registry.refresh(db).await?;
             }
-        },
-        Err(e) => {
-            error!("{}", e);
-            std::process::exit(1);
         }
     }
+    enter(Phase::Stopped);
+    Ok(())
 }
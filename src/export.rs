@@ -0,0 +1,710 @@
+// Dumps of indexed tables for use outside of SurrealDB. Every format
+// flattens stored records into the same fixed columns, so every dump of a
+// table has the same schema whatever its records hold.
+use crate::{
+    config::Config,
+    console::{BlockData, Proposal, Stakeaddress, Vin, Vout},
+    db, engine,
+    pools::Labels,
+};
+use clap::ArgMatches;
+use parquet::{
+    data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use serde_json::{json, Value};
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+use surrealdb::{engine::any::Any, Surreal};
+
+pub const TABLES: [&str; 10] = [
+    "blocks",
+    "coinstakes",
+    "transactions",
+    "outputs",
+    "votes",
+    "proposals",
+    "pools",
+    "stakeaddresses",
+    "pool_stats",
+    "network_stats",
+];
+
+pub const FORMATS: [&str; 3] = ["jsonl", "csv", "parquet"];
+
+// Views of the blocks table rather than tables of their own.
+const DERIVED: [&str; 3] = ["coinstakes", "transactions", "outputs"];
+
+// Tables keyed by height are paged through by record id, the rest are small enough
+// to be read at once.
+const BY_HEIGHT: [&str; 2] = ["blocks", "votes"];
+const BATCH: u64 = 1000;
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Int,
+    Float,
+    Text,
+    Bool,
+}
+
+// A column of a flattened table and where its value sits in the rows
+// records expand to, the top level field of its name by default.
+struct Column {
+    name: &'static str,
+    kind: Kind,
+    pointer: Option<&'static str>,
+}
+
+impl Column {
+    const fn at(self, pointer: &'static str) -> Column {
+        Column {
+            pointer: Some(pointer),
+            ..self
+        }
+    }
+}
+
+const fn int(name: &'static str) -> Column {
+    Column {
+        name,
+        kind: Kind::Int,
+        pointer: None,
+    }
+}
+
+const fn float(name: &'static str) -> Column {
+    Column {
+        name,
+        kind: Kind::Float,
+        pointer: None,
+    }
+}
+
+const fn text(name: &'static str) -> Column {
+    Column {
+        name,
+        kind: Kind::Text,
+        pointer: None,
+    }
+}
+
+const fn flag(name: &'static str) -> Column {
+    Column {
+        name,
+        kind: Kind::Bool,
+        pointer: None,
+    }
+}
+
+const BLOCKS: &[Column] = &[
+    int("height"),
+    text("hash"),
+    int("time"),
+    int("mediantime"),
+    text("previousblockhash"),
+    text("merkleroot"),
+    text("bits"),
+    float("difficulty"),
+    text("chainwork"),
+    int("version"),
+    int("size"),
+    int("strippedsize"),
+    int("weight"),
+    int("n_tx"),
+    float("stakekernelvalue"),
+    text("staker"),
+    float("reward"),
+    text("pool"),
+    text("pool_name"),
+    int("proposal"),
+    int("option"),
+];
+
+const COINSTAKES: &[Column] = &[
+    int("height"),
+    int("time"),
+    text("txid"),
+    text("staker"),
+    text("pool"),
+    text("pool_name"),
+    float("stakekernelvalue"),
+    float("reward"),
+    int("outputs"),
+    float("smsgfeerate"),
+    text("smsgdifficulty"),
+    float("treasury_fund_cfwd"),
+];
+
+const TRANSACTIONS: &[Column] = &[
+    int("height"),
+    int("time"),
+    int("position"),
+    text("txid"),
+    text("hash"),
+    int("version"),
+    int("size"),
+    int("vsize"),
+    int("weight"),
+    int("locktime"),
+    int("inputs"),
+    int("anon_inputs"),
+    int("outputs"),
+    flag("coinstake"),
+];
+
+const OUTPUTS: &[Column] = &[
+    int("height"),
+    text("txid"),
+    int("n"),
+    text("type"),
+    float("value"),
+    int("valuesat"),
+    text("script_type"),
+    text("address"),
+    text("stakeaddress"),
+    text("pubkey"),
+    text("value_commitment"),
+    text("data_hex"),
+    text("vote"),
+];
+
+const VOTES: &[Column] = &[
+    int("height"),
+    int("proposal"),
+    int("option"),
+    text("stakeaddress"),
+    text("pool"),
+];
+
+// One row per option, as tallied.
+const PROPOSALS: &[Column] = &[
+    int("proposal_id"),
+    text("option"),
+    int("votes"),
+    float("share"),
+];
+
+const POOLS: &[Column] = &[
+    text("pubkey"),
+    text("name"),
+    text("url"),
+    flag("active"),
+    float("fee"),
+    int("first_seen"),
+    int("last_seen"),
+    text("status").at("/activity/status"),
+    int("last_height").at("/activity/last_height"),
+    int("last_time").at("/activity/last_time"),
+    int("recent_blocks").at("/activity/recent_blocks"),
+    float("share").at("/activity/share"),
+    float("previous_share").at("/activity/previous_share"),
+    float("trend").at("/activity/trend"),
+];
+
+const STAKEADDRESSES: &[Column] = &[
+    text("stakeaddress").at("/raw"),
+    text("pubkey").at("/pool/pubkey"),
+    text("name").at("/pool/name"),
+    text("url").at("/pool/url"),
+    flag("pool_is_active").at("/pool/pool_is_active"),
+    text("status").at("/pool/status"),
+];
+
+const POOL_STATS: &[Column] = &[
+    text("period"),
+    int("bucket"),
+    text("pubkey"),
+    text("name"),
+    int("blocks"),
+    float("share"),
+    float("rewards"),
+    int("stakers"),
+];
+
+const NETWORK_STATS: &[Column] = &[
+    text("period"),
+    int("bucket"),
+    int("blocks"),
+    int("pool_blocks"),
+    int("solo_blocks"),
+    float("pool_ratio"),
+    float("rewards"),
+];
+
+fn columns(table: &str) -> &'static [Column] {
+    match table {
+        "blocks" => BLOCKS,
+        "coinstakes" => COINSTAKES,
+        "transactions" => TRANSACTIONS,
+        "outputs" => OUTPUTS,
+        "votes" => VOTES,
+        "proposals" => PROPOSALS,
+        "pools" => POOLS,
+        "stakeaddresses" => STAKEADDRESSES,
+        "pool_stats" => POOL_STATS,
+        _ => NETWORK_STATS,
+    }
+}
+
+// Where the exported table comes from.
+fn source(table: &str) -> &str {
+    match DERIVED.contains(&table) {
+        true => "blocks",
+        false => table,
+    }
+}
+
+// Rows a stored record stands for, before its columns are picked.
+fn expand(table: &str, record: Value, labels: &Labels) -> Result<Vec<Value>, Box<dyn Error>> {
+    let rows = match table {
+        "blocks" | "coinstakes" | "transactions" | "outputs" => {
+            let mut blockdata: BlockData = serde_json::from_value(record)?;
+            if let Some(pool) = blockdata.coldstaking.as_mut() {
+                labels.relabel(pool);
+            }
+            match table {
+                "blocks" => vec![block(&blockdata)],
+                "coinstakes" => coinstake(&blockdata).into_iter().collect(),
+                "transactions" => transactions(&blockdata),
+                _ => outputs(&blockdata),
+            }
+        }
+        "proposals" => {
+            let proposal: Proposal = serde_json::from_value(record)?;
+            let mut options: Vec<_> = proposal.stats.into_iter().collect();
+            options.sort_by(|a, b| a.0.cmp(&b.0));
+            options
+                .into_iter()
+                .map(|(option, (votes, share))| {
+                    json!({
+                        "proposal_id": proposal.proposal_id,
+                        "option": option,
+                        "votes": votes,
+                        "share": share,
+                    })
+                })
+                .collect()
+        }
+        "stakeaddresses" => {
+            let mut stakeaddress: Stakeaddress = serde_json::from_value(record)?;
+            labels.relabel(&mut stakeaddress.pool);
+            vec![serde_json::to_value(stakeaddress)?]
+        }
+        _ => vec![record],
+    };
+    Ok(rows)
+}
+
+fn block(blockdata: &BlockData) -> Value {
+    let coinstake = blockdata.tx.first();
+    json!({
+        "height": blockdata.height,
+        "hash": blockdata.hash,
+        "time": blockdata.time,
+        "mediantime": blockdata.mediantime,
+        "previousblockhash": blockdata.previousblockhash,
+        "merkleroot": blockdata.merkleroot,
+        "bits": blockdata.bits,
+        "difficulty": blockdata.difficulty,
+        "chainwork": blockdata.chainwork,
+        "version": blockdata.version,
+        "size": blockdata.size,
+        "strippedsize": blockdata.strippedsize,
+        "weight": blockdata.weight,
+        "n_tx": blockdata.n_tx,
+        "stakekernelvalue": blockdata.stakekernelvalue,
+        "staker": blockdata.staker(),
+        "reward": coinstake.and_then(|tx| tx.reward(blockdata.stakekernelvalue)),
+        "pool": blockdata.coldstaking.as_ref().map(|pool| &pool.pubkey),
+        "pool_name": blockdata.coldstaking.as_ref().and_then(|pool| pool.name.as_ref()),
+        "proposal": blockdata.voting_info.as_ref().map(|vote| vote.proposal_id),
+        "option": blockdata.voting_info.as_ref().map(|vote| vote.voted_for_option),
+    })
+}
+
+fn coinstake(blockdata: &BlockData) -> Option<Value> {
+    let tx = blockdata.tx.first()?;
+    let mut row = json!({
+        "height": blockdata.height,
+        "time": blockdata.time,
+        "txid": tx.txid,
+        "staker": tx.staker(),
+        "pool": blockdata.coldstaking.as_ref().map(|pool| &pool.pubkey),
+        "pool_name": blockdata.coldstaking.as_ref().and_then(|pool| pool.name.as_ref()),
+        "stakekernelvalue": blockdata.stakekernelvalue,
+        "reward": tx.reward(blockdata.stakekernelvalue),
+        "outputs": tx.vout.len(),
+    });
+    if let Some(Vout::Data {
+        smsgfeerate,
+        smsgdifficulty,
+        treasury_fund_cfwd,
+        ..
+    }) = tx.vout.first()
+    {
+        row["smsgfeerate"] = json!(smsgfeerate);
+        row["smsgdifficulty"] = json!(smsgdifficulty);
+        row["treasury_fund_cfwd"] = json!(treasury_fund_cfwd);
+    }
+    Some(row)
+}
+
+fn transactions(blockdata: &BlockData) -> Vec<Value> {
+    blockdata
+        .tx
+        .iter()
+        .enumerate()
+        .map(|(position, tx)| {
+            let anon = tx
+                .vin
+                .iter()
+                .filter(|vin| matches!(vin, Vin::Anon { .. }))
+                .count();
+            json!({
+                "height": blockdata.height,
+                "time": blockdata.time,
+                "position": position,
+                "txid": tx.txid,
+                "hash": tx.hash,
+                "version": tx.version,
+                "size": tx.size,
+                "vsize": tx.vsize,
+                "weight": tx.weight,
+                "locktime": tx.locktime,
+                "inputs": tx.vin.len(),
+                "anon_inputs": anon,
+                "outputs": tx.vout.len(),
+                "coinstake": position == 0,
+            })
+        })
+        .collect()
+}
+
+fn outputs(blockdata: &BlockData) -> Vec<Value> {
+    let mut rows = Vec::new();
+    for tx in &blockdata.tx {
+        for vout in &tx.vout {
+            let mut row = json!({
+                "height": blockdata.height,
+                "txid": tx.txid,
+                "type": vout.kind(),
+            });
+            let first = |addresses: &Option<Vec<String>>| {
+                addresses
+                    .as_ref()
+                    .and_then(|addresses| addresses.first().cloned())
+            };
+            match vout {
+                Vout::Data {
+                    n, data_hex, vote, ..
+                } => {
+                    row["n"] = json!(n);
+                    row["data_hex"] = json!(data_hex);
+                    row["vote"] = json!(vote);
+                }
+                Vout::Standard {
+                    n,
+                    value,
+                    valuesat,
+                    scriptpubkey,
+                } => {
+                    row["n"] = json!(n);
+                    row["value"] = json!(value);
+                    row["valuesat"] = json!(valuesat);
+                    row["script_type"] = json!(scriptpubkey.staking_type);
+                    row["address"] = json!(first(&scriptpubkey.addresses));
+                    row["stakeaddress"] = json!(first(&scriptpubkey.stakeaddresses));
+                }
+                Vout::Blind {
+                    n,
+                    pubkey,
+                    scriptpubkey,
+                    value_commitment,
+                    data_hex,
+                    ..
+                } => {
+                    row["n"] = json!(n);
+                    row["pubkey"] = json!(pubkey);
+                    row["value_commitment"] = json!(value_commitment);
+                    row["data_hex"] = json!(data_hex);
+                    if let Some(scriptpubkey) = scriptpubkey {
+                        row["script_type"] = json!(scriptpubkey.staking_type);
+                        row["address"] = json!(first(&scriptpubkey.addresses));
+                    }
+                }
+                Vout::Anon {
+                    n,
+                    pubkey,
+                    value_commitment,
+                    data_hex,
+                    ..
+                } => {
+                    row["n"] = json!(n);
+                    row["pubkey"] = json!(pubkey);
+                    row["value_commitment"] = json!(value_commitment);
+                    row["data_hex"] = json!(data_hex);
+                }
+            }
+            rows.push(row);
+        }
+    }
+    rows
+}
+
+// The row's cells in column order, each null or of the column's type.
+fn cells(columns: &[Column], row: &Value) -> Vec<Value> {
+    columns
+        .iter()
+        .map(|column| {
+            let value = match column.pointer {
+                Some(pointer) => row.pointer(pointer),
+                None => row.get(column.name),
+            };
+            match (column.kind, value) {
+                (_, None | Some(Value::Null)) => Value::Null,
+                (Kind::Int, Some(value)) => value.as_i64().map_or(Value::Null, Value::from),
+                (Kind::Float, Some(value)) => value.as_f64().map_or(Value::Null, Value::from),
+                (Kind::Bool, Some(value)) => value.as_bool().map_or(Value::Null, Value::from),
+                (Kind::Text, Some(Value::String(text))) => Value::from(text.as_str()),
+                (Kind::Text, Some(value)) => Value::from(value.to_string()),
+            }
+        })
+        .collect()
+}
+
+pub enum Sink {
+    Jsonl(Box<dyn Write>),
+    Csv(csv::Writer<Box<dyn Write>>),
+    Parquet(SerializedFileWriter<File>),
+}
+
+impl Sink {
+    // A sink for the table in the format, to the file or stdout.
+    pub fn open(format: &str, output: Option<&str>, table: &str) -> Result<Sink, Box<dyn Error>> {
+        let out: Box<dyn Write> = match (format, output) {
+            ("parquet", None) => return Err("Parquet is written to a file, pass --output".into()),
+            ("parquet", Some(path)) => {
+                let fields: Vec<String> = columns(table)
+                    .iter()
+                    .map(|column| match column.kind {
+                        Kind::Int => format!("OPTIONAL INT64 {};", column.name),
+                        Kind::Float => format!("OPTIONAL DOUBLE {};", column.name),
+                        Kind::Bool => format!("OPTIONAL BOOLEAN {};", column.name),
+                        Kind::Text => format!("OPTIONAL BYTE_ARRAY {} (UTF8);", column.name),
+                    })
+                    .collect();
+                let schema =
+                    parse_message_type(&format!("message {} {{ {} }}", table, fields.join(" ")))?;
+                let properties = WriterProperties::builder().build();
+                let writer = SerializedFileWriter::new(
+                    File::create(path)?,
+                    Arc::new(schema),
+                    Arc::new(properties),
+                )?;
+                return Ok(Sink::Parquet(writer));
+            }
+            (_, Some(path)) => Box::new(BufWriter::new(File::create(path)?)),
+            (_, None) => Box::new(BufWriter::new(io::stdout().lock())),
+        };
+        match format {
+            "csv" => {
+                let mut writer = csv::Writer::from_writer(out);
+                writer.write_record(columns(table).iter().map(|column| column.name))?;
+                Ok(Sink::Csv(writer))
+            }
+            _ => Ok(Sink::Jsonl(out)),
+        }
+    }
+
+    // Write a batch of stored records, returning how many rows they made.
+    fn write(
+        &mut self,
+        table: &str,
+        records: Vec<Value>,
+        labels: &Labels,
+    ) -> Result<u64, Box<dyn Error>> {
+        let columns = columns(table);
+        let mut rows = Vec::new();
+        for record in records {
+            for row in expand(table, record, labels)? {
+                rows.push(cells(columns, &row));
+            }
+        }
+        match self {
+            Sink::Jsonl(out) => {
+                for row in &rows {
+                    let fields: Vec<String> = columns
+                        .iter()
+                        .zip(row)
+                        .map(|(column, cell)| format!("{}:{}", json!(column.name), cell))
+                        .collect();
+                    writeln!(out, "{{{}}}", fields.join(","))?;
+                }
+            }
+            Sink::Csv(writer) => {
+                for row in &rows {
+                    writer.write_record(row.iter().map(|cell| match cell {
+                        Value::Null => String::new(),
+                        Value::String(text) => text.clone(),
+                        other => other.to_string(),
+                    }))?;
+                }
+            }
+            Sink::Parquet(writer) => {
+                if !rows.is_empty() {
+                    rowgroup(writer, columns, &rows)?;
+                }
+            }
+        }
+        Ok(rows.len() as u64)
+    }
+
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Sink::Jsonl(mut out) => out.flush()?,
+            Sink::Csv(mut writer) => writer.flush()?,
+            Sink::Parquet(writer) => {
+                writer.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// One row group per batch, nulls left out of the values and marked by
+// their definition level.
+fn rowgroup(
+    writer: &mut SerializedFileWriter<File>,
+    columns: &[Column],
+    rows: &[Vec<Value>],
+) -> Result<(), Box<dyn Error>> {
+    let mut group = writer.next_row_group()?;
+    for (index, column) in columns.iter().enumerate() {
+        let mut out = group
+            .next_column()?
+            .ok_or("the schema has fewer columns than the table")?;
+        let cells: Vec<&Value> = rows.iter().map(|row| &row[index]).collect();
+        let levels: Vec<i16> = cells.iter().map(|cell| !cell.is_null() as i16).collect();
+        match column.kind {
+            Kind::Int => {
+                let values: Vec<i64> = cells.iter().filter_map(|cell| cell.as_i64()).collect();
+                out.typed::<Int64Type>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+            Kind::Float => {
+                let values: Vec<f64> = cells.iter().filter_map(|cell| cell.as_f64()).collect();
+                out.typed::<DoubleType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+            Kind::Bool => {
+                let values: Vec<bool> = cells.iter().filter_map(|cell| cell.as_bool()).collect();
+                out.typed::<BoolType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+            Kind::Text => {
+                let values: Vec<ByteArray> = cells
+                    .iter()
+                    .filter_map(|cell| cell.as_str())
+                    .map(ByteArray::from)
+                    .collect();
+                out.typed::<ByteArrayType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+        }
+        out.close()?;
+    }
+    group.close()?;
+    Ok(())
+}
+
+// Format asked for, or told by the output file's extension.
+fn format(args: &ArgMatches) -> &str {
+    if let Some(format) = args.get_one::<String>("format") {
+        return format;
+    }
+    let extension = args
+        .get_one::<String>("output")
+        .and_then(|path| Path::new(path).extension())
+        .and_then(|extension| extension.to_str());
+    match extension {
+        Some("csv") => "csv",
+        Some("parquet") => "parquet",
+        _ => "jsonl",
+    }
+}
+
+pub async fn run(config: &Config, args: &ArgMatches) {
+    let table = args.get_one::<String>("table").unwrap();
+    let db = engine::connect(config).await;
+    let output = args.get_one::<String>("output").map(String::as_str);
+    let mut sink = match Sink::open(format(args), output, table) {
+        Ok(sink) => sink,
+        Err(e) => {
+            error!("Can't write {}: {}", output.unwrap_or("to stdout"), e);
+            std::process::exit(1);
+        }
+    };
+    let from = args.get_one::<u64>("from").copied();
+    let to = args.get_one::<u64>("to").copied();
+    let exported = match export(&db, table, from, to, &mut sink).await {
+        Ok(count) => sink.finish().map(|_| count),
+        Err(e) => Err(e),
+    };
+    match exported {
+        Ok(count) => info!("Exported {} rows of {}.", count, table),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Stream the table into the sink and return how many rows it wrote.
+pub async fn export(
+    db: &Surreal<Any>,
+    table: &str,
+    from: Option<u64>,
+    to: Option<u64>,
+    sink: &mut Sink,
+) -> Result<u64, Box<dyn Error>> {
+    let mut count = 0;
+    let stored = source(table);
+    let labels = Labels::load(db).await?;
+    if !BY_HEIGHT.contains(&stored) {
+        if from.is_some() || to.is_some() {
+            return Err(format!(
+                "{} is not keyed by height, export it without --from and --to",
+                table
+            )
+            .into());
+        }
+        count += sink.write(table, db::getrecords(db, stored).await?, &labels)?;
+        return Ok(count);
+    }
+    let to = match to {
+        Some(to) => to,
+        None => match db::gettip(db).await? {
+            Some(top) => top,
+            None => return Ok(0),
+        },
+    };
+    let mut cursor = from.unwrap_or(0);
+    while cursor <= to {
+        let last = to.min(cursor.saturating_add(BATCH - 1));
+        let records = db::getrecordrange(db, stored, cursor, last).await?;
+        count += sink.write(table, records, &labels)?;
+        debug!("Exported {} up to {}.", table, last);
+        match last.checked_add(1) {
+            Some(next) => cursor = next,
+            None => break,
+        }
+    }
+    Ok(count)
+}
@@ -0,0 +1,36 @@
+// Live events published by the engine once they are committed to the DB.
+use crate::console::{BlockData, Proposal};
+use serde::Serialize;
+use std::sync::OnceLock;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+// Events a slow subscriber may fall behind by before it starts missing them.
+const CAPACITY: usize = 1024;
+
+static FEED: OnceLock<Sender<Event>> = OnceLock::new();
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "lowercase")]
+pub enum Event {
+    Block(Box<BlockData>),
+    // Blocks above the fork height were orphaned and rolled back.
+    Reorg {
+        fork_height: u64,
+        depth: u64,
+        orphaned: Vec<String>,
+    },
+    Proposal(Proposal),
+}
+
+fn sender() -> &'static Sender<Event> {
+    FEED.get_or_init(|| broadcast::channel(CAPACITY).0)
+}
+
+pub fn publish(event: Event) {
+    // Sending only fails when nobody is subscribed, which is fine.
+    let _ = sender().send(event);
+}
+
+pub fn subscribe() -> Receiver<Event> {
+    sender().subscribe()
+}
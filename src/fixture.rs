@@ -0,0 +1,60 @@
+// Captures raw getblock responses for the regression corpus in tests/fixtures.
+use crate::{
+    config::Config,
+    console::{getblockhash, BlockData},
+    engine,
+    rpc::{call, RPCURL},
+};
+use clap::ArgMatches;
+use serde_json::Value;
+use std::{error::Error, fs};
+
+pub async fn record(config: &Config, args: &ArgMatches) {
+    let height = *args.get_one::<u64>("height").unwrap();
+    let raw = args.get_flag("raw");
+    let rpcurl = engine::needrpcurl(config);
+    let block = match fetch(height, raw, &rpcurl).await {
+        Ok(block) => block,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // Kept either way, a block the parser chokes on is the fixture worth having.
+    if !raw {
+        if let Err(e) = serde_json::from_value::<BlockData>(block.clone()) {
+            warn!("Block {} does not deserialize: {}", height, e);
+        }
+    }
+    let text = match &block {
+        Value::String(hex) => Ok(hex.clone()),
+        block => serde_json::to_string_pretty(block),
+    };
+    let text = match text {
+        Ok(text) => text + "\n",
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    match args.get_one::<String>("output") {
+        Some(path) => match fs::write(path, text) {
+            Ok(()) => info!("Recorded block {} to {}.", height, path),
+            Err(e) => {
+                error!("Can't write {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => print!("{}", text),
+    }
+}
+
+// The block exactly as ghostd returns it, before any of the parser's processing,
+// verbose or as the hex of its bytes.
+async fn fetch(height: u64, raw: bool, rpcurl: &RPCURL) -> Result<Value, Box<dyn Error>> {
+    let hash = getblockhash(height, rpcurl).await?;
+    match raw {
+        true => call(&format!("getblock {} 0", hash), rpcurl).await,
+        false => call(&format!("getblock {} 2 true", hash), rpcurl).await,
+    }
+}
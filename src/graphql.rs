@@ -0,0 +1,588 @@
+// GraphQL schema over the indexed data, served next to the REST API.
+use crate::{
+    activity::{PoolActivity, PoolStatus},
+    console::{BlockData, Proposal, Stakeaddress, Transaction, Vin, Vote, VoteChange, Vout},
+    db::{self, PoolRecord},
+    pools::Pool,
+    poolstats::{Period, PoolStats},
+};
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Enum, Json, Object, Result, Schema, SimpleObject,
+};
+use std::error::Error;
+use surrealdb::{engine::any::Any, Surreal};
+
+const DEFAULT_LIMIT: u64 = 50;
+const MAX_LIMIT: u64 = 1000;
+
+pub type GhostSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+pub fn schema(db: Surreal<Any>) -> GhostSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(db)
+        .finish()
+}
+
+// Log the detail and answer as the REST API does.
+fn gql(e: Box<dyn Error>) -> async_graphql::Error {
+    error!("GraphQL: {}", e);
+    async_graphql::Error::new("Internal error")
+}
+
+fn database<'a>(ctx: &Context<'a>) -> Result<&'a Surreal<Any>> {
+    ctx.data::<Surreal<Any>>()
+}
+
+// Pools are served as labelled now, not as stored with blocks and addresses.
+async fn label(ctx: &Context<'_>, pubkey: &str) -> Result<PoolNode> {
+    let record = db::getpool(database(ctx)?, pubkey).await.map_err(gql)?;
+    Ok(match record {
+        Some(record) => PoolNode::from(record),
+        None => PoolNode {
+            pool: Pool::unknown(pubkey),
+            activity: None,
+        },
+    })
+}
+
+fn limit(limit: Option<u64>) -> u64 {
+    limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT)
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn block(
+        &self,
+        ctx: &Context<'_>,
+        height: Option<u64>,
+        hash: Option<String>,
+    ) -> Result<Option<Block>> {
+        let db = database(ctx)?;
+        let block = match (height, hash) {
+            (Some(height), _) => db::getblockbyheight(db, height).await.map_err(gql)?,
+            (None, Some(hash)) => db::getblockbyhash(db, &hash).await.map_err(gql)?,
+            (None, None) => return Err("Either height or hash is required".into()),
+        };
+        Ok(block.map(Block))
+    }
+
+    // Newest blocks first.
+    async fn blocks(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<u64>,
+        offset: Option<u64>,
+    ) -> Result<Vec<Block>> {
+        let blocks = db::getblocks(database(ctx)?, self::limit(limit), offset.unwrap_or(0))
+            .await
+            .map_err(gql)?;
+        Ok(blocks.into_iter().map(Block).collect())
+    }
+
+    async fn tx(&self, ctx: &Context<'_>, txid: String) -> Result<Option<Tx>> {
+        let tx = db::gettx(database(ctx)?, &txid).await.map_err(gql)?;
+        Ok(tx.map(Tx))
+    }
+
+    async fn pool(&self, ctx: &Context<'_>, pubkey: String) -> Result<Option<PoolNode>> {
+        let record = db::getpool(database(ctx)?, &pubkey).await.map_err(gql)?;
+        Ok(record.map(PoolNode::from))
+    }
+
+    async fn pools(&self, ctx: &Context<'_>) -> Result<Vec<PoolNode>> {
+        let records = db::getpools(database(ctx)?).await.map_err(gql)?;
+        Ok(records.into_iter().map(PoolNode::from).collect())
+    }
+
+    async fn stakeaddress(
+        &self,
+        ctx: &Context<'_>,
+        address: String,
+    ) -> Result<Option<StakeaddressNode>> {
+        let stakeaddress = db::getstakeaddress(database(ctx)?, &address)
+            .await
+            .map_err(gql)?;
+        Ok(stakeaddress.map(StakeaddressNode))
+    }
+
+    async fn proposal(&self, ctx: &Context<'_>, id: u64) -> Result<Option<ProposalNode>> {
+        let proposal = db::getproposal(database(ctx)?, id).await.map_err(gql)?;
+        Ok(proposal.map(ProposalNode))
+    }
+}
+
+pub struct Block(BlockData);
+
+#[Object]
+impl Block {
+    async fn height(&self) -> u64 {
+        self.0.height
+    }
+    async fn hash(&self) -> &str {
+        &self.0.hash
+    }
+    async fn previousblockhash(&self) -> Option<&str> {
+        self.0.previousblockhash.as_deref()
+    }
+    async fn time(&self) -> u64 {
+        self.0.time
+    }
+    async fn mediantime(&self) -> u64 {
+        self.0.mediantime
+    }
+    async fn bits(&self) -> &str {
+        &self.0.bits
+    }
+    async fn difficulty(&self) -> f64 {
+        self.0.difficulty
+    }
+    async fn chainwork(&self) -> &str {
+        &self.0.chainwork
+    }
+    async fn size(&self) -> u64 {
+        self.0.size
+    }
+    async fn weight(&self) -> u64 {
+        self.0.weight
+    }
+    #[graphql(name = "nTx")]
+    async fn n_tx(&self) -> u64 {
+        self.0.n_tx
+    }
+    async fn stakekernelvalue(&self) -> Option<f64> {
+        self.0.stakekernelvalue
+    }
+    async fn staker(&self) -> Option<String> {
+        self.0.staker()
+    }
+    async fn coinstake(&self) -> Option<Tx> {
+        self.0.tx.first().cloned().map(Tx)
+    }
+    async fn transactions(&self) -> Vec<Tx> {
+        self.0.tx.iter().cloned().map(Tx).collect()
+    }
+    async fn pool(&self, ctx: &Context<'_>) -> Result<Option<PoolNode>> {
+        match &self.0.coldstaking {
+            Some(pool) => Ok(Some(label(ctx, &pool.pubkey).await?)),
+            None => Ok(None),
+        }
+    }
+    async fn vote(&self) -> Option<VoteNode> {
+        self.0.voting_info.clone().map(VoteNode)
+    }
+}
+
+pub struct Tx(Transaction);
+
+#[Object]
+impl Tx {
+    async fn txid(&self) -> &str {
+        &self.0.txid
+    }
+    async fn hash(&self) -> &str {
+        &self.0.hash
+    }
+    async fn version(&self) -> u64 {
+        self.0.version
+    }
+    async fn size(&self) -> u64 {
+        self.0.size
+    }
+    async fn vsize(&self) -> u64 {
+        self.0.vsize
+    }
+    async fn weight(&self) -> u64 {
+        self.0.weight
+    }
+    async fn locktime(&self) -> u64 {
+        self.0.locktime
+    }
+    async fn vin(&self) -> Vec<VinNode> {
+        self.0.vin.iter().map(VinNode::from).collect()
+    }
+    async fn vout(&self) -> Vec<VoutNode> {
+        self.0.vout.iter().cloned().map(VoutNode).collect()
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "ScriptSig")]
+pub struct ScriptSigNode {
+    asm: String,
+    hex: String,
+}
+
+// Both kinds of input in one type, fields of the other kind left null.
+#[derive(SimpleObject)]
+#[graphql(name = "Vin")]
+pub struct VinNode {
+    #[graphql(name = "type")]
+    vin_type: String,
+    txid: Option<String>,
+    vout: Option<u64>,
+    script_sig: Option<ScriptSigNode>,
+    num_inputs: Option<u64>,
+    ring_size: Option<u64>,
+    txinwitness: Option<Vec<String>>,
+    sequence: Option<u64>,
+}
+
+impl From<&Vin> for VinNode {
+    fn from(vin: &Vin) -> Self {
+        match vin {
+            Vin::Standard {
+                txid,
+                vout,
+                script_sig,
+            } => VinNode {
+                vin_type: "standard".to_string(),
+                txid: Some(txid.clone()),
+                vout: Some(*vout),
+                script_sig: Some(ScriptSigNode {
+                    asm: script_sig.asm.clone(),
+                    hex: script_sig.hex.clone(),
+                }),
+                num_inputs: None,
+                ring_size: None,
+                txinwitness: None,
+                sequence: None,
+            },
+            Vin::Anon {
+                input_type,
+                num_inputs,
+                ring_size,
+                txinwitness,
+                sequence,
+            } => VinNode {
+                vin_type: input_type.clone(),
+                txid: None,
+                vout: None,
+                script_sig: None,
+                num_inputs: Some(*num_inputs),
+                ring_size: Some(*ring_size),
+                txinwitness: Some(txinwitness.clone()),
+                sequence: Some(*sequence),
+            },
+        }
+    }
+}
+
+pub struct VoutNode(Vout);
+
+#[Object(name = "Vout")]
+impl VoutNode {
+    async fn n(&self) -> u64 {
+        match &self.0 {
+            Vout::Data { n, .. }
+            | Vout::Standard { n, .. }
+            | Vout::Blind { n, .. }
+            | Vout::Anon { n, .. } => *n,
+        }
+    }
+    #[graphql(name = "type")]
+    async fn vout_type(&self) -> &str {
+        self.0.kind()
+    }
+    // Only standard outputs carry a plain value.
+    async fn value(&self) -> Option<f64> {
+        match &self.0 {
+            Vout::Standard { value, .. } => Some(*value),
+            _ => None,
+        }
+    }
+    #[graphql(name = "valueSat")]
+    async fn valuesat(&self) -> Option<u64> {
+        match &self.0 {
+            Vout::Standard { valuesat, .. } => Some(*valuesat),
+            _ => None,
+        }
+    }
+    async fn addresses(&self) -> Option<Vec<String>> {
+        match &self.0 {
+            Vout::Standard { scriptpubkey, .. } => scriptpubkey.addresses.clone(),
+            _ => None,
+        }
+    }
+    async fn stakeaddresses(&self) -> Option<Vec<String>> {
+        match &self.0 {
+            Vout::Standard { scriptpubkey, .. } => scriptpubkey.stakeaddresses.clone(),
+            _ => None,
+        }
+    }
+    // The output exactly as ghostd reported it.
+    async fn raw(&self) -> Json<Vout> {
+        Json(self.0.clone())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Enum)]
+#[graphql(name = "Period")]
+pub enum PeriodInput {
+    Day,
+    Week,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Enum)]
+#[graphql(name = "PoolStatus")]
+pub enum StatusOutput {
+    Active,
+    Dormant,
+    Inactive,
+}
+
+impl From<PoolStatus> for StatusOutput {
+    fn from(status: PoolStatus) -> Self {
+        match status {
+            PoolStatus::Active => StatusOutput::Active,
+            PoolStatus::Dormant => StatusOutput::Dormant,
+            PoolStatus::Inactive => StatusOutput::Inactive,
+        }
+    }
+}
+
+impl From<PeriodInput> for Period {
+    fn from(period: PeriodInput) -> Self {
+        match period {
+            PeriodInput::Day => Period::Day,
+            PeriodInput::Week => Period::Week,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "PoolActivity")]
+pub struct PoolActivityNode {
+    status: StatusOutput,
+    last_height: Option<u64>,
+    last_time: Option<u64>,
+    recent_blocks: u64,
+    share: f64,
+    previous_share: f64,
+    trend: f64,
+}
+
+impl From<&PoolActivity> for PoolActivityNode {
+    fn from(activity: &PoolActivity) -> Self {
+        PoolActivityNode {
+            status: activity.status.into(),
+            last_height: activity.last_height,
+            last_time: activity.last_time,
+            recent_blocks: activity.recent_blocks,
+            share: activity.share,
+            previous_share: activity.previous_share,
+            trend: activity.trend,
+        }
+    }
+}
+
+pub struct PoolNode {
+    pool: Pool,
+    activity: Option<PoolActivity>,
+}
+
+impl From<PoolRecord> for PoolNode {
+    fn from(record: PoolRecord) -> Self {
+        PoolNode {
+            pool: record.label(),
+            activity: record.activity,
+        }
+    }
+}
+
+#[Object(name = "Pool")]
+impl PoolNode {
+    async fn pubkey(&self) -> &str {
+        &self.pool.pubkey
+    }
+    async fn name(&self) -> Option<&str> {
+        self.pool.name.as_deref()
+    }
+    async fn url(&self) -> Option<&str> {
+        self.pool.url.as_deref()
+    }
+    async fn active(&self) -> Option<bool> {
+        self.pool.pool_is_active
+    }
+    async fn status(&self) -> Option<StatusOutput> {
+        self.pool.status.map(StatusOutput::from)
+    }
+    // Activity derived from the chain, for registered pools only.
+    async fn activity(&self) -> Option<PoolActivityNode> {
+        self.activity.as_ref().map(PoolActivityNode::from)
+    }
+    // Blocks staked by the pool, newest first.
+    async fn blocks(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<u64>,
+        offset: Option<u64>,
+    ) -> Result<Vec<Block>> {
+        let blocks = db::getblocksbypool(
+            database(ctx)?,
+            &self.pool.pubkey,
+            self::limit(limit),
+            offset.unwrap_or(0),
+        )
+        .await
+        .map_err(gql)?;
+        Ok(blocks.into_iter().map(Block).collect())
+    }
+    async fn stats(
+        &self,
+        ctx: &Context<'_>,
+        period: Option<PeriodInput>,
+        limit: Option<u64>,
+        offset: Option<u64>,
+    ) -> Result<Vec<PoolStatsNode>> {
+        let stats = db::getpoolstats(
+            database(ctx)?,
+            &self.pool.pubkey,
+            period.unwrap_or(PeriodInput::Day).into(),
+            self::limit(limit),
+            offset.unwrap_or(0),
+        )
+        .await
+        .map_err(gql)?;
+        Ok(stats.into_iter().map(PoolStatsNode).collect())
+    }
+}
+
+pub struct PoolStatsNode(PoolStats);
+
+#[Object(name = "PoolStats")]
+impl PoolStatsNode {
+    async fn bucket(&self) -> u64 {
+        self.0.bucket
+    }
+    async fn blocks(&self) -> u64 {
+        self.0.blocks
+    }
+    async fn share(&self) -> f64 {
+        self.0.share
+    }
+    async fn rewards(&self) -> Option<f64> {
+        self.0.rewards
+    }
+    async fn stakers(&self) -> u64 {
+        self.0.stakers
+    }
+}
+
+pub struct StakeaddressNode(Stakeaddress);
+
+#[Object(name = "Stakeaddress")]
+impl StakeaddressNode {
+    async fn raw(&self) -> &str {
+        &self.0.raw
+    }
+    async fn pool(&self, ctx: &Context<'_>) -> Result<PoolNode> {
+        label(ctx, &self.0.pool.pubkey).await
+    }
+}
+
+pub struct VoteNode(Vote);
+
+#[Object(name = "Vote")]
+impl VoteNode {
+    async fn proposal_id(&self) -> u64 {
+        self.0.proposal_id
+    }
+    async fn voted_for_option(&self) -> u64 {
+        self.0.voted_for_option
+    }
+    async fn proposal(&self, ctx: &Context<'_>) -> Result<Option<ProposalNode>> {
+        let proposal = db::getproposal(database(ctx)?, self.0.proposal_id)
+            .await
+            .map_err(gql)?;
+        Ok(proposal.map(ProposalNode))
+    }
+}
+
+pub struct ProposalNode(Proposal);
+
+#[Object(name = "Proposal")]
+impl ProposalNode {
+    async fn proposal_id(&self) -> u64 {
+        self.0.proposal_id
+    }
+    // Tally per option, ordered by option as tallyvotes names them.
+    async fn stats(&self) -> Vec<OptionTally> {
+        let mut tallies: Vec<OptionTally> = self
+            .0
+            .stats
+            .iter()
+            .map(|(option, (votes, share))| OptionTally {
+                option: option.clone(),
+                votes: *votes,
+                share: *share,
+            })
+            .collect();
+        tallies.sort_by(|a, b| a.option.cmp(&b.option));
+        tallies
+    }
+    // How the given pool's blocks voted on this proposal.
+    async fn pool_votes(&self, ctx: &Context<'_>, pubkey: String) -> Result<Vec<PoolVoteNode>> {
+        let votes = db::getpoolvotes(database(ctx)?, &pubkey, self.0.proposal_id)
+            .await
+            .map_err(gql)?;
+        Ok(votes
+            .into_iter()
+            .map(|vote| PoolVoteNode {
+                option: vote.option,
+                blocks: vote.blocks,
+            })
+            .collect())
+    }
+    // Stakers that switched options, with the height of every switch.
+    async fn vote_changes(&self, ctx: &Context<'_>) -> Result<Vec<VoteChangeNode>> {
+        let changes = db::getvotechanges(database(ctx)?, self.0.proposal_id)
+            .await
+            .map_err(gql)?;
+        Ok(changes.into_iter().map(VoteChangeNode::from).collect())
+    }
+}
+
+// Blocks voting for an option and their percentage of the tally.
+#[derive(SimpleObject)]
+pub struct OptionTally {
+    option: String,
+    votes: u64,
+    share: f64,
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "PoolVote")]
+pub struct PoolVoteNode {
+    option: u64,
+    blocks: u64,
+}
+
+#[derive(SimpleObject)]
+pub struct OptionSwitch {
+    height: u64,
+    option: u64,
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "VoteChange")]
+pub struct VoteChangeNode {
+    stakeaddress: String,
+    pool: Option<String>,
+    history: Vec<OptionSwitch>,
+}
+
+impl From<VoteChange> for VoteChangeNode {
+    fn from(change: VoteChange) -> Self {
+        VoteChangeNode {
+            stakeaddress: change.stakeaddress,
+            pool: change.pool,
+            history: change
+                .history
+                .into_iter()
+                .map(|(height, option)| OptionSwitch { height, option })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,355 @@
+// Offline bulk import from ghostd's blocks/blk*.dat files, with the node
+// stopped or from a copy of its data directory. Blocks are decoded here and
+// stakeaddresses resolved without validateaddress, so no node is needed
+// until the RPC path takes over at the tip.
+use crate::{
+    console::BlockData,
+    db,
+    decode::{self, HeaderInfo},
+    network::Network,
+    pools::Registry,
+    writer::Writer,
+};
+use bitcoin_hashes::{sha256d, Hash};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+use surrealdb::{engine::any::Any, Surreal};
+
+const HEADER_SIZE: usize = 112;
+// Blocks whose times make up the median time past.
+const MEDIAN_SPAN: usize = 11;
+const PROGRESS_EVERY: u64 = 10000;
+// Blocks written per transaction.
+const BATCH: usize = 1000;
+
+type Hash256 = [u8; 32];
+
+// Where a block lies in the files, with the header fields ordering needs.
+struct Location {
+    file: usize,
+    offset: u64,
+    len: usize,
+    prev: Hash256,
+    time: u32,
+    bits: u32,
+}
+
+// Every block found in the files, keyed by hash.
+struct BlockFiles {
+    files: Vec<PathBuf>,
+    blocks: HashMap<Hash256, Location>,
+}
+
+impl BlockFiles {
+    fn scan(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("blk") && name.ends_with(".dat"))
+            })
+            .collect();
+        files.sort();
+        if files.is_empty() {
+            return Err(format!("No blk*.dat files in {}", dir.display()).into());
+        }
+        let mut blocks = HashMap::new();
+        // Every record starts with the network's message start, which the
+        // first one tells.
+        let mut magic = None;
+        for (file, path) in files.iter().enumerate() {
+            info!("Scanning {} ...", path.display());
+            let bytes = fs::read(path)?;
+            let mut pos = 0;
+            while pos + 8 <= bytes.len() {
+                let start = &bytes[pos..pos + 4];
+                // Files are preallocated, zeros follow the last record.
+                if start == [0; 4] {
+                    break;
+                }
+                if *magic.get_or_insert_with(|| start.to_vec()) != start {
+                    warn!(
+                        "{}: unexpected bytes at {}, skipping the rest.",
+                        path.display(),
+                        pos
+                    );
+                    break;
+                }
+                let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap()) as usize;
+                let offset = pos + 8;
+                if len < HEADER_SIZE || offset + len > bytes.len() {
+                    warn!("{}: truncated block at {}.", path.display(), pos);
+                    break;
+                }
+                let header = &bytes[offset..offset + HEADER_SIZE];
+                let field = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+                blocks.insert(
+                    sha256d::Hash::hash(header).to_byte_array(),
+                    Location {
+                        file,
+                        offset: offset as u64,
+                        len,
+                        prev: header[4..36].try_into().unwrap(),
+                        time: field(100),
+                        bits: field(104),
+                    },
+                );
+                pos = offset + len;
+            }
+        }
+        info!("Found {} blocks in {} files.", blocks.len(), files.len());
+        Ok(BlockFiles { files, blocks })
+    }
+
+    // The chain with the most work from the genesis block, in order, as
+    // ghostd picks it. Stale blocks and orphans the node kept on disk are
+    // left out. Of tips with equal work the one stored first wins, as the
+    // node keeps to the first it received.
+    fn chain(&self) -> Vec<Hash256> {
+        let mut children: HashMap<Hash256, Vec<Hash256>> = HashMap::new();
+        for (hash, location) in &self.blocks {
+            children.entry(location.prev).or_default().push(*hash);
+        }
+        let mut reached: HashSet<Hash256> = HashSet::new();
+        let mut tip: Option<(Hash256, u128, (usize, u64))> = None;
+        let mut pending: Vec<(Hash256, u128)> = children
+            .get(&[0; 32])
+            .map(|genesis| genesis.iter().map(|hash| (*hash, 0)).collect())
+            .unwrap_or_default();
+        while let Some((hash, chainwork)) = pending.pop() {
+            let location = &self.blocks[&hash];
+            let chainwork = chainwork.saturating_add(work(location.bits));
+            let stored = (location.file, location.offset);
+            reached.insert(hash);
+            if tip.is_none_or(|(_, most, first)| {
+                chainwork > most || (chainwork == most && stored < first)
+            }) {
+                tip = Some((hash, chainwork, stored));
+            }
+            if let Some(next) = children.get(&hash) {
+                pending.extend(next.iter().map(|child| (*child, chainwork)));
+            }
+        }
+        let mut chain = Vec::new();
+        let mut cursor = tip.map(|(hash, _, _)| hash);
+        while let Some(hash) = cursor {
+            chain.push(hash);
+            cursor = self
+                .blocks
+                .get(&hash)
+                .map(|location| location.prev)
+                .filter(|prev| reached.contains(prev));
+        }
+        chain.reverse();
+        chain
+    }
+
+    fn read(
+        &self,
+        hash: &Hash256,
+        file: &mut Option<(usize, File)>,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let location = &self.blocks[hash];
+        if file.as_ref().map(|(index, _)| *index) != Some(location.file) {
+            *file = Some((location.file, File::open(&self.files[location.file])?));
+        }
+        let (_, handle) = file.as_mut().unwrap();
+        handle.seek(SeekFrom::Start(location.offset))?;
+        let mut bytes = vec![0; location.len];
+        handle.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+// Import the files' chain above the indexed tip, returning the new tip.
+pub async fn load(
+    dir: &Path,
+    db: &Surreal<Any>,
+    registry: &mut Registry,
+    network: Network,
+) -> Result<Option<u64>, Box<dyn Error>> {
+    let files = BlockFiles::scan(dir)?;
+    let chain = files.chain();
+    let next = match db::toprec(db).await? {
+        Some(top) => top + 1,
+        None => 0,
+    };
+    if next > 0 {
+        let indexed = db::gethash(db, next - 1).await?;
+        let found = chain.get(next as usize - 1).map(display);
+        if indexed != found {
+            return Err(format!(
+                "The files don't hold the indexed block {}, roll back below the fork first",
+                next - 1
+            )
+            .into());
+        }
+    }
+    if chain.len() as u64 <= next {
+        info!("The files hold nothing above the indexed tip.");
+        return Ok(next.checked_sub(1));
+    }
+    info!(
+        "Importing blocks {} to {} from {} ...",
+        next,
+        chain.len() - 1,
+        dir.display()
+    );
+
+    let mut writer = Writer::new(db, BATCH).await?;
+    let mut times: VecDeque<u32> = VecDeque::with_capacity(MEDIAN_SPAN);
+    let mut chainwork: u128 = 0;
+    let mut file = None;
+    for (height, hash) in chain.iter().enumerate() {
+        let height = height as u64;
+        let location = &files.blocks[hash];
+        chainwork = chainwork.saturating_add(work(location.bits));
+        if times.len() == MEDIAN_SPAN {
+            times.pop_front();
+        }
+        times.push_back(location.time);
+        if height < next {
+            continue;
+        }
+        let mut sorted: Vec<u32> = times.iter().copied().collect();
+        sorted.sort();
+        let info = HeaderInfo {
+            hash: display(hash),
+            height,
+            mediantime: sorted[sorted.len() / 2] as u64,
+            chainwork: format!("{:064x}", chainwork),
+            difficulty: difficulty(location.bits),
+            ..Default::default()
+        };
+        let mut blockdata = decode::block(&files.read(hash, &mut file)?, info, network)?;
+        label(&mut blockdata, db, registry, network).await?;
+        if let Err(e) = blockdata.read_vote() {
+            warn!("Block {}: {}", blockdata.height, e);
+        }
+        writer.write(db, blockdata).await?;
+        if height.is_multiple_of(PROGRESS_EVERY) {
+            info!("Imported block {} of {}.", height, chain.len() - 1);
+        }
+    }
+    writer.flush(db).await?;
+    info!("Imported the files up to block {}.", chain.len() - 1);
+    Ok(Some(chain.len() as u64 - 1))
+}
+
+// Attribute a coldstaked block to its pool, deriving the stake-only pubkey
+// where ghostd would have been asked for it.
+async fn label(
+    blockdata: &mut BlockData,
+    db: &Surreal<Any>,
+    registry: &mut Registry,
+    network: Network,
+) -> Result<(), Box<dyn Error>> {
+    let Some(stakeaddress) = blockdata.stakeaddress()? else {
+        return Ok(());
+    };
+    if let Some(pool) = registry.stakeaddress(db, &stakeaddress).await? {
+        blockdata.coldstaking = Some(pool);
+        return Ok(());
+    }
+    let pubkey = decode::stakeonly(&stakeaddress, network.params()).ok_or_else(|| {
+        format!(
+            "Block {}: can't derive the stake-only key of {}",
+            blockdata.height, stakeaddress
+        )
+    })?;
+    blockdata.coldstaking = Some(registry.remember(db, &stakeaddress, &pubkey).await?);
+    Ok(())
+}
+
+fn display(hash: &Hash256) -> String {
+    hash.iter()
+        .rev()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// Difficulty as ghostd reports it for the compact target.
+fn difficulty(bits: u32) -> f64 {
+    let mut shift = (bits >> 24) & 0xff;
+    let mut difficulty = 0xffff as f64 / (bits & 0x00ff_ffff).max(1) as f64;
+    while shift < 29 {
+        difficulty *= 256.0;
+        shift += 1;
+    }
+    while shift > 29 {
+        difficulty /= 256.0;
+        shift -= 1;
+    }
+    difficulty
+}
+
+// Work a block adds to the chain, 2^256 / (target + 1), as the high and low
+// halves of 256 bit numbers. Ghost's targets keep it well within 128 bits.
+fn work(bits: u32) -> u128 {
+    let mantissa = (bits & 0x007f_ffff) as u128;
+    let exponent = bits >> 24;
+    let target = match exponent {
+        0..=3 => (0, mantissa >> (8 * (3 - exponent))),
+        _ => shl((0, mantissa), 8 * (exponent - 3)),
+    };
+    if target == (0, 0) {
+        return 0;
+    }
+    // ~target / (target + 1) + 1, which fits where 2^256 doesn't.
+    let (high, low) = target;
+    let divisor = match low.checked_add(1) {
+        Some(low) => (high, low),
+        None => match high.checked_add(1) {
+            Some(high) => (high, 0),
+            None => return 1,
+        },
+    };
+    let (quotient_high, quotient_low) = div((!high, !low), divisor);
+    match quotient_high {
+        0 => quotient_low.saturating_add(1),
+        _ => u128::MAX,
+    }
+}
+
+fn shl((high, low): (u128, u128), n: u32) -> (u128, u128) {
+    match n {
+        0 => (high, low),
+        1..=127 => (high << n | low >> (128 - n), low << n),
+        128..=255 => (low << (n - 128), 0),
+        _ => (0, 0),
+    }
+}
+
+// Long division, a bit at a time.
+fn div(numerator: (u128, u128), divisor: (u128, u128)) -> (u128, u128) {
+    let mut quotient = (0, 0);
+    let mut remainder: (u128, u128) = (0, 0);
+    for bit in (0..256).rev() {
+        let carry = remainder.0 >> 127;
+        let next = match bit {
+            128.. => (numerator.0 >> (bit - 128)) & 1,
+            _ => (numerator.1 >> bit) & 1,
+        };
+        remainder = shl(remainder, 1);
+        remainder.1 |= next;
+        quotient = shl(quotient, 1);
+        if carry == 1 || remainder >= divisor {
+            let (low, borrow) = remainder.1.overflowing_sub(divisor.1);
+            remainder = (
+                remainder
+                    .0
+                    .wrapping_sub(divisor.0)
+                    .wrapping_sub(borrow as u128),
+                low,
+            );
+            quotient.1 |= 1;
+        }
+    }
+    quotient
+}
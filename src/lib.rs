@@ -0,0 +1,28 @@
+#[macro_use]
+extern crate log;
+pub const CRATE_NAME: &str = module_path!();
+pub mod activity;
+pub mod alerts;
+pub mod api;
+pub mod args;
+pub mod config;
+pub mod console;
+pub mod db;
+pub mod decode;
+pub mod discovery;
+pub mod engine;
+pub mod export;
+pub mod feed;
+pub mod fixture;
+pub mod graphql;
+pub mod import;
+pub mod logger;
+pub mod mempool;
+pub mod metrics;
+pub mod network;
+pub mod pools;
+pub mod poolstats;
+pub mod query;
+pub mod retry;
+pub mod rpc;
+pub mod writer;
@@ -1,40 +1,135 @@
-use crate::CRATE_NAME;
+use crate::{
+    config::{LogFormat, Logging, Rotation},
+    CRATE_NAME,
+};
 use fern;
 use fern::colors::{Color, ColoredLevelConfig};
+use file_rotate::{compression::Compression, suffix::AppendCount, ContentLimit, FileRotate};
 use humantime::format_rfc3339_seconds as timestamp;
-use std::time::SystemTime;
+use log::{LevelFilter, Record};
+use std::{fmt::Arguments, io::Write, time::SystemTime};
 
-pub fn init() {
+const MEGABYTE: u64 = 1024 * 1024;
+
+pub fn init(config: &Logging, stderr: bool) {
     let colors = ColoredLevelConfig::new()
         .info(Color::Green)
         .debug(Color::Magenta)
         .trace(Color::Blue)
         .warn(Color::Yellow)
         .error(Color::Red);
+    // Problems are only reported once there is somewhere to report them to.
+    let mut problems: Vec<String> = Vec::new();
+
+    let level = match config.level.parse::<LevelFilter>() {
+        Ok(level) => level,
+        Err(_) => {
+            problems.push(format!(
+                "Unknown log level '{}', logging everything.",
+                config.level
+            ));
+            LevelFilter::Trace
+        }
+    };
+    let mut dispatch = fern::Dispatch::new()
+        .level(LevelFilter::Off)
+        .level_for(CRATE_NAME.replace("-", "_"), level);
+    // Comma separated module=level pairs, for example 'ghostd_parser::rpc=info,surrealdb=warn'.
+    for filter in config.filters.split(",").filter(|f| !f.trim().is_empty()) {
+        match filter
+            .split_once("=")
+            .map(|(module, level)| (module.trim(), level.trim().parse::<LevelFilter>()))
+        {
+            Some((module, Ok(level))) => dispatch = dispatch.level_for(module.to_string(), level),
+            _ => problems.push(format!("Ignoring malformed log filter '{}'.", filter)),
+        }
+    }
 
-    fern::Dispatch::new()
-        .format(move |out, message, record| {
-            let mut level = colors.color(record.level()).to_string();
-            let time = timestamp(SystemTime::now())
-                .to_string()
-                .replace("T", " ")
-                .replace("Z", "");
-            if level.len() == 13 {
-                level += " ";
+    let console: fern::Output = match stderr {
+        true => std::io::stderr().into(),
+        false => std::io::stdout().into(),
+    };
+    let format = config.format;
+    dispatch = dispatch.chain(
+        fern::Dispatch::new()
+            .format(move |out, message, record| match format {
+                LogFormat::Text => {
+                    let mut level = colors.color(record.level()).to_string();
+                    if level.len() == 13 {
+                        level += " ";
+                    }
+                    out.finish(format_args!("[ {} {} ] {}", time(), level, message))
+                }
+                LogFormat::Json => out.finish(format_args!("{}", json(message, record))),
+            })
+            .chain(console),
+    );
+    if !config.file.is_empty() {
+        match file(config) {
+            Ok(file) => {
+                dispatch = dispatch.chain(
+                    fern::Dispatch::new()
+                        .format(move |out, message, record| match format {
+                            LogFormat::Text => out.finish(format_args!(
+                                "[ {} {:<5} ] {}",
+                                time(),
+                                record.level(),
+                                message
+                            )),
+                            LogFormat::Json => {
+                                out.finish(format_args!("{}", json(message, record)))
+                            }
+                        })
+                        .chain(file),
+                )
             }
-            out.finish(format_args!(
-                "[ {} {} ] {}",
-                time,
-                level,
-                //record.target(),
-                message
-            ))
-        })
-        .level(log::LevelFilter::Off)
-        .level_for(CRATE_NAME.replace("-", "_"), log::LevelFilter::Trace)
-        .chain(std::io::stdout())
-        .chain(fern::log_file("debug.log").unwrap())
-        .apply()
-        .unwrap();
+            Err(e) => problems.push(format!(
+                "Can't log to {}, logging to the console only: {}",
+                config.file, e
+            )),
+        }
+    }
+    dispatch.apply().unwrap();
+    for problem in problems {
+        warn!("{}", problem);
+    }
     info!("Starting up ...");
 }
+
+fn file(config: &Logging) -> Result<fern::Output, std::io::Error> {
+    // Fail here rather than have the rotating writer panic on it.
+    let file = fern::log_file(&config.file)?;
+    let limit = match config.rotation {
+        Rotation::Never => return Ok(file.into()),
+        Rotation::Daily => ContentLimit::Time(file_rotate::TimeFrequency::Daily),
+        Rotation::Size => {
+            ContentLimit::BytesSurpassed((config.max_size.max(1) * MEGABYTE) as usize)
+        }
+    };
+    let writer: Box<dyn Write + Send> = Box::new(FileRotate::new(
+        &config.file,
+        AppendCount::new(config.keep),
+        limit,
+        Compression::None,
+        #[cfg(unix)]
+        None,
+    ));
+    Ok(writer.into())
+}
+
+fn time() -> String {
+    timestamp(SystemTime::now())
+        .to_string()
+        .replace("T", " ")
+        .replace("Z", "")
+}
+
+// One JSON object per line, as Loki and ELK ingest them.
+fn json(message: &Arguments, record: &Record) -> serde_json::Value {
+    serde_json::json!({
+        "time": timestamp(SystemTime::now()).to_string(),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": message.to_string(),
+    })
+}
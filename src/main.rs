@@ -1,18 +1,70 @@
-#![allow(unused)]
 #[macro_use]
 extern crate log;
-pub const CRATE_NAME: &str = module_path!();
-mod args;
-mod console;
-mod db;
-mod engine;
-mod logger;
-mod pools;
-mod rpc;
 
-#[tokio::main]
-async fn main() {
+use clap::ArgMatches;
+use ghostd_parser::{args, config::Config, engine, export, fixture, logger, query};
+
+fn main() {
     let args = args::args();
-    logger::init();
-    engine::run(&args).await;
+    // Global flags are collected by the innermost command given.
+    let mut command = (None, &args);
+    while let Some((name, sub)) = command.1.subcommand() {
+        command = (Some(name), sub);
+    }
+    let config = Config::load(command.1);
+    // Keep stdout clean for commands that print their results there.
+    let stderr = matches!(
+        command.0,
+        Some("query") | Some("export") | Some("print") | Some("record-fixture")
+    );
+    let logging = match &config {
+        Ok(config) => config.logging.clone(),
+        Err(_) => Default::default(),
+    };
+    logger::init(&logging, stderr);
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Config error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(threads) = config.workers.threads {
+        runtime.worker_threads(threads);
+    }
+    if let Some(threads) = config.workers.blocking {
+        runtime.max_blocking_threads(threads);
+    }
+    let runtime = match runtime.build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    runtime.block_on(run(&args, &config));
+}
+
+async fn run(args: &ArgMatches, config: &Config) {
+    match args.subcommand() {
+        Some(("verify", args)) => engine::verify(config, args).await,
+        Some(("rollback", args)) => engine::rollback_to(config, args).await,
+        Some(("reindex", args)) => engine::reindex(config, args).await,
+        Some(("query", args)) => query::run(config, args).await,
+        Some(("export", args)) => export::run(config, args).await,
+        Some(("serve", _)) => engine::serve(config).await,
+        Some(("import", args)) => engine::import(config, args).await,
+        Some(("record-fixture", args)) => fixture::record(config, args).await,
+        Some(("config", _)) => match config.print() {
+            Ok(text) => print!("{}", text),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        _ => engine::run(config).await,
+    }
 }
@@ -0,0 +1,248 @@
+// Unconfirmed transactions, from the moment ghostd announces them until a
+// block confirms them or they drop out of the mempool.
+use crate::{
+    console::{getblockcount, BlockData},
+    db, metrics,
+    rpc::{self, call, RPCURL, RPC_INVALID_ADDRESS_OR_KEY},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error};
+use surrealdb::{engine::any::Any, Surreal};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+// Confirmation latencies are summarised over this much chain time.
+const LATENCY_WINDOW: u64 = 86400;
+const SATS: f64 = 100_000_000.0;
+// Announced transactions waiting to be tracked. Past this many they are
+// dropped, for the next sweep to pick up.
+const QUEUE: usize = 10_000;
+// Transactions tracked before the gauges are updated.
+const BATCH: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MempoolTx {
+    pub txid: String,
+    // Unix time ghostd first saw the transaction.
+    pub first_seen: u64,
+    pub vsize: u64,
+    pub fee: f64,
+    // Satoshis per virtual byte.
+    pub feerate: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Confirmation {
+    pub txid: String,
+    pub height: u64,
+    pub first_seen: u64,
+    // Time of the confirming block.
+    pub confirmed: u64,
+    pub latency: u64,
+}
+
+// A `getmempoolentry` answer, also the values of `getrawmempool true`.
+#[derive(Debug, Deserialize)]
+struct Entry {
+    vsize: u64,
+    time: u64,
+    fee: Option<f64>,
+    fees: Option<Fees>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Fees {
+    base: f64,
+}
+
+impl Entry {
+    fn record(self, txid: &str) -> MempoolTx {
+        // Newer nodes only report the fee under `fees`.
+        let fee = self
+            .fees
+            .map(|fees| fees.base)
+            .or(self.fee)
+            .unwrap_or_default();
+        MempoolTx {
+            txid: txid.to_string(),
+            first_seen: self.time,
+            vsize: self.vsize,
+            fee,
+            feerate: fee * SATS / self.vsize.max(1) as f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Spread {
+    pub min: f64,
+    pub median: f64,
+    pub p90: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl Spread {
+    fn of(mut values: Vec<f64>) -> Option<Spread> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(|a, b| a.total_cmp(b));
+        let rank = |q: f64| values[((values.len() - 1) as f64 * q).round() as usize];
+        Some(Spread {
+            min: values[0],
+            median: rank(0.5),
+            p90: rank(0.9),
+            max: values[values.len() - 1],
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MempoolStats {
+    pub size: u64,
+    pub vbytes: u64,
+    pub fees: f64,
+    // Satoshis per virtual byte of the transactions waiting.
+    pub feerate: Option<Spread>,
+    // Transactions confirmed over the last day of blocks.
+    pub confirmed: u64,
+    // Seconds from first seen to the confirming block, over the same day.
+    pub latency: Option<Spread>,
+}
+
+// Track announced transactions on a task of their own, so a slow node
+// holds up neither the blocks nor the ZMQ stream.
+pub fn tracker(db: &Surreal<Any>, rpcurl: &RPCURL) -> Sender<String> {
+    let (sender, receiver) = mpsc::channel(QUEUE);
+    tokio::spawn(run(receiver, db.clone(), rpcurl.clone()));
+    sender
+}
+
+async fn run(mut receiver: Receiver<String>, db: Surreal<Any>, rpcurl: RPCURL) {
+    while let Some(txid) = receiver.recv().await {
+        let mut txids = vec![txid];
+        while txids.len() < BATCH {
+            match receiver.try_recv() {
+                Ok(txid) => txids.push(txid),
+                Err(_) => break,
+            }
+        }
+        // The mempool is extra, losing track of it stops nothing.
+        for txid in txids.iter() {
+            if let Err(e) = track(txid, &db, &rpcurl).await {
+                warn!("Mempool: {}", e);
+            }
+        }
+        if let Err(e) = gauge(&db).await {
+            warn!("Mempool: {}", e);
+        }
+    }
+}
+
+// Record a transaction ghostd announced, unless it already left the mempool.
+// Asked once, as the next sweep catches what a failed call missed.
+async fn track(txid: &str, db: &Surreal<Any>, rpcurl: &RPCURL) -> Result<(), Box<dyn Error>> {
+    let arg = format!("getmempoolentry {}", txid);
+    let entry: Entry = match rpc::once(&arg, rpcurl).await {
+        Ok(value) => serde_json::from_value(value)?,
+        Err(e) if rpc::is_node_error(e.as_ref(), RPC_INVALID_ADDRESS_OR_KEY) => {
+            trace!("{} is no longer in the mempool.", txid);
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    db::regmempooltx(db, &entry.record(txid)).await?;
+    Ok(())
+}
+
+// Drop the block's transactions from the mempool, recording how long
+// each of them waited.
+pub async fn confirm(db: &Surreal<Any>, blockdata: &BlockData) -> Result<(), Box<dyn Error>> {
+    let txids: Vec<&str> = blockdata.tx.iter().map(|tx| tx.txid.as_str()).collect();
+    let confirmed = db::forgetmempooltxs(db, &txids).await?;
+    if confirmed.is_empty() {
+        return Ok(());
+    }
+    debug!(
+        "Block {} confirmed {} tracked transactions.",
+        blockdata.height,
+        confirmed.len()
+    );
+    let metrics = metrics::get();
+    for tx in confirmed {
+        let latency = blockdata.time.saturating_sub(tx.first_seen);
+        metrics.confirmation_latency.observe(latency as f64);
+        let confirmation = Confirmation {
+            txid: tx.txid,
+            height: blockdata.height,
+            first_seen: tx.first_seen,
+            confirmed: blockdata.time,
+            latency,
+        };
+        db::regconfirmation(db, &confirmation).await?;
+    }
+    gauge(db).await
+}
+
+// Bring the table in line with ghostd's mempool: pick up transactions
+// announced while nobody listened and drop the ones evicted.
+pub async fn sweep(db: &Surreal<Any>, rpcurl: &RPCURL) -> Result<(), Box<dyn Error>> {
+    trace!("Sweeping the mempool ...");
+    let node_height = getblockcount(rpcurl).await?;
+    let pool: HashMap<String, Entry> =
+        serde_json::from_value(call("getrawmempool true", rpcurl).await?)?;
+    let tracked = db::getmempoolids(db).await?;
+    // Transactions of a block not indexed yet would look evicted.
+    if db::gettip(db).await? >= Some(node_height) {
+        let evicted: Vec<&str> = tracked
+            .iter()
+            .filter(|txid| !pool.contains_key(*txid))
+            .map(|txid| txid.as_str())
+            .collect();
+        if !evicted.is_empty() {
+            debug!(
+                "{} transactions left the mempool unconfirmed.",
+                evicted.len()
+            );
+            let evicted = db::forgetmempooltxs(db, &evicted).await?;
+            metrics::get().mempool_evicted.inc_by(evicted.len() as u64);
+        }
+    }
+    for (txid, entry) in pool {
+        if !tracked.contains(&txid) {
+            db::regmempooltx(db, &entry.record(&txid)).await?;
+        }
+    }
+    gauge(db).await
+}
+
+pub async fn stats(db: &Surreal<Any>) -> Result<MempoolStats, Box<dyn Error>> {
+    let txs = db::getmempool(db).await?;
+    let since = db::gettoptime(db)
+        .await?
+        .unwrap_or_default()
+        .saturating_sub(LATENCY_WINDOW);
+    let latencies = db::getlatencies(db, since).await?;
+    Ok(MempoolStats {
+        size: txs.len() as u64,
+        vbytes: txs.iter().map(|tx| tx.vsize).sum(),
+        fees: txs.iter().map(|tx| tx.fee).sum(),
+        feerate: Spread::of(txs.iter().map(|tx| tx.feerate).collect()),
+        confirmed: latencies.len() as u64,
+        latency: Spread::of(
+            latencies
+                .into_iter()
+                .map(|latency| latency as f64)
+                .collect(),
+        ),
+    })
+}
+
+async fn gauge(db: &Surreal<Any>) -> Result<(), Box<dyn Error>> {
+    let (size, vbytes) = db::getmempoolsize(db).await?;
+    let metrics = metrics::get();
+    metrics.mempool_size.set(size as i64);
+    metrics.mempool_vbytes.set(vbytes as i64);
+    Ok(())
+}
@@ -0,0 +1,160 @@
+// Prometheus metrics, recorded across the parser and served at /metrics.
+use prometheus::{
+    core::Collector, histogram_opts, opts, Encoder, Histogram, HistogramTimer, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Registry, TextEncoder,
+};
+use std::{error::Error, sync::OnceLock};
+
+const NAMESPACE: &str = "ghostd_parser";
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub struct Metrics {
+    registry: Registry,
+    pub indexed_height: IntGauge,
+    pub node_height: IntGauge,
+    pub lag: IntGauge,
+    pub blocks_indexed: IntCounter,
+    pub rpc_calls: IntCounterVec,
+    pub rpc_errors: IntCounterVec,
+    pub rpc_seconds: HistogramVec,
+    pub rpc_node_up: IntGaugeVec,
+    pub node_disagreements: IntCounter,
+    pub db_write_seconds: HistogramVec,
+    pub zmq_messages: IntCounter,
+    pub reorgs: IntCounter,
+    pub reorged_blocks: Histogram,
+    pub pool_blocks: IntCounterVec,
+    pub mempool_size: IntGauge,
+    pub mempool_vbytes: IntGauge,
+    pub mempool_evicted: IntCounter,
+    pub confirmation_latency: Histogram,
+}
+
+impl Metrics {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        let registry = Registry::new_custom(Some(NAMESPACE.to_string()), None)?;
+        let metrics = Metrics {
+            indexed_height: IntGauge::new("indexed_height", "Height of the last indexed block")?,
+            node_height: IntGauge::new("node_height", "Block count reported by ghostd")?,
+            lag: IntGauge::new("lag_blocks", "Blocks ghostd is ahead of the index")?,
+            blocks_indexed: IntCounter::new(
+                "blocks_indexed_total",
+                "Blocks written to the DB, rate() of it is blocks per second",
+            )?,
+            rpc_calls: IntCounterVec::new(
+                opts!("rpc_calls_total", "RPC calls made to ghostd"),
+                &["method"],
+            )?,
+            rpc_errors: IntCounterVec::new(
+                opts!("rpc_errors_total", "RPC calls to ghostd that failed"),
+                &["method"],
+            )?,
+            rpc_seconds: HistogramVec::new(
+                histogram_opts!("rpc_duration_seconds", "RPC call latency"),
+                &["method"],
+            )?,
+            rpc_node_up: IntGaugeVec::new(
+                opts!(
+                    "rpc_node_up",
+                    "Whether a ghostd node answered its last call"
+                ),
+                &["node"],
+            )?,
+            node_disagreements: IntCounter::new(
+                "node_disagreements_total",
+                "Blocks ghostd nodes held different hashes for",
+            )?,
+            db_write_seconds: HistogramVec::new(
+                histogram_opts!("db_write_duration_seconds", "DB write latency"),
+                &["query"],
+            )?,
+            zmq_messages: IntCounter::new(
+                "zmq_messages_total",
+                "Block and transaction notifications received over ZMQ",
+            )?,
+            reorgs: IntCounter::new("reorgs_total", "Chain reorganisations rolled back")?,
+            reorged_blocks: Histogram::with_opts(histogram_opts!(
+                "reorg_depth_blocks",
+                "Blocks rolled back per reorganisation",
+                vec![1.0, 2.0, 3.0, 5.0, 10.0, 20.0, 50.0, 100.0]
+            ))?,
+            pool_blocks: IntCounterVec::new(
+                opts!("pool_blocks_total", "Blocks indexed per staking pool"),
+                &["pool", "name"],
+            )?,
+            mempool_size: IntGauge::new("mempool_size", "Unconfirmed transactions tracked")?,
+            mempool_vbytes: IntGauge::new(
+                "mempool_vbytes",
+                "Virtual size of the unconfirmed transactions tracked",
+            )?,
+            mempool_evicted: IntCounter::new(
+                "mempool_evicted_total",
+                "Transactions that left the mempool without confirming",
+            )?,
+            confirmation_latency: Histogram::with_opts(histogram_opts!(
+                "confirmation_latency_seconds",
+                "Time from a transaction first seen to the block confirming it",
+                vec![30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0, 7200.0, 21600.0, 86400.0]
+            ))?,
+            registry,
+        };
+        let collectors: Vec<Box<dyn Collector>> = vec![
+            Box::new(metrics.indexed_height.clone()),
+            Box::new(metrics.node_height.clone()),
+            Box::new(metrics.lag.clone()),
+            Box::new(metrics.blocks_indexed.clone()),
+            Box::new(metrics.rpc_calls.clone()),
+            Box::new(metrics.rpc_errors.clone()),
+            Box::new(metrics.rpc_seconds.clone()),
+            Box::new(metrics.rpc_node_up.clone()),
+            Box::new(metrics.node_disagreements.clone()),
+            Box::new(metrics.db_write_seconds.clone()),
+            Box::new(metrics.zmq_messages.clone()),
+            Box::new(metrics.reorgs.clone()),
+            Box::new(metrics.reorged_blocks.clone()),
+            Box::new(metrics.pool_blocks.clone()),
+            Box::new(metrics.mempool_size.clone()),
+            Box::new(metrics.mempool_vbytes.clone()),
+            Box::new(metrics.mempool_evicted.clone()),
+            Box::new(metrics.confirmation_latency.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector)?;
+        }
+        Ok(metrics)
+    }
+}
+
+pub fn get() -> &'static Metrics {
+    // Only a duplicate or malformed metric fails here, which is a bug.
+    METRICS.get_or_init(|| Metrics::new().expect("Metrics definitions are valid."))
+}
+
+// Observes the elapsed time once dropped.
+pub fn db_write(query: &str) -> HistogramTimer {
+    get()
+        .db_write_seconds
+        .with_label_values(&[query])
+        .start_timer()
+}
+
+pub fn heights(indexed: Option<u64>, node: Option<u64>) {
+    let metrics = get();
+    if let Some(indexed) = indexed {
+        metrics.indexed_height.set(indexed as i64);
+    }
+    if let Some(node) = node {
+        metrics.node_height.set(node as i64);
+    }
+    metrics
+        .lag
+        .set((metrics.node_height.get() - metrics.indexed_height.get()).max(0));
+}
+
+// Text exposition format, as Prometheus scrapes it.
+pub fn render() -> Result<String, Box<dyn Error>> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&get().registry.gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
@@ -0,0 +1,91 @@
+// Chain parameters that differ between mainnet, testnet and regtest.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+pub struct Params {
+    pub rpc_port: u16,
+    // Votes before this height are not tallied by ghostd.
+    pub governance_start: u64,
+    // Registry shipped with the binary, used when no file is given.
+    pub registry: &'static str,
+    // Human readable part of bech32 stake-only addresses.
+    pub stake_hrp: &'static str,
+    // Base58 prefixes of key and script hash addresses, 160 and 256 bit.
+    pub pubkey_address: u8,
+    pub script_address: u8,
+    pub pubkey_address_256: u8,
+    pub script_address_256: u8,
+}
+
+const MAINNET: Params = Params {
+    rpc_port: 51725,
+    governance_start: 710800,
+    registry: include_str!("../configs/pools.toml"),
+    stake_hrp: "gcs",
+    pubkey_address: 38,
+    script_address: 97,
+    pubkey_address_256: 57,
+    script_address_256: 61,
+};
+
+// No pools are known on the test chains.
+const TESTNET: Params = Params {
+    rpc_port: 51925,
+    governance_start: 0,
+    registry: "",
+    stake_hrp: "tgcs",
+    pubkey_address: 118,
+    script_address: 122,
+    pubkey_address_256: 119,
+    script_address_256: 123,
+};
+
+const REGTEST: Params = Params {
+    rpc_port: 51926,
+    governance_start: 0,
+    registry: "",
+    stake_hrp: "rtgcs",
+    pubkey_address: 118,
+    script_address: 122,
+    pubkey_address_256: 119,
+    script_address_256: 123,
+};
+
+impl Network {
+    pub fn params(self) -> &'static Params {
+        match self {
+            Network::Mainnet => &MAINNET,
+            Network::Testnet => &TESTNET,
+            Network::Regtest => &REGTEST,
+        }
+    }
+}
+
+impl Params {
+    // Whether a stake-only pubkey, as validateaddress reports it, belongs to this network.
+    pub fn is_stake_key(&self, pubkey: &str) -> bool {
+        pubkey
+            .strip_prefix(self.stake_hrp)
+            .is_some_and(|rest| rest.starts_with('1'))
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Regtest => "regtest",
+        };
+        write!(f, "{}", name)
+    }
+}
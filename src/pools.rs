@@ -1,103 +1,279 @@
+use crate::{activity::PoolStatus, console::Stakeaddress, db, network::Network};
 use serde::{Deserialize, Serialize};
-// Deserialisation requires lifetime 'de to outlive 'static.
-// This is why private is converted to public which is deserializable.
-#[derive(Debug, Copy, Clone)]
-pub struct PrivatePool {
-    pub pubkey: &'static str,
-    pub url: &'static str,
-    pub is_active: bool,
+use std::{collections::HashMap, error::Error, fs, path::PathBuf, time::SystemTime};
+use surrealdb::{engine::any::Any, Surreal};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PoolEntry {
+    pub pubkey: String,
+    pub name: Option<String>,
+    pub url: Option<String>,
+    pub active: bool,
+    pub fee: Option<f64>,
+    pub first_seen: Option<u64>,
+    pub last_seen: Option<u64>,
 }
 
-impl PrivatePool {
-    pub fn getpool(self) -> Pool {
+impl PoolEntry {
+    pub fn getpool(&self) -> Pool {
         Pool {
-            pubkey: self.pubkey.to_string(),
-            url: Some(self.url.to_string()),
-            pool_is_active: Some(self.is_active),
+            pubkey: self.pubkey.clone(),
+            name: self.name.clone(),
+            url: self.url.clone(),
+            pool_is_active: Some(self.active),
+            status: None,
         }
     }
+
+    // Label with the status derived from the chain, once there is one.
+    pub fn label(&self, status: Option<PoolStatus>) -> Pool {
+        let mut pool = self.getpool();
+        if let Some(status) = status {
+            pool.pool_is_active = Some(status == PoolStatus::Active);
+            pool.status = Some(status);
+        }
+        pool
+    }
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Pool {
     pub pubkey: String,
+    pub name: Option<String>,
     pub url: Option<String>,
     pub pool_is_active: Option<bool>,
+    // Activity derived from the chain, once it has been.
+    pub status: Option<PoolStatus>,
+}
+
+impl Pool {
+    // Label for a stake-only pubkey missing from the registry.
+    pub fn unknown(pubkey: &str) -> Pool {
+        Pool {
+            pubkey: pubkey.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+// Labels of the pools table as it is now. Blocks keep the label their pool
+// had when they were indexed, so what is served is relabelled with these.
+#[derive(Debug, Clone, Default)]
+pub struct Labels(HashMap<String, Pool>);
+
+impl Labels {
+    pub async fn load(db: &Surreal<Any>) -> Result<Self, Box<dyn Error>> {
+        let records = db::getpools(db).await?;
+        Ok(Labels(
+            records
+                .iter()
+                .map(|record| (record.entry.pubkey.clone(), record.label()))
+                .collect(),
+        ))
+    }
+
+    pub fn get(&self, pubkey: &str) -> Pool {
+        self.0
+            .get(pubkey)
+            .cloned()
+            .unwrap_or_else(|| Pool::unknown(pubkey))
+    }
+
+    pub fn relabel(&self, pool: &mut Pool) {
+        *pool = self.get(&pool.pubkey);
+    }
 }
 
-const MYGHOST: PrivatePool = PrivatePool {
-    pubkey: "gcs179wukwy95kewa6pex7f47w3xuzn3nywqdng394",
-    url: "https://myghost.org/",
-    is_active: true,
-};
-const MEGA: PrivatePool = PrivatePool {
-    pubkey: "gcs1wzjvh9cmdf5h9atk785tvmu68729534rzu34p6",
-    url: "https://mega.myghost.org/",
-    is_active: true,
-};
-const GHOSTCSP: PrivatePool = PrivatePool {
-    pubkey: "gcs1gvdfylxy4597rq5qutqelr9m37ewtsvzkza8j2",
-    url: "https://ghostcsp.ddns.net/",
-    is_active: true,
-};
-const SUPERGHOSTPOS: PrivatePool = PrivatePool {
-    pubkey: "gcs1zn850aeltu0d85fruw4wf5yt2e4nj990802p2r",
-    url: "https://superghostpos.ru/",
-    is_active: true,
-};
-const POOLGHOSTRUS: PrivatePool = PrivatePool {
-    pubkey: "gcs169zkwmr9zt8mz2epql8wnly3dyf4hkavcprrm2",
-    url: "https://пул.гост.рус",
-    is_active: true,
-};
-
-const GHOSTAKE: PrivatePool = PrivatePool {
-    pubkey: "gcs1al0tw5g8danpluh2rsqxkd5cj4sc08wc2tsvjt",
-    url: "https://ghostake.com",
-    is_active: false,
-};
-const COLDSTAKE_IO: PrivatePool = PrivatePool {
-    pubkey: "gcs12ezltnndc6f6ds4zcwdy82d6mv94xx2anch950",
-    url: "https://ghost.coldstake.io/",
-    is_active: false,
-};
-const DARKPAY: PrivatePool = PrivatePool {
-    pubkey: "gcs1wmr49e2fd8p09dll8djdfsek6m5zuxhjdq3c4c",
-    url: "https://ghost-pool.darkpay.market/",
-    is_active: false,
-};
-const GHOST_STAKE: PrivatePool = PrivatePool {
-    pubkey: "gcs1cxgy3uzz05djx5j0g40trxkweq7jwllkzzpkf5",
-    url: "https://ghoststake.com/",
-    is_active: false,
-};
-const NOVASTERA_EU: PrivatePool = PrivatePool {
-    pubkey: "gcs1gujv3jjfec75pqkezjckskxw9cfvvdmjkw5g6x",
-    url: "https://ghost.novastera.eu/",
-    is_active: false,
-};
-const GHOST_CHEAP: PrivatePool = PrivatePool {
-    pubkey: "gcs10hnqtq2uvm538n8xck5e7phyqgast5v2m5wv7f",
-    url: "https://ghost.cheap/",
-    is_active: false,
-};
-const GHOST_POOL_DOT_NET: PrivatePool = PrivatePool {
-    pubkey: "gcs154p3rm80dg3arc5jak3293mkgk45uhfggej3da",
-    url: "https://ghost-pool.net/",
-    is_active: false,
-};
-
-pub const POOLS: [PrivatePool; 12] = [
-    MYGHOST,
-    MEGA,
-    GHOSTCSP,
-    SUPERGHOSTPOS,
-    POOLGHOSTRUS,
-    GHOSTAKE,
-    COLDSTAKE_IO,
-    DARKPAY,
-    GHOST_STAKE,
-    NOVASTERA_EU,
-    GHOST_CHEAP,
-    GHOST_POOL_DOT_NET,
-];
+#[derive(Debug, Deserialize)]
+struct RegistryFile {
+    #[serde(rename = "pool", alias = "pools", default)]
+    pools: Vec<PoolEntry>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    network: Network,
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    pub pools: Vec<PoolEntry>,
+    // Activity derived from the chain, superseding the static active flag.
+    statuses: HashMap<String, PoolStatus>,
+    // Stake-only pubkeys of validated stakeaddresses, read from the DB once.
+    // Labels come from the registry, as every relabelling in the DB does.
+    stakeaddresses: Option<HashMap<String, String>>,
+}
+
+impl Registry {
+    pub fn load(path: Option<&String>, network: Network) -> Result<Self, Box<dyn Error>> {
+        match path {
+            Some(path) => {
+                info!("Loading pool registry from {} ...", path);
+                let path = PathBuf::from(path);
+                let modified = fs::metadata(&path)?.modified().ok();
+                let pools = parse(&path, network)?;
+                Ok(Registry {
+                    network,
+                    path: Some(path),
+                    modified,
+                    pools,
+                    ..Default::default()
+                })
+            }
+            None => {
+                debug!("Using built-in {} pool registry ...", network);
+                let file: RegistryFile = toml::from_str(network.params().registry)?;
+                Ok(Registry {
+                    network,
+                    pools: file.pools,
+                    ..Default::default()
+                })
+            }
+        }
+    }
+
+    pub fn lookup(&self, pubkey: &str) -> Option<&PoolEntry> {
+        self.pools.iter().find(|pool| pool.pubkey == pubkey)
+    }
+
+    // Label a stake-only pubkey as the registry currently knows it.
+    pub fn label(&self, pubkey: &str) -> Pool {
+        match self.lookup(pubkey) {
+            Some(entry) => entry.label(self.status(pubkey)),
+            None => Pool::unknown(pubkey),
+        }
+    }
+
+    // Label of a stakeaddress validated before, if it was.
+    pub async fn stakeaddress(
+        &mut self,
+        db: &Surreal<Any>,
+        raw: &str,
+    ) -> Result<Option<Pool>, Box<dyn Error>> {
+        if self.stakeaddresses.is_none() {
+            let known = db::getstakeaddresses(db).await?;
+            debug!("Loaded {} known stakeaddresses.", known.len());
+            self.stakeaddresses = Some(
+                known
+                    .into_iter()
+                    .map(|stakeaddress| (stakeaddress.raw, stakeaddress.pool.pubkey))
+                    .collect(),
+            );
+        }
+        let pubkey = self
+            .stakeaddresses
+            .as_ref()
+            .and_then(|known| known.get(raw));
+        Ok(pubkey.map(|pubkey| self.label(pubkey)))
+    }
+
+    // Record a newly validated stakeaddress, returning its label.
+    pub async fn remember(
+        &mut self,
+        db: &Surreal<Any>,
+        raw: &str,
+        pubkey: &str,
+    ) -> Result<Pool, Box<dyn Error>> {
+        let pool = self.label(pubkey);
+        let stakeaddress = Stakeaddress {
+            raw: raw.to_string(),
+            pool: pool.clone(),
+        };
+        db::regstakeaddress(db, &stakeaddress).await?;
+        self.stakeaddresses
+            .get_or_insert_with(HashMap::new)
+            .insert(raw.to_string(), pubkey.to_string());
+        Ok(pool)
+    }
+
+    pub fn status(&self, pubkey: &str) -> Option<PoolStatus> {
+        self.statuses.get(pubkey).copied()
+    }
+
+    // Replace derived statuses, returning pubkeys whose status changed.
+    pub fn set_statuses(&mut self, statuses: HashMap<String, PoolStatus>) -> Vec<String> {
+        let changed = statuses
+            .iter()
+            .filter(|(pubkey, status)| self.statuses.get(*pubkey) != Some(status))
+            .map(|(pubkey, _)| pubkey.clone())
+            .collect();
+        self.statuses = statuses;
+        changed
+    }
+
+    // Bring the pools table and recorded stakeaddresses in line with the registry.
+    pub async fn sync(&mut self, db: &Surreal<Any>) -> Result<(), Box<dyn Error>> {
+        trace!("Synchronising pool registry with DB ...");
+        self.statuses = db::getpoolstatuses(db).await?;
+        for entry in self.pools.iter() {
+            db::regpool(db, entry).await?;
+            db::relabelstakeaddresses(db, &self.label(&entry.pubkey)).await?;
+        }
+        let pubkeys: Vec<String> = self.pools.iter().map(|p| p.pubkey.clone()).collect();
+        db::forgetpools(db, &pubkeys).await?;
+        Ok(())
+    }
+
+    // Reload the registry file if it changed on disk since the last look,
+    // relabelling stakeaddresses of every pubkey whose entry moved.
+    pub async fn refresh(&mut self, db: &Surreal<Any>) -> Result<(), Box<dyn Error>> {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => return Ok(()),
+        };
+        // Editors replacing the file leave it missing for a moment.
+        let modified = match fs::metadata(&path) {
+            Ok(metadata) => metadata.modified().ok(),
+            Err(e) => {
+                warn!("Keeping previous pool registry: {}", e);
+                return Ok(());
+            }
+        };
+        if modified == self.modified {
+            return Ok(());
+        }
+        info!("Pool registry changed, reloading ...");
+        let pools = match parse(&path, self.network) {
+            Ok(pools) => pools,
+            Err(e) => {
+                warn!("Keeping previous pool registry: {}", e);
+                self.modified = modified;
+                return Ok(());
+            }
+        };
+        let previous = std::mem::replace(&mut self.pools, pools);
+        self.modified = modified;
+        for entry in self.pools.iter() {
+            if previous.iter().find(|p| p.pubkey == entry.pubkey) != Some(entry) {
+                debug!("Pool {} changed.", entry.pubkey);
+                db::regpool(db, entry).await?;
+                db::relabelstakeaddresses(db, &self.label(&entry.pubkey)).await?;
+            }
+        }
+        for entry in previous.iter() {
+            if self.lookup(&entry.pubkey).is_none() {
+                debug!("Pool {} removed from registry.", entry.pubkey);
+                db::relabelstakeaddresses(db, &Pool::unknown(&entry.pubkey)).await?;
+            }
+        }
+        let pubkeys: Vec<String> = self.pools.iter().map(|p| p.pubkey.clone()).collect();
+        db::forgetpools(db, &pubkeys).await?;
+        Ok(())
+    }
+}
+
+fn parse(path: &PathBuf, network: Network) -> Result<Vec<PoolEntry>, Box<dyn Error>> {
+    let content = fs::read_to_string(path)?;
+    let file: RegistryFile = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&content)?,
+        _ => toml::from_str(&content)?,
+    };
+    // A registry for another network would label nothing.
+    if let Some(entry) = file
+        .pools
+        .iter()
+        .find(|entry| !network.params().is_stake_key(&entry.pubkey))
+    {
+        return Err(format!("{} is not a {} stake key", entry.pubkey, network).into());
+    }
+    Ok(file.pools)
+}
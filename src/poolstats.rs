@@ -0,0 +1,186 @@
+// Per-pool staking statistics, bucketed by day and week.
+use crate::{
+    console::Transaction,
+    db,
+    pools::{Pool, Registry},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+};
+use surrealdb::{engine::any::Any, Surreal};
+
+const DAY: u64 = 86400;
+const WEEK: u64 = 7 * DAY;
+// Unix epoch fell on a Thursday, weeks start on the following Monday.
+const WEEK_OFFSET: u64 = 4 * DAY;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    Week,
+}
+
+impl Period {
+    pub fn length(self) -> u64 {
+        match self {
+            Period::Day => DAY,
+            Period::Week => WEEK,
+        }
+    }
+    // Start of the bucket holding the given unix time.
+    pub fn bucket(self, time: u64) -> u64 {
+        match self {
+            Period::Day => time - time % DAY,
+            Period::Week if time < WEEK_OFFSET => 0,
+            Period::Week => time - (time - WEEK_OFFSET) % WEEK,
+        }
+    }
+}
+
+// Just enough of a block to attribute it and estimate its reward.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StakedBlock {
+    pub height: u64,
+    pub time: u64,
+    pub coldstaking: Option<Pool>,
+    pub stakekernelvalue: Option<f64>,
+    pub coinstake: Option<Transaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolStats {
+    pub period: Period,
+    pub bucket: u64,
+    pub pubkey: String,
+    pub name: Option<String>,
+    pub blocks: u64,
+    pub share: f64,
+    // None when a block's reward is unknown, as without stake kernel values.
+    pub rewards: Option<f64>,
+    pub stakers: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkStats {
+    pub period: Period,
+    pub bucket: u64,
+    pub blocks: u64,
+    pub pool_blocks: u64,
+    pub solo_blocks: u64,
+    pub pool_ratio: f64,
+    pub rewards: Option<f64>,
+}
+
+pub async fn materialize(db: &Surreal<Any>, registry: &Registry) -> Result<(), Box<dyn Error>> {
+    for period in [Period::Day, Period::Week] {
+        materialize_period(db, registry, period).await?;
+    }
+    Ok(())
+}
+
+// Fill buckets from the last materialized one, which may have been
+// partial at the time, up to the chain tip.
+async fn materialize_period(
+    db: &Surreal<Any>,
+    registry: &Registry,
+    period: Period,
+) -> Result<(), Box<dyn Error>> {
+    let tip = match db::gettoptime(db).await? {
+        Some(time) => time,
+        None => return Ok(()),
+    };
+    let mut bucket = match db::getlaststatsbucket(db, period).await? {
+        Some(bucket) => bucket,
+        None => period.bucket(db::getbottomtime(db).await?.unwrap_or(tip)),
+    };
+    debug!(
+        "Materializing {:?} pool statistics from {} ...",
+        period, bucket
+    );
+    while bucket <= tip {
+        let blocks = db::getstakedblocks(db, bucket, bucket + period.length()).await?;
+        let (network, pools) = aggregate(registry, period, bucket, &blocks);
+        db::regstats(db, &network, &pools).await?;
+        bucket += period.length();
+    }
+    Ok(())
+}
+
+pub fn aggregate(
+    registry: &Registry,
+    period: Period,
+    bucket: u64,
+    blocks: &[StakedBlock],
+) -> (NetworkStats, Vec<PoolStats>) {
+    let mut network = NetworkStats {
+        period,
+        bucket,
+        blocks: blocks.len() as u64,
+        pool_blocks: 0,
+        solo_blocks: 0,
+        pool_ratio: 0.0,
+        rewards: Some(0.0),
+    };
+    let mut pools: HashMap<String, (PoolStats, HashSet<String>)> = HashMap::new();
+    for block in blocks {
+        // Blocks without a coinstake earn nothing, the others' rewards
+        // can't be told without the kernel value they staked.
+        let reward = match &block.coinstake {
+            Some(coinstake) => coinstake.reward(block.stakekernelvalue),
+            None => Some(0.0),
+        };
+        network.rewards = add(network.rewards, reward);
+        // Only registered pools count as pool staking, unknown
+        // stake keys are most likely people coldstaking to themselves.
+        let pool = match block
+            .coldstaking
+            .as_ref()
+            .and_then(|pool| registry.lookup(&pool.pubkey))
+        {
+            Some(entry) => entry,
+            None => {
+                network.solo_blocks += 1;
+                continue;
+            }
+        };
+        network.pool_blocks += 1;
+        let (stats, stakers) = pools.entry(pool.pubkey.clone()).or_insert_with(|| {
+            let stats = PoolStats {
+                period,
+                bucket,
+                pubkey: pool.pubkey.clone(),
+                name: pool.name.clone(),
+                blocks: 0,
+                share: 0.0,
+                rewards: Some(0.0),
+                stakers: 0,
+            };
+            (stats, HashSet::new())
+        });
+        stats.blocks += 1;
+        stats.rewards = add(stats.rewards, reward);
+        if let Some(staker) = block.coinstake.as_ref().and_then(|tx| tx.staker()) {
+            stakers.insert(staker);
+        }
+    }
+    if network.blocks > 0 {
+        network.pool_ratio = network.pool_blocks as f64 / network.blocks as f64;
+    }
+    let mut pools: Vec<PoolStats> = pools
+        .into_values()
+        .map(|(mut stats, stakers)| {
+            stats.share = stats.blocks as f64 / network.blocks as f64;
+            stats.stakers = stakers.len() as u64;
+            stats
+        })
+        .collect();
+    pools.sort_by_key(|stats| std::cmp::Reverse(stats.blocks));
+    (network, pools)
+}
+
+fn add(total: Option<f64>, reward: Option<f64>) -> Option<f64> {
+    Some(total? + reward?)
+}
@@ -0,0 +1,57 @@
+// One-off lookups of indexed records, printed as JSON.
+use crate::{config::Config, db, engine};
+use clap::ArgMatches;
+use serde_json::{json, Value};
+use std::error::Error;
+use surrealdb::{engine::any::Any, Surreal};
+
+// Newest blocks paying to an address that are listed.
+const ADDRESS_HEIGHTS: u64 = 1000;
+
+pub async fn run(config: &Config, args: &ArgMatches) {
+    let kind = args.get_one::<String>("kind").unwrap();
+    let id = args.get_one::<String>("id").unwrap();
+    let db = engine::connect(config).await;
+    match lookup(&db, kind, id).await {
+        Ok(Some(record)) => println!("{:#}", record),
+        Ok(None) => {
+            error!("No {} '{}' is indexed.", kind, id);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn lookup(db: &Surreal<Any>, kind: &str, id: &str) -> Result<Option<Value>, Box<dyn Error>> {
+    let record = match kind {
+        "block" => match id.parse::<u64>() {
+            Ok(height) => db::getblockbyheight(db, height).await?.map(|b| json!(b)),
+            Err(_) => db::getblockbyhash(db, id).await?.map(|b| json!(b)),
+        },
+        "tx" => db::gettx(db, id).await?.map(|tx| json!(tx)),
+        "address" => {
+            let stakeaddress = db::getstakeaddress(db, id).await?;
+            let heights = db::getaddressheights(db, id, ADDRESS_HEIGHTS, 0).await?;
+            match stakeaddress.is_some() || !heights.is_empty() {
+                true => Some(json!({
+                    "address": id,
+                    "stakeaddress": stakeaddress,
+                    "heights": heights,
+                })),
+                false => None,
+            }
+        }
+        "pool" => db::getpool(db, id).await?.map(|pool| json!(pool)),
+        "proposal" => {
+            let id = id
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid proposal ID '{}'", id))?;
+            db::getproposal(db, id).await?.map(|p| json!(p))
+        }
+        _ => unreachable!(),
+    };
+    Ok(record)
+}
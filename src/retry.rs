@@ -0,0 +1,43 @@
+// Exponential backoff with jitter for calls that can fail transiently.
+use crate::config::Retry;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+pub struct Backoff {
+    policy: Retry,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(policy: Retry) -> Self {
+        Backoff { policy, attempt: 0 }
+    }
+}
+
+// Delays before each retry, ending once the retries are used up.
+impl Iterator for Backoff {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        if self.attempt >= self.policy.retries {
+            return None;
+        }
+        let ceiling = self
+            .policy
+            .initial_ms
+            .saturating_mul(1 << self.attempt.min(32))
+            .min(self.policy.max_ms);
+        self.attempt += 1;
+        // Half of the delay is random so that failed callers don't retry in step.
+        let jitter = random() % (ceiling / 2 + 1);
+        Some(Duration::from_millis(ceiling - ceiling / 2 + jitter))
+    }
+}
+
+// Randomly keyed hasher, good enough for jitter without pulling in rand.
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
@@ -1,10 +1,74 @@
 // Collection of functions to interface with ghostd.
+use crate::{config::Retry, metrics, network::Network, retry::Backoff};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::error::Error;
+use std::{
+    error::Error,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// ghostd's answer to a height past its tip, among other bad parameters.
+pub const RPC_INVALID_PARAMETER: i64 = -8;
+// ... and to a transaction or block it doesn't know.
+pub const RPC_INVALID_ADDRESS_OR_KEY: i64 = -5;
+// ghostd is still loading its block index.
+const RPC_IN_WARMUP: i64 = -28;
+
+// How long a failed node is passed over before it gets another chance.
+const COOLDOWN: Duration = Duration::from_secs(30);
+// How long health checks wait on a node.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+struct Node {
+    url: String,
+    // host:port, safe to log unlike the URL.
+    name: String,
+    down_since: Mutex<Option<Instant>>,
+}
+
+impl Node {
+    fn healthy(&self) -> bool {
+        match *self.down_since.lock().unwrap() {
+            Some(since) => since.elapsed() > COOLDOWN,
+            None => true,
+        }
+    }
+
+    fn up(&self) {
+        if self.down_since.lock().unwrap().take().is_some() {
+            info!("ghostd at {} is answering again.", self.name);
+        }
+        metrics::get()
+            .rpc_node_up
+            .with_label_values(&[&self.name])
+            .set(1);
+    }
+
+    fn down(&self, e: &RpcError) {
+        let mut down_since = self.down_since.lock().unwrap();
+        if down_since.is_none() {
+            warn!("ghostd at {} failed: {}", self.name, e);
+        }
+        *down_since = Some(Instant::now());
+        metrics::get()
+            .rpc_node_up
+            .with_label_values(&[&self.name])
+            .set(0);
+    }
+}
 
 #[derive(Debug, Clone, Default)]
-pub struct RPCURL(String);
+pub struct RPCURL {
+    // The first node is preferred, the others are failed over to in order.
+    nodes: Vec<Arc<Node>>,
+    retry: Retry,
+    crosscheck: bool,
+    // Network to decode raw blocks for, None to ask for verbose ones.
+    rawblocks: Option<Network>,
+}
 
 impl RPCURL {
     pub fn target(
@@ -16,26 +80,128 @@ impl RPCURL {
         password: &str,
     ) -> Self {
         trace!("Constructing RPC console URL ...");
-        if walletname.len() == 0 {
-            if user != "" && password != "" {
-                self.0 = format!("http://{}:{}@{}:{}/", user, password, ip, port);
+        let url;
+        if walletname.is_empty() {
+            if !user.is_empty() && !password.is_empty() {
+                url = format!("http://{}:{}@{}:{}/", user, password, ip, port);
             } else {
-                self.0 = format!("http://{}:{}/", ip, port);
+                url = format!("http://{}:{}/", ip, port);
             }
         } else {
-            if user != "" && password != "" {
-                self.0 = format!(
+            if !user.is_empty() && !password.is_empty() {
+                url = format!(
                     "http://{}:{}@{}:{}/wallet/{}",
                     user, password, ip, port, walletname
                 );
             } else {
-                self.0 = format!("http://{}:{}/wallet/{}", ip, port, walletname);
+                url = format!("http://{}:{}/wallet/{}", ip, port, walletname);
             }
         }
-        return self;
+        // Every target is another node to fail over to.
+        self.nodes.push(Arc::new(Node {
+            url,
+            name: format!("{}:{}", ip, port),
+            down_since: Mutex::new(None),
+        }));
+        self
+    }
+
+    pub fn retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    // Compare block hashes across nodes before a block is indexed.
+    pub fn crosscheck(mut self, crosscheck: bool) -> Self {
+        self.crosscheck = crosscheck;
+        self
+    }
+
+    pub fn crosschecking(&self) -> bool {
+        self.crosscheck
+    }
+
+    // Fetch blocks raw and decode them here, sparing ghostd the verbose JSON.
+    pub fn rawblocks(mut self, network: Option<Network>) -> Self {
+        self.rawblocks = network;
+        self
+    }
+
+    pub fn decoding(&self) -> Option<Network> {
+        self.rawblocks
+    }
+
+    // Healthy nodes first, in the configured order, then the failed ones.
+    fn order(&self) -> Vec<&Arc<Node>> {
+        let (mut healthy, down): (Vec<_>, Vec<_>) =
+            self.nodes.iter().partition(|node| node.healthy());
+        healthy.extend(down);
+        healthy
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpcError {
+    // ghostd understood the call and refused it.
+    Node { code: i64, message: String },
+    // An HTTP error without a JSON-RPC error in the body.
+    Http(u16),
+    // ghostd could not be reached or the answer got lost.
+    Transport(String),
+    // Nodes hold different blocks at the same height.
+    Disagreement(String),
+}
+
+impl RpcError {
+    // Whether the same call may succeed if made again.
+    fn transient(&self) -> bool {
+        match self {
+            RpcError::Node { code, .. } => *code == RPC_IN_WARMUP,
+            RpcError::Http(status) => *status >= 500,
+            RpcError::Transport(_) => true,
+            RpcError::Disagreement(_) => false,
+        }
+    }
+
+    // Whether another node may know what this one doesn't yet: a block or
+    // transaction it hasn't seen, or a height past its tip.
+    fn lagging(&self, method: &str) -> bool {
+        match self {
+            RpcError::Node { code, .. } => {
+                *code == RPC_INVALID_ADDRESS_OR_KEY
+                    || (*code == RPC_INVALID_PARAMETER && method == "getblockhash")
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpcError::Node { code, message } => write!(f, "ghostd error {}: {}", code, message),
+            RpcError::Http(status) => write!(f, "ghostd answered with HTTP {}", status),
+            RpcError::Transport(e) => write!(f, "Can't reach ghostd: {}", e),
+            RpcError::Disagreement(e) => write!(f, "ghostd nodes disagree: {}", e),
+        }
     }
 }
 
+impl Error for RpcError {}
+
+// Whether nodes held different blocks, which may pass once they catch up.
+pub fn is_disagreement(e: &(dyn Error + 'static)) -> bool {
+    matches!(
+        e.downcast_ref::<RpcError>(),
+        Some(RpcError::Disagreement(_))
+    )
+}
+
+// Whether an error is ghostd refusing a call with the given code.
+pub fn is_node_error(e: &(dyn Error + 'static), code: i64) -> bool {
+    matches!(e.downcast_ref::<RpcError>(), Some(RpcError::Node { code: c, .. }) if *c == code)
+}
+
 fn parametrize(args: &str) -> Vec<Value> {
     trace!("Parsing arguments ...");
     let mut params: Vec<Value> = Vec::new();
@@ -49,24 +215,30 @@ fn parametrize(args: &str) -> Vec<Value> {
             }
         }
     }
-    return params;
+    params
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RPCResponse {
+    #[serde(default)]
     pub result: Value,
-    pub error: Option<String>,
-    pub id: String,
+    pub error: Option<Value>,
+    #[serde(default)]
+    pub id: Value,
 }
 
 impl RPCResponse {
-    fn unpack(self) -> Value {
+    // The result, or ghostd's refusal for the caller to deal with.
+    fn unpack(self) -> Result<Value, RpcError> {
         match self.error {
-            Some(err) => {
-                error!("{}", err);
-                std::process::exit(1);
-            }
-            None => self.result,
+            Some(error) => Err(RpcError::Node {
+                code: error["code"].as_i64().unwrap_or_default(),
+                message: match &error["message"] {
+                    Value::String(message) => message.clone(),
+                    other => other.to_string(),
+                },
+            }),
+            None => Ok(self.result),
         }
     }
 }
@@ -79,7 +251,7 @@ struct Post<'r> {
     params: Value,
 }
 
-pub(crate) fn call(args: &str, rpcurl: &RPCURL) -> Result<Value, Box<dyn Error>> {
+pub(crate) async fn call(args: &str, rpcurl: &RPCURL) -> Result<Value, Box<dyn Error>> {
     let mut params = parametrize(args);
     let method = params[0].clone();
     params.remove(0);
@@ -91,9 +263,151 @@ pub(crate) fn call(args: &str, rpcurl: &RPCURL) -> Result<Value, Box<dyn Error>>
         params: Value::Array(params),
     };
     debug!("RPC: {} {} ...", &post.method, &post.params);
-    let response: Value = ureq::post(&rpcurl.0)
-        .set("Content-Type", "application/json")
-        .send_json(serde_json::to_value(post)?)?
-        .into_json()?;
-    return Ok(response["result"].clone());
+    let label = post.method.as_str().unwrap_or_default().to_string();
+    let body = serde_json::to_value(post)?;
+    let mut backoff = Backoff::new(rpcurl.retry);
+    loop {
+        let mut failure = None;
+        let mut refusal = None;
+        for node in rpcurl.order() {
+            match attempt(&label, &body, node, None).await {
+                Ok(result) => {
+                    node.up();
+                    return Ok(result);
+                }
+                Err(e) if e.transient() => {
+                    node.down(&e);
+                    failure = Some(e);
+                }
+                // A node behind the others, ask the next one.
+                Err(e) if e.lagging(&label) => {
+                    debug!("RPC {} on {}: {}", label, node.name, e);
+                    node.up();
+                    refusal = Some(e);
+                }
+                // The node is fine, the call is not.
+                Err(e) => {
+                    node.up();
+                    return Err(e.into());
+                }
+            }
+        }
+        // Every node that answered refused alike.
+        if let Some(e) = refusal {
+            return Err(e.into());
+        }
+        let e = failure.ok_or("No ghostd node to call")?;
+        match backoff.next() {
+            Some(delay) => {
+                warn!("RPC {}: {}, retrying in {:?} ...", label, e, delay);
+                tokio::time::sleep(delay).await;
+            }
+            None => return Err(e.into()),
+        }
+    }
+}
+
+// A node's name and its answer.
+pub(crate) type Answer = (String, Result<Value, RpcError>);
+
+// Make the same call once on every healthy node, without failing over.
+pub(crate) async fn call_each(args: &str, rpcurl: &RPCURL) -> Result<Vec<Answer>, Box<dyn Error>> {
+    let mut params = parametrize(args);
+    let method = params.remove(0);
+    let label = method.as_str().unwrap_or_default().to_string();
+    let body = serde_json::to_value(Post {
+        jsonrpc: "",
+        id: "",
+        method,
+        params: Value::Array(params),
+    })?;
+    let mut answers = Vec::new();
+    for node in rpcurl.nodes.iter().filter(|node| node.healthy()) {
+        let answer = attempt(&label, &body, node, None).await;
+        match &answer {
+            Err(e) if e.transient() => node.down(e),
+            _ => node.up(),
+        }
+        answers.push((node.name.clone(), answer));
+    }
+    Ok(answers)
+}
+
+// A call without arguments made once on each node until one answers,
+// never waiting long, for checks that have to answer whether ghostd does.
+pub async fn probe(method: &str, rpcurl: &RPCURL) -> Result<Value, Box<dyn Error>> {
+    once(method, rpcurl).await
+}
+
+// A call made once on each node until one answers, never waiting long,
+// for work that is better dropped than held up by a slow node.
+pub(crate) async fn once(args: &str, rpcurl: &RPCURL) -> Result<Value, Box<dyn Error>> {
+    let mut params = parametrize(args);
+    let method = params.remove(0);
+    let label = method.as_str().unwrap_or_default().to_string();
+    let body = serde_json::to_value(Post {
+        jsonrpc: "",
+        id: "",
+        method,
+        params: Value::Array(params),
+    })?;
+    let mut failure = None;
+    let mut refusal = None;
+    for node in rpcurl.order() {
+        match attempt(&label, &body, node, Some(PROBE_TIMEOUT)).await {
+            Ok(result) => return Ok(result),
+            Err(e) if e.lagging(&label) => refusal = Some(e),
+            Err(e) => failure = Some(e),
+        }
+    }
+    Err(refusal.or(failure).ok_or("No ghostd node to call")?.into())
+}
+
+// A call made once, on a thread of its own so the runtime carries on meanwhile.
+async fn attempt(
+    label: &str,
+    body: &Value,
+    node: &Arc<Node>,
+    timeout: Option<Duration>,
+) -> Result<Value, RpcError> {
+    let (label, body, node) = (label.to_string(), body.clone(), node.clone());
+    tokio::task::spawn_blocking(move || send(&label, &body, &node, timeout))
+        .await
+        .map_err(|e| RpcError::Transport(e.to_string()))?
+}
+
+// One attempt at a call, counted and timed per method.
+fn send(
+    label: &str,
+    body: &Value,
+    node: &Node,
+    timeout: Option<Duration>,
+) -> Result<Value, RpcError> {
+    let metrics = metrics::get();
+    metrics.rpc_calls.with_label_values(&[label]).inc();
+    let timer = metrics
+        .rpc_seconds
+        .with_label_values(&[label])
+        .start_timer();
+    let mut request = ureq::post(&node.url).set("Content-Type", "application/json");
+    if let Some(timeout) = timeout {
+        request = request.timeout(timeout);
+    }
+    let response = match request.send_json(body) {
+        Ok(response) => response
+            .into_json::<RPCResponse>()
+            .map_err(|e| RpcError::Transport(e.to_string())),
+        // ghostd sends JSON-RPC errors with an HTTP error status.
+        Err(ureq::Error::Status(status, response)) => match response.into_json::<RPCResponse>() {
+            Ok(response) if response.error.is_some() => Ok(response),
+            _ => Err(RpcError::Http(status)),
+        },
+        Err(ureq::Error::Transport(e)) => Err(RpcError::Transport(e.to_string())),
+    };
+    timer.observe_duration();
+    let result = response.and_then(RPCResponse::unpack);
+    if result.is_err() {
+        metrics.rpc_errors.with_label_values(&[label]).inc();
+    }
+    result
 }
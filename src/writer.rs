@@ -0,0 +1,91 @@
+// Indexed blocks on their way into the DB. While catching up they are held
+// back and written a batch at a time in one transaction, rather than a few
+// round trips per block; while listening each block is written as it comes.
+use crate::{
+    console::{BlockData, VoteRecord},
+    db,
+    feed::{self, Event},
+    mempool, metrics,
+};
+use std::error::Error;
+use surrealdb::{engine::any::Any, Surreal};
+
+pub struct Writer {
+    pub proposal_ids: Vec<u64>,
+    pending: Vec<BlockData>,
+    batch: usize,
+}
+
+impl Writer {
+    pub async fn new(db: &Surreal<Any>, batch: usize) -> Result<Self, Box<dyn Error>> {
+        Ok(Writer {
+            proposal_ids: db::getproposalids(db).await?,
+            pending: Vec::new(),
+            batch: batch.max(1),
+        })
+    }
+
+    // Whether the block follows the last one held back. The DB doesn't
+    // know held back blocks yet, so anything else needs a flush first.
+    pub fn extends(&self, blockdata: &BlockData) -> bool {
+        self.pending
+            .last()
+            .is_some_and(|last| blockdata.previousblockhash.as_ref() == Some(&last.hash))
+    }
+
+    pub async fn write(
+        &mut self,
+        db: &Surreal<Any>,
+        blockdata: BlockData,
+    ) -> Result<(), Box<dyn Error>> {
+        self.pending.push(blockdata);
+        if self.pending.len() >= self.batch {
+            self.flush(db).await?;
+        }
+        Ok(())
+    }
+
+    // Write whatever is held back. Blocks stay held back if that fails, for
+    // the retry to find them.
+    pub async fn flush(&mut self, db: &Surreal<Any>) -> Result<(), Box<dyn Error>> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let votes: Vec<VoteRecord> = self
+            .pending
+            .iter()
+            .filter_map(|blockdata| blockdata.vote_record())
+            .collect();
+        db::regblocks(db, &self.pending, &votes).await?;
+        let blocks = std::mem::take(&mut self.pending);
+        // Nothing confirms while the mempool table is empty, as it is
+        // through most of a catch-up.
+        let (tracked, _) = db::getmempoolsize(db).await?;
+        for blockdata in blocks {
+            if tracked > 0 {
+                mempool::confirm(db, &blockdata).await?;
+            }
+            count(&blockdata);
+            feed::publish(Event::Block(Box::new(blockdata)));
+        }
+        Ok(())
+    }
+}
+
+fn count(blockdata: &BlockData) {
+    let metrics = metrics::get();
+    metrics.blocks_indexed.inc();
+    metrics::heights(Some(blockdata.height), None);
+    // Unregistered stake keys would blow up the label set.
+    if let Some(pool) = blockdata
+        .coldstaking
+        .as_ref()
+        .filter(|pool| pool.name.is_some())
+    {
+        let name = pool.name.as_deref().unwrap_or_default();
+        metrics
+            .pool_blocks
+            .with_label_values(&[&pool.pubkey, name])
+            .inc();
+    }
+}
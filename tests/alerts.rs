@@ -0,0 +1,74 @@
+// Alert rules firing into the deliveries table.
+mod common;
+
+use common::until;
+use ghostd_parser::{
+    alerts::{Alerts, Delivery},
+    config::{Config, Db},
+    db,
+};
+use std::time::Duration;
+use surrealdb::{engine::any::Any, Surreal};
+
+// Nothing listens there, so every delivery fails at once.
+const WEBHOOK: &str = "http://127.0.0.1:9/alerts";
+
+async fn memdb() -> Surreal<Any> {
+    let config = Config {
+        db: Db {
+            address: Some("mem://".to_string()),
+            stage: Some("test".to_string()),
+        },
+        ..Default::default()
+    };
+    db::init(&config).await.unwrap()
+}
+
+fn rules(name: &str, rules: &str) -> Alerts {
+    let path = std::env::temp_dir().join(format!(
+        "ghostd-parser-{}-{}.toml",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, format!("retries = 0\n{}", rules)).unwrap();
+    Alerts::load(path.to_str().unwrap()).unwrap()
+}
+
+async fn delivered(db: &Surreal<Any>) -> Vec<String> {
+    let records = db::getrecords(db, "deliveries").await.unwrap();
+    let mut rules: Vec<String> = records
+        .into_iter()
+        .map(|record| serde_json::from_value::<Delivery>(record).unwrap().rule)
+        .collect();
+    rules.sort();
+    rules
+}
+
+#[tokio::test]
+async fn every_no_block_rule_fires_once_per_stall() {
+    let db = memdb().await;
+    let mut alerts = rules(
+        "stall",
+        &format!(
+            "[[rule]]\nname = \"short\"\nkind = \"no_block\"\nminutes = 1\nwebhook = \"{0}\"\n\
+             [[rule]]\nname = \"long\"\nkind = \"no_block\"\nminutes = 5\nwebhook = \"{0}\"\n",
+            WEBHOOK
+        ),
+    );
+
+    alerts.stall(Duration::from_secs(90), &db);
+    until("the short rule", || async {
+        delivered(&db).await == ["short"]
+    })
+    .await;
+    // The short rule fired already, the long one still does.
+    alerts.stall(Duration::from_secs(120), &db);
+    alerts.stall(Duration::from_secs(400), &db);
+    until("the long rule", || async {
+        delivered(&db).await == ["long", "short"]
+    })
+    .await;
+    alerts.stall(Duration::from_secs(600), &db);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(delivered(&db).await, ["long", "short"]);
+}
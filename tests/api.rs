@@ -0,0 +1,229 @@
+// The HTTP API, GraphQL, the live feeds, health checks and alerts of an
+// engine syncing from the mock ghostd.
+mod common;
+
+use common::{coldstake, engine, registry, until, Ghostd};
+use ghostd_parser::{alerts::Delivery, db};
+use serde_json::{json, Value};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+const POOL: &str = "rtgcs1qapipool";
+// Nothing listens there, so every delivery fails at once.
+const WEBHOOK: &str = "http://127.0.0.1:9/alerts";
+
+fn freeaddr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+// Status and JSON body of a request, whatever the status, 0 while the
+// server is not up.
+async fn request(method: &str, url: String, body: Option<Value>) -> (u16, Value) {
+    let method = method.to_string();
+    tokio::task::spawn_blocking(move || {
+        let request = ureq::request(&method, &url).timeout(Duration::from_secs(10));
+        let response = match body {
+            Some(body) => request.send_json(body),
+            None => request.call(),
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => return (0, Value::String(e.to_string())),
+        };
+        let status = response.status();
+        let text = response.into_string().unwrap();
+        (
+            status,
+            serde_json::from_str(&text).unwrap_or(Value::String(text)),
+        )
+    })
+    .await
+    .unwrap()
+}
+
+async fn get(addr: &str, path: &str) -> (u16, Value) {
+    request("GET", format!("http://{}{}", addr, path), None).await
+}
+
+async fn graphql(addr: &str, query: &str) -> Value {
+    let url = format!("http://{}/graphql", addr);
+    let (status, body) = request("POST", url, Some(json!({ "query": query }))).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["errors"], Value::Null, "{}", body);
+    body["data"].clone()
+}
+
+// First event of the kind from the SSE feed, connected before returning.
+fn sse(addr: &str, kind: &str) -> Receiver<Value> {
+    let url = format!("http://{}/feed/sse", addr);
+    let kind = format!("event: {}", kind);
+    let (connected, ready) = mpsc::channel();
+    let (sender, events) = mpsc::channel();
+    std::thread::spawn(move || {
+        let response = ureq::get(&url).call().unwrap();
+        connected.send(()).unwrap();
+        let mut lines = BufReader::new(response.into_reader()).lines();
+        while let Some(Ok(line)) = lines.next() {
+            if line == kind {
+                let data = lines.next().unwrap().unwrap();
+                let data = data.strip_prefix("data:").unwrap().trim();
+                let _ = sender.send(serde_json::from_str(data).unwrap());
+                return;
+            }
+        }
+    });
+    ready.recv_timeout(Duration::from_secs(10)).unwrap();
+    events
+}
+
+// First text message from the WebSocket feed, spoken by hand.
+fn websocket(addr: &str) -> Receiver<Value> {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "GET /feed/ws HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        addr
+    )
+    .unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0; 1];
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    assert!(String::from_utf8_lossy(&head).starts_with("HTTP/1.1 101"));
+    let (sender, messages) = mpsc::channel();
+    std::thread::spawn(move || {
+        // Server frames are unmasked: opcode, length, payload.
+        let mut header = [0; 2];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(header[0], 0x81, "a final text frame");
+        let length = match header[1] & 0x7f {
+            126 => {
+                let mut length = [0; 2];
+                stream.read_exact(&mut length).unwrap();
+                u16::from_be_bytes(length) as usize
+            }
+            127 => {
+                let mut length = [0; 8];
+                stream.read_exact(&mut length).unwrap();
+                u64::from_be_bytes(length) as usize
+            }
+            length => length as usize,
+        };
+        let mut payload = vec![0; length];
+        stream.read_exact(&mut payload).unwrap();
+        let _ = sender.send(serde_json::from_slice(&payload).unwrap());
+    });
+    messages
+}
+
+#[tokio::test]
+async fn serves_what_the_engine_indexes() {
+    let ghostd = Ghostd::start(3);
+    ghostd.stake("rtstakeapi", POOL);
+    ghostd.mine(0, |block| coldstake(block, "rtstakeapi"));
+    let addr = freeaddr();
+    let rules = std::env::temp_dir().join(format!("ghostd-parser-{}-api.toml", std::process::id()));
+    std::fs::write(
+        &rules,
+        format!(
+            "retries = 0\n[[rule]]\nname = \"watch\"\nkind = \"watched_address\"\naddress = \"rtstaker4\"\nwebhook = \"{}\"\n",
+            WEBHOOK
+        ),
+    )
+    .unwrap();
+    let mut config = ghostd.config(Some(registry("Api Pool", POOL)));
+    config.api.listen = Some(addr.clone());
+    config.alerts.rules = Some(rules.to_string_lossy().to_string());
+    let db = engine(config);
+
+    until("readiness", || async {
+        get(&addr, "/readyz").await.0 == 200
+    })
+    .await;
+    let (status, health) = get(&addr, "/healthz").await;
+    assert_eq!(status, 200);
+    assert_eq!(health["phase"], "listening");
+    assert_eq!(health["indexed_height"], 3);
+    assert_eq!(health["node_height"], 3);
+    let (_, status) = get(&addr, "/status").await;
+    assert_eq!(status["indexed_height"], 3);
+
+    // REST.
+    let (_, blocks) = get(&addr, "/blocks?limit=2").await;
+    let heights: Vec<&Value> = blocks["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|b| &b["height"])
+        .collect();
+    assert_eq!(heights, [3, 2]);
+    let hash = ghostd.hash(3);
+    let (status, block) = get(&addr, &format!("/blocks/{}", hash)).await;
+    assert_eq!(status, 200);
+    assert_eq!(block["height"], 3);
+    assert_eq!(block["coldstaking"]["name"], "Api Pool");
+    let (_, block) = get(&addr, "/blocks/3").await;
+    assert_eq!(block["hash"], hash);
+    let txid = block["tx"][0]["txid"].as_str().unwrap().to_string();
+    let (_, tx) = get(&addr, &format!("/tx/{}", txid)).await;
+    assert_eq!(tx["txid"], txid);
+    let (_, address) = get(&addr, "/address/rtstakeapi").await;
+    assert_eq!(address["stakeaddress"]["pool"]["pubkey"], POOL);
+    assert_eq!(address["heights"]["items"], json!([3]));
+    let (_, pools) = get(&addr, "/pools").await;
+    assert_eq!(pools[0]["pubkey"], POOL);
+    let (status, missing) = get(&addr, "/blocks/99").await;
+    assert_eq!((status, missing), (404, json!({ "error": "Not found" })));
+    let (status, _) = get(&addr, "/proposals/x").await;
+    assert_eq!(status, 400);
+    let (status, metrics) = get(&addr, "/metrics").await;
+    assert_eq!(status, 200);
+    assert!(metrics
+        .as_str()
+        .unwrap()
+        .contains("ghostd_parser_indexed_height 3"));
+
+    // GraphQL.
+    let data = graphql(
+        &addr,
+        "{ blocks(limit: 1) { height pool { name } transactions { txid } } }",
+    )
+    .await;
+    assert_eq!(
+        data,
+        json!({ "blocks": [{ "height": 3, "pool": { "name": "Api Pool" }, "transactions": [{ "txid": txid }] }] })
+    );
+
+    // The live feeds carry the next block, which the alert watches.
+    let sse = sse(&addr, "block");
+    let websocket = websocket(&addr);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let hash = ghostd.mine(0, |_| {});
+    until("the announced block", || {
+        ghostd.announce(&hash);
+        async { db::gethash(&db, 4).await.unwrap().as_deref() == Some(hash.as_str()) }
+    })
+    .await;
+    let event = sse.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(event["data"]["height"], 4);
+    let message = websocket.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(message["event"], "block");
+    assert_eq!(message["data"]["height"], 4);
+    until("the alert", || async {
+        let deliveries = db::getrecords(&db, "deliveries").await.unwrap();
+        deliveries.into_iter().any(|record| {
+            let delivery: Delivery = serde_json::from_value(record).unwrap();
+            delivery.rule == "watch" && delivery.alert.details["height"] == 4
+        })
+    })
+    .await;
+}
@@ -0,0 +1,433 @@
+// A mock ghostd serving canned JSON-RPC answers over a synthetic chain,
+// with a ZMQ publisher for block announcements, and an in-memory DB.
+#![allow(unused)]
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use ghostd_parser::{
+    config::{Config, Db, Pools, Rpc, Zmq},
+    db, engine,
+    network::Network,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+use surrealdb::{engine::any::Any, Surreal};
+
+// How long a test waits for the engine to reach the expected state.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+// First block time, chain time advances a minute per block.
+pub const GENESIS_TIME: u64 = 1_700_000_000;
+
+#[derive(Default)]
+pub struct Chain {
+    pub blocks: Vec<Value>,
+    // validateaddress answers: stakeaddress to stake-only pubkey.
+    pub stakers: HashMap<String, String>,
+    // Calls still to be refused the way an overloaded ghostd does.
+    pub failures: usize,
+    // getmempoolentry answers by txid.
+    pub mempool: HashMap<String, Value>,
+    // Calls answered, by method.
+    pub calls: HashMap<String, usize>,
+}
+
+pub struct Ghostd {
+    pub chain: Arc<Mutex<Chain>>,
+    // host:port of the JSON-RPC server.
+    pub rpc: String,
+    // Endpoint subscribers connect to.
+    pub zmq: String,
+    publisher: zmq::Socket,
+}
+
+impl Ghostd {
+    // A node with `height` blocks on branch 0.
+    pub fn start(height: u64) -> Ghostd {
+        let chain = Arc::new(Mutex::new(Chain::default()));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let rpc = listener.local_addr().unwrap().to_string();
+        let app = Router::new()
+            .route("/", post(handle))
+            .with_state(chain.clone());
+        // Outlives the test, so an engine still running never loses its node.
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                axum::serve(listener, app).await
+            })
+        });
+
+        let publisher = zmq::Context::new().socket(zmq::PUB).unwrap();
+        publisher.bind("tcp://127.0.0.1:*").unwrap();
+        let zmq = publisher.get_last_endpoint().unwrap().unwrap();
+
+        let ghostd = Ghostd {
+            chain,
+            rpc,
+            zmq,
+            publisher,
+        };
+        for _ in 0..height {
+            ghostd.mine(0, |_| {});
+        }
+        ghostd
+    }
+
+    // Append a block on top of the current tip, returning its hash.
+    pub fn mine(&self, branch: u64, edit: impl FnOnce(&mut Value)) -> String {
+        let mut chain = self.chain.lock().unwrap();
+        let previous = chain.blocks.last().map(|block| text(&block["hash"]));
+        let mut block = block(chain.blocks.len() as u64, branch, previous);
+        edit(&mut block);
+        let hash = text(&block["hash"]);
+        chain.blocks.push(block);
+        hash
+    }
+
+    // Drop every block from `height` on, as a reorganisation does.
+    pub fn truncate(&self, height: u64) {
+        self.chain.lock().unwrap().blocks.truncate(height as usize);
+    }
+
+    pub fn hash(&self, height: u64) -> String {
+        text(&self.chain.lock().unwrap().blocks[height as usize]["hash"])
+    }
+
+    pub fn tip(&self) -> u64 {
+        self.chain.lock().unwrap().blocks.len() as u64 - 1
+    }
+
+    pub fn calls(&self, method: &str) -> usize {
+        let chain = self.chain.lock().unwrap();
+        chain.calls.get(method).copied().unwrap_or_default()
+    }
+
+    pub fn fail(&self, calls: usize) {
+        self.chain.lock().unwrap().failures = calls;
+    }
+
+    pub fn stake(&self, stakeaddress: &str, pubkey: &str) {
+        let mut chain = self.chain.lock().unwrap();
+        chain
+            .stakers
+            .insert(stakeaddress.to_string(), pubkey.to_string());
+    }
+
+    // Accept a transaction into the mempool, first seen at `time`.
+    pub fn broadcast(&self, txid: &str, vsize: u64, fee: f64, time: u64) {
+        let entry = json!({
+            "vsize": vsize,
+            "weight": vsize * 4,
+            "time": time,
+            "height": self.tip(),
+            "fees": { "base": fee, "modified": fee },
+        });
+        let mut chain = self.chain.lock().unwrap();
+        chain.mempool.insert(txid.to_string(), entry);
+    }
+
+    // Drop a transaction from the mempool unconfirmed.
+    pub fn evict(&self, txid: &str) {
+        self.chain.lock().unwrap().mempool.remove(txid);
+    }
+
+    // Mine a block confirming a transaction of the mempool.
+    pub fn confirm(&self, txid: &str) -> String {
+        self.evict(txid);
+        self.mine(0, |block| {
+            let mut tx = block["tx"][0].clone();
+            tx["txid"] = json!(txid);
+            tx["hash"] = json!(txid);
+            block["tx"].as_array_mut().unwrap().push(tx);
+            block["nTx"] = json!(2);
+        })
+    }
+
+    // Publish a hashblock notification the way ghostd does.
+    pub fn announce(&self, hash: &str) {
+        self.publish(b"hashblock", hash);
+    }
+
+    pub fn announce_tx(&self, txid: &str) {
+        self.publish(b"hashtx", txid);
+    }
+
+    fn publish(&self, topic: &[u8], hash: &str) {
+        let seq: u32 = 0;
+        self.publisher
+            .send_multipart(
+                [topic.to_vec(), decode(hash), seq.to_le_bytes().to_vec()],
+                0,
+            )
+            .unwrap();
+    }
+
+    // Regtest settings pointing at this node and an in-memory DB.
+    pub fn config(&self, registry: Option<PathBuf>) -> Config {
+        Config {
+            network: Network::Regtest,
+            rpc: Rpc {
+                ip: Some(self.rpc.clone()),
+                ..Default::default()
+            },
+            zmq: Zmq {
+                address: self.zmq.clone(),
+                ..Default::default()
+            },
+            db: Db {
+                address: Some("mem://".to_string()),
+                stage: Some("test".to_string()),
+            },
+            pools: Pools {
+                registry: registry.map(|path| path.to_string_lossy().to_string()),
+            },
+            ..Default::default()
+        }
+    }
+}
+
+async fn handle(State(chain): State<Arc<Mutex<Chain>>>, Json(post): Json<Value>) -> Response {
+    let mut chain = chain.lock().unwrap();
+    if chain.failures > 0 {
+        chain.failures -= 1;
+        return (StatusCode::SERVICE_UNAVAILABLE, "Work queue depth exceeded").into_response();
+    }
+    let params = &post["params"];
+    let method = post["method"].as_str().unwrap_or_default();
+    *chain.calls.entry(method.to_string()).or_default() += 1;
+    let result = match method {
+        "getblockcount" => Ok(json!(chain.blocks.len() - 1)),
+        "getblockhash" => params[0]
+            .as_u64()
+            .and_then(|height| chain.blocks.get(height as usize))
+            .map(|block| block["hash"].clone())
+            .ok_or((-8, "Block height out of range")),
+        "getblock" => chain
+            .blocks
+            .iter()
+            .find(|block| block["hash"] == params[0])
+            .cloned()
+            .ok_or((-5, "Block not found")),
+        "validateaddress" => {
+            let address = params[0].as_str().unwrap_or_default();
+            let pubkey = match chain.stakers.get(address) {
+                Some(pubkey) => pubkey.clone(),
+                None => format!("rtgcs1{}", address),
+            };
+            Ok(json!({ "isvalid": true, "stakeonly_address": pubkey }))
+        }
+        "tallyvotes" => Ok(tally(&chain.blocks, params)),
+        "getmempoolentry" => params[0]
+            .as_str()
+            .and_then(|txid| chain.mempool.get(txid))
+            .cloned()
+            .ok_or((-5, "Transaction not in mempool")),
+        "getrawmempool" => Ok(json!(chain.mempool)),
+        _ => Err((-32601, "Method not found")),
+    };
+    match result {
+        Ok(result) => (
+            StatusCode::OK,
+            Json(json!({ "result": result, "error": null, "id": "" })),
+        )
+            .into_response(),
+        // ghostd answers failed calls with an HTTP error status.
+        Err((code, message)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "result": null,
+                "error": { "code": code, "message": message },
+                "id": "",
+            })),
+        )
+            .into_response(),
+    }
+}
+
+// Votes counted per option, in the shape ghostd reports them.
+fn tally(blocks: &[Value], params: &Value) -> Value {
+    let proposal = params[0].as_u64().unwrap_or_default();
+    let start = params[1].as_u64().unwrap_or_default();
+    let end = params[2].as_u64().unwrap_or(u64::MAX);
+    let mut counted = 0;
+    let mut options: HashMap<u64, u64> = HashMap::new();
+    for block in blocks {
+        let height = block["height"].as_u64().unwrap();
+        if height < start || height > end {
+            continue;
+        }
+        counted += 1;
+        let Some(vote) = block["tx"][0]["vout"][0]["vote"].as_str() else {
+            continue;
+        };
+        if let Some((id, option)) = vote.split_once(", ") {
+            if id.parse::<u64>() == Ok(proposal) {
+                *options.entry(option.parse().unwrap()).or_default() += 1;
+            }
+        }
+    }
+    let mut result = json!({
+        "proposal": proposal,
+        "height_start": start,
+        "height_end": end,
+        "blocks_counted": counted,
+    });
+    for (option, votes) in options {
+        result[format!("Option {}", option)] = json!(format!(
+            "{}, {:.2}%",
+            votes,
+            votes as f64 * 100.0 / counted as f64
+        ));
+    }
+    result
+}
+
+// Hashes of different branches never collide, and keep the leading
+// zeros that make the RPC client pass them on as strings.
+pub fn blockhash(height: u64, branch: u64) -> String {
+    format!("{:064x}", (branch << 32) | height)
+}
+
+// A proof of stake block as `getblock <hash> 2 true` returns it, its
+// coinstake carrying an empty data output and a plain payout.
+pub fn block(height: u64, branch: u64, previous: Option<String>) -> Value {
+    let hash = blockhash(height, branch);
+    let txid = format!("{:064x}", (1 << 63) | (branch << 32) | height);
+    json!({
+        "bits": "1d00ffff",
+        "chainwork": format!("{:064x}", height + 1),
+        "difficulty": 1.0,
+        "hash": hash,
+        "height": height,
+        "mediantime": GENESIS_TIME + height * 60,
+        "merkleroot": txid,
+        "nTx": 1,
+        "nonce": 0,
+        "previousblockhash": previous,
+        "size": 250,
+        "strippedsize": 250,
+        "time": GENESIS_TIME + height * 60,
+        "tx": [{
+            "txid": txid,
+            "hash": txid,
+            "version": 2,
+            "size": 150,
+            "vsize": 150,
+            "weight": 600,
+            "locktime": 0,
+            "hex": "",
+            "vin": [{
+                "txid": format!("{:064x}", height),
+                "vout": 0,
+                "scriptSig": { "asm": "", "hex": "" },
+            }],
+            "vout": [
+                {
+                    "n": 0,
+                    "type": "data",
+                    "data_hex": "",
+                },
+                {
+                    "n": 1,
+                    "type": "standard",
+                    "value": 12.5,
+                    "valueSat": 1_250_000_000u64,
+                    "scriptPubKey": {
+                        "addresses": [format!("rtstaker{}", height)],
+                        "asm": "",
+                        "hex": "",
+                        "reqSigs": 1,
+                        "type": "pubkeyhash",
+                    },
+                },
+            ],
+        }],
+        "version": 2,
+        "versionHex": "00000002",
+        "weight": 1000,
+        "witnessmerkleroot": txid,
+    })
+}
+
+// Coldstake the block through a stakeaddress.
+pub fn coldstake(block: &mut Value, stakeaddress: &str) {
+    block["tx"][0]["vout"][1]["scriptPubKey"]["stakeaddresses"] = json!([stakeaddress]);
+}
+
+pub fn vote(block: &mut Value, proposal: u64, option: u64) {
+    block["tx"][0]["vout"][0]["vote"] = json!(format!("{}, {}", proposal, option));
+}
+
+fn text(value: &Value) -> String {
+    value.as_str().unwrap().to_string()
+}
+
+pub fn decode(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+// Run `engine::sync` on its own thread and runtime, as main does, and
+// hand back the in-memory DB it writes to.
+pub fn engine(config: Config) -> Surreal<Any> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let db = db::init(&config).await.unwrap();
+            sender.send(db.clone()).unwrap();
+            engine::sync(&config, &db).await
+        })
+    });
+    receiver.recv().unwrap()
+}
+
+// A registry file in the temp directory holding one pool.
+pub fn registry(name: &str, pubkey: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "ghostd-parser-{}-{}.toml",
+        std::process::id(),
+        name.replace(' ', "-")
+    ));
+    std::fs::write(
+        &path,
+        format!(
+            "[[pool]]\npubkey = \"{}\"\nname = \"{}\"\nactive = true\n",
+            pubkey, name
+        ),
+    )
+    .unwrap();
+    path
+}
+
+// Poll until `check` holds, panicking once TIMEOUT passes.
+pub async fn until<F>(what: &str, mut check: impl FnMut() -> F)
+where
+    F: Future<Output = bool>,
+{
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    while !check().await {
+        if tokio::time::Instant::now() > deadline {
+            panic!("Timed out waiting for {}.", what);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
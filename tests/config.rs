@@ -0,0 +1,50 @@
+// Effective settings as `config print` reports them: the config file,
+// overridden by environment variables, overridden by flags.
+use std::{path::PathBuf, process::Command};
+
+fn print(name: &str, file: &str, envs: &[(&str, &str)], flags: &[&str]) -> toml::Value {
+    let dir = std::env::temp_dir().join(format!("ghostd-parser-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path: PathBuf = dir.join("config.toml");
+    std::fs::write(&path, file).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_ghostd-parser"))
+        .current_dir(&dir)
+        .env_clear()
+        .envs(envs.iter().copied())
+        .arg("--config")
+        .arg(&path)
+        .args(flags)
+        .args(["config", "print"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    toml::from_str(&String::from_utf8(output.stdout).unwrap()).unwrap()
+}
+
+#[test]
+fn flags_override_the_environment_which_overrides_the_file() {
+    let file = "network = \"testnet\"\n\
+                [rpc]\nip = \"10.0.0.1\"\nuser = \"file\"\npassword = \"secret\"\n\
+                [db]\nstage = \"file\"\naddress = \"10.0.0.2:8000\"\n";
+    let envs = [
+        ("GHOSTD_PARSER_RPC_USER", "env"),
+        ("GHOSTD_PARSER_DB_STAGE", "env"),
+    ];
+    let config = print("precedence", file, &envs, &["--rpc-user", "flag"]);
+    assert_eq!(config["network"].as_str(), Some("testnet"));
+    assert_eq!(config["rpc"]["ip"].as_str(), Some("10.0.0.1"));
+    assert_eq!(config["db"]["address"].as_str(), Some("10.0.0.2:8000"));
+    assert_eq!(config["db"]["stage"].as_str(), Some("env"));
+    assert_eq!(config["rpc"]["user"].as_str(), Some("flag"));
+}
+
+#[test]
+fn secrets_are_redacted() {
+    let file = "[rpc]\nuser = \"user\"\npassword = \"file\"\n";
+    let config = print("redaction", file, &[], &[]);
+    assert_eq!(config["rpc"]["password"].as_str(), Some("<redacted>"));
+    let envs = [("GHOSTD_PARSER_RPC_PASSWORD", "env")];
+    let config = print("redaction", file, &envs, &["--rpc-password", "flag"]);
+    assert_eq!(config["rpc"]["password"].as_str(), Some("<redacted>"));
+    assert_eq!(config["rpc"]["user"].as_str(), Some("user"));
+}
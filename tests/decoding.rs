@@ -0,0 +1,252 @@
+// Blocks decoded from raw bytes. tests/fixtures/raw holds a block in
+// `getblock <hash> 0` form: a coldstaked coinstake voting and carrying the
+// smsg and treasury fields, and a transaction spending anon inputs into
+// blind, anon and data outputs. The block was serialized by hand, as ghostd
+// does, rather than captured from mainnet, so its hashes match nothing
+// on chain. tests/fixtures/captured takes mainnet blocks recorded both
+// ways, `record-fixture <height> --raw -o <name>.hex` next to
+// `record-fixture <height> -o <name>.json`, which have to decode to what
+// ghostd reports; none have been recorded yet.
+use ghostd_parser::{
+    console::{BlockData, Vin, Vout},
+    decode::{self, HeaderInfo},
+    network::Network,
+};
+use serde_json::Value;
+use std::{fs, path::PathBuf};
+
+const HASH: &str = "d46fc54c6b8653c089862f280dff1131fa12fe806e38b9ea6cd02294f084fc7f";
+
+fn raw() -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/raw/coldstake.hex");
+    decode::unhex(fs::read_to_string(path).unwrap().trim()).unwrap()
+}
+
+fn info(hash: &str) -> HeaderInfo {
+    HeaderInfo {
+        hash: hash.to_string(),
+        height: 1234,
+        mediantime: 1_699_999_000,
+        chainwork: format!("{:064x}", 1235),
+        difficulty: 1.0,
+        stakekernelvalue: Some(1500.0),
+        ..Default::default()
+    }
+}
+
+fn decoded() -> BlockData {
+    decode::block(&raw(), info(HASH), Network::Mainnet).unwrap()
+}
+
+#[test]
+fn the_header_and_sizes_match_the_node() {
+    let block = decoded();
+    assert_eq!(block.height, 1234);
+    assert_eq!(
+        block.previousblockhash.as_deref(),
+        Some("aaa39c958e878079726b645d564f48413a332c251e17100902fbf4ede6dfd8d1")
+    );
+    assert_eq!(
+        (block.time, block.bits.as_str()),
+        (1_700_000_000, "1d00ffff")
+    );
+    assert_eq!((block.n_tx, block.size), (2, 790));
+    let txids: Vec<&str> = block.tx.iter().map(|tx| tx.txid.as_str()).collect();
+    assert_eq!(
+        txids,
+        [
+            "0d7d84a7366a5aa2654d1c29be0394328d3a8923e8c2e5db2ca410b09e08da94",
+            "222ee8abc69f21f3f53a647f9e37d4fcf61ad090ee52978a192ca024bfc95a98",
+        ]
+    );
+    let coinstake = &block.tx[0];
+    assert_eq!(
+        coinstake.hash,
+        "ff507befd2f63f9074855e4a50633994053ceee72808daa14372bf044ca6cd1d"
+    );
+    assert_eq!((coinstake.size, coinstake.weight), (258, 151 * 3 + 258));
+    // Version 0xa0, transaction type 2 for a coinstake.
+    assert_eq!(coinstake.version, 0x2a0);
+}
+
+#[test]
+fn the_coinstake_names_its_stakeaddress_and_vote() {
+    let mut block = decoded();
+    match &block.tx[0].vout[0] {
+        Vout::Data {
+            smsgfeerate,
+            smsgdifficulty,
+            treasury_fund_cfwd,
+            vote,
+            ..
+        } => {
+            assert_eq!(*smsgfeerate, Some(0.0002));
+            assert_eq!(smsgdifficulty.as_deref(), Some("1f0fffff"));
+            assert_eq!(*treasury_fund_cfwd, Some(57.81));
+            assert_eq!(vote.as_deref(), Some("12, 1"));
+        }
+        other => panic!("Expected the data output, got {:?}", other),
+    }
+    let Vout::Standard {
+        value,
+        scriptpubkey,
+        ..
+    } = &block.tx[0].vout[1]
+    else {
+        panic!("The coinstake pays out through a standard output.");
+    };
+    assert_eq!(*value, 1524.5441);
+    assert_eq!(scriptpubkey.staking_type, "pubkeyhash256");
+    assert!(scriptpubkey
+        .asm
+        .starts_with("OP_ISCOINSTAKE OP_IF OP_DUP OP_HASH160"));
+    assert_eq!(
+        scriptpubkey.addresses,
+        Some(vec![
+            "2vGrgFoikk8b8EAT2U2VrvUQgBVJj8eMrDRLGFmYABGpi6ghPaj".to_string()
+        ])
+    );
+    assert_eq!(
+        block.stakeaddress().unwrap().as_deref(),
+        Some("GLgd6dSLywi5NzxCaWU1ThLCSJQCJytQQY")
+    );
+    assert_eq!(
+        decode::stakeonly(
+            "GLgd6dSLywi5NzxCaWU1ThLCSJQCJytQQY",
+            Network::Mainnet.params()
+        )
+        .as_deref(),
+        Some("gcs1runz6dpmgfy4q467v4k8x75p3z8ed8dy0830fe")
+    );
+    block.read_vote().unwrap();
+    let vote = block.voting_info.unwrap();
+    assert_eq!((vote.proposal_id, vote.voted_for_option), (12, 1));
+}
+
+#[test]
+fn private_inputs_and_outputs_keep_their_type() {
+    let block = decoded();
+    let tx = &block.tx[1];
+    match &tx.vin[0] {
+        Vin::Anon {
+            num_inputs,
+            ring_size,
+            txinwitness,
+            ..
+        } => assert_eq!((*num_inputs, *ring_size, txinwitness.len()), (1, 5, 2)),
+        other => panic!("Expected an anon input, got {:?}", other),
+    }
+    // The key images come before the outputs, the ring signature after them.
+    assert_eq!(tx.size, 348);
+    assert_eq!(tx.weight, 272 * 3 + 348);
+    assert_eq!(
+        tx.hash,
+        "669093a7b40184863ee660e4dd3f10e316a0d0c4e4406e00647a1d44f6a181fc"
+    );
+    let kinds: Vec<&str> = tx.vout.iter().map(|vout| vout.kind()).collect();
+    assert_eq!(kinds, ["blind", "anon", "data"]);
+    let Vout::Blind { scriptpubkey, .. } = &tx.vout[0] else {
+        unreachable!()
+    };
+    assert_eq!(
+        scriptpubkey.as_ref().unwrap().owner().as_deref(),
+        Some("GasQFBhQKHiVSp3m7LRfnpAtDnS15e82XN")
+    );
+}
+
+#[test]
+fn rewards_need_the_kernel_value_getblock_reports() {
+    let block = decoded();
+    assert_eq!(block.stakekernelvalue, Some(1500.0));
+    let reward = block.tx[0].reward(block.stakekernelvalue).unwrap();
+    assert!((reward - 24.5441).abs() < 1e-9);
+    let unknown = HeaderInfo {
+        stakekernelvalue: None,
+        ..info(HASH)
+    };
+    let block = decode::block(&raw(), unknown, Network::Mainnet).unwrap();
+    assert_eq!(block.tx[0].reward(block.stakekernelvalue), None);
+}
+
+#[test]
+fn decoded_blocks_read_back_without_the_raw_hex() {
+    let block = decoded();
+    let serialized = serde_json::to_value(&block).unwrap();
+    assert!(serialized["tx"][0].get("hex").is_none());
+    let again: BlockData = serde_json::from_value(serialized.clone()).unwrap();
+    assert_eq!(serde_json::to_value(again).unwrap(), serialized);
+}
+
+#[test]
+fn damaged_blocks_are_refused() {
+    let raw = raw();
+    let other = format!("{:064x}", 1);
+    assert!(decode::block(&raw, info(&other), Network::Mainnet).is_err());
+    for len in 0..raw.len() {
+        assert!(
+            decode::block(&raw[..len], info(HASH), Network::Mainnet).is_err(),
+            "{} bytes",
+            len
+        );
+    }
+}
+
+// Paths at which the two values differ.
+fn differences(path: &str, decoded: &Value, verbose: &Value, found: &mut Vec<String>) {
+    match (decoded, verbose) {
+        (Value::Object(decoded), Value::Object(verbose)) => {
+            for key in decoded
+                .keys()
+                .chain(verbose.keys().filter(|k| !decoded.contains_key(*k)))
+            {
+                let (a, b) = (&decoded.get(key), &verbose.get(key));
+                let path = format!("{}.{}", path, key);
+                match (a, b) {
+                    (Some(a), Some(b)) => differences(&path, a, b, found),
+                    _ => found.push(path),
+                }
+            }
+        }
+        (Value::Array(decoded), Value::Array(verbose)) if decoded.len() == verbose.len() => {
+            for (n, (a, b)) in decoded.iter().zip(verbose).enumerate() {
+                differences(&format!("{}[{}]", path, n), a, b, found);
+            }
+        }
+        (a, b) if a != b => found.push(format!("{}: {} rather than {}", path, a, b)),
+        _ => {}
+    }
+}
+
+#[test]
+fn captured_blocks_decode_to_what_ghostd_reports() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/captured");
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return;
+    };
+    for path in entries.map(|entry| entry.unwrap().path()) {
+        if path.extension().is_none_or(|ext| ext != "hex") {
+            continue;
+        }
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        let text = fs::read_to_string(path.with_extension("json"))
+            .unwrap_or_else(|e| panic!("{} has no verbose capture: {}", name, e));
+        let verbose: Value = serde_json::from_str(&text).unwrap();
+        let bytes = decode::unhex(fs::read_to_string(&path).unwrap().trim()).unwrap();
+        let info: HeaderInfo = serde_json::from_value(verbose.clone()).unwrap();
+        let decoded = decode::block(&bytes, info, Network::Mainnet)
+            .unwrap_or_else(|e| panic!("{} does not decode: {}", name, e));
+        let mut expected: BlockData = serde_json::from_value(verbose).unwrap();
+        // Decoding leaves out what the bytes already are.
+        for tx in expected.tx.iter_mut() {
+            tx.hex.clear();
+        }
+        let mut found = Vec::new();
+        differences(
+            &name,
+            &serde_json::to_value(&decoded).unwrap(),
+            &serde_json::to_value(&expected).unwrap(),
+            &mut found,
+        );
+        assert!(found.is_empty(), "{}", found.join("\n"));
+    }
+}
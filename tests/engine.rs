@@ -0,0 +1,381 @@
+// End to end runs of the engine against the mock ghostd in tests/common.
+mod common;
+
+use common::{blockhash, coldstake, engine, registry, until, vote, Ghostd, GENESIS_TIME};
+use ghostd_parser::{
+    activity::PoolStatus,
+    config::{Config, Db},
+    db, engine, mempool,
+    network::Network,
+    pools,
+    poolstats::Period,
+    rpc,
+};
+use surrealdb::{engine::any::Any, Surreal};
+
+const POOL: &str = "rtgcs1qtestpool";
+const UNKNOWN: &str = "rtgcs1qunknownpool";
+
+async fn indexed(db: &Surreal<Any>, height: u64, hash: &str) -> bool {
+    db::gethash(db, height).await.unwrap().as_deref() == Some(hash)
+}
+
+async fn tracked(db: &Surreal<Any>, txid: &str) -> bool {
+    db::getmempoolids(db)
+        .await
+        .unwrap()
+        .iter()
+        .any(|id| id == txid)
+}
+
+#[tokio::test]
+async fn catchup_indexes_the_whole_chain() {
+    let ghostd = Ghostd::start(8);
+    ghostd.stake("rtstakepool", POOL);
+    ghostd.stake("rtstakeother", UNKNOWN);
+    ghostd.mine(0, |block| coldstake(block, "rtstakeother"));
+    for _ in 0..2 {
+        ghostd.mine(0, |block| coldstake(block, "rtstakepool"));
+    }
+    let path = registry("Test Pool", POOL);
+    let db = engine(ghostd.config(Some(path)));
+
+    let tip = ghostd.hash(10);
+    until("the tip", || indexed(&db, 10, &tip)).await;
+    for link in db::getchain(&db, 0, 10).await.unwrap() {
+        assert_eq!(link.hash, ghostd.hash(link.height));
+    }
+    let stakeaddress = db::getstakeaddress(&db, "rtstakepool")
+        .await
+        .unwrap()
+        .expect("the stakeaddress is recorded");
+    assert_eq!(stakeaddress.pool.pubkey, POOL);
+    assert_eq!(stakeaddress.pool.name.as_deref(), Some("Test Pool"));
+    // The second block finds the stakeaddress known already.
+    assert_eq!(ghostd.calls("validateaddress"), 2);
+    assert_eq!(db::getnetwork(&db).await.unwrap(), Some(Network::Regtest));
+    // Transactions and addresses are found through their lookups.
+    let txid = format!("{:064x}", (1u64 << 63) | 10);
+    let tx = db::gettx(&db, &txid).await.unwrap();
+    assert_eq!(tx.expect("the coinstake is found").txid, txid);
+    let heights = db::getaddressheights(&db, "rtstakepool", 10, 0)
+        .await
+        .unwrap();
+    assert_eq!(heights, [10, 9]);
+
+    // The registered pool has no url and is still no candidate.
+    until("the pool candidates", || async {
+        !db::getpoolcandidates(&db).await.unwrap().is_empty()
+    })
+    .await;
+    let candidates = db::getpoolcandidates(&db).await.unwrap();
+    let pubkeys: Vec<&str> = candidates.iter().map(|c| c.pubkey.as_str()).collect();
+    assert_eq!(pubkeys, [UNKNOWN]);
+
+    // Activity counts the tip the pool just staked.
+    until("the pool activity", || async {
+        let pool = db::getpool(&db, POOL).await.unwrap();
+        pool.is_some_and(|pool| pool.activity.is_some())
+    })
+    .await;
+    let activity = db::getpool(&db, POOL).await.unwrap().unwrap().activity;
+    let activity = activity.unwrap();
+    assert_eq!(activity.status, PoolStatus::Active);
+    assert_eq!(activity.recent_blocks, 2);
+    assert_eq!(activity.last_height, Some(10));
+    until("the relabelled stakeaddress", || async {
+        let stakeaddress = db::getstakeaddress(&db, "rtstakepool").await.unwrap();
+        stakeaddress.unwrap().pool.status == Some(PoolStatus::Active)
+    })
+    .await;
+
+    // Statistics go by the registry too.
+    until("the pool statistics", || async {
+        let stats = db::getpoolstats(&db, POOL, Period::Week, 10, 0)
+            .await
+            .unwrap();
+        !stats.is_empty()
+    })
+    .await;
+    let stats = db::getpoolstats(&db, POOL, Period::Week, 10, 0)
+        .await
+        .unwrap();
+    assert_eq!(stats.iter().map(|stats| stats.blocks).sum::<u64>(), 2);
+}
+
+#[tokio::test]
+async fn the_network_guard_keeps_networks_apart() {
+    let config = Config {
+        db: Db {
+            address: Some("mem://".to_string()),
+            stage: Some("test".to_string()),
+        },
+        ..Default::default()
+    };
+    let db = db::init(&config).await.unwrap();
+    engine::guard(&db, Network::Regtest).await.unwrap();
+    assert_eq!(db::getnetwork(&db).await.unwrap(), Some(Network::Regtest));
+    engine::guard(&db, Network::Regtest).await.unwrap();
+    assert!(engine::guard(&db, Network::Mainnet).await.is_err());
+
+    // Blocks of a DB from before networks were recorded are mainnet ones.
+    let db = db::init(&config).await.unwrap();
+    db.query("CREATE blocks:1 SET height = 1").await.unwrap();
+    assert!(engine::guard(&db, Network::Testnet).await.is_err());
+    engine::guard(&db, Network::Mainnet).await.unwrap();
+    assert_eq!(db::getnetwork(&db).await.unwrap(), Some(Network::Mainnet));
+}
+
+#[tokio::test]
+async fn listen_indexes_announced_blocks() {
+    let ghostd = Ghostd::start(3);
+    let db = engine(ghostd.config(None));
+    let tip = ghostd.hash(2);
+    until("the catch up", || indexed(&db, 2, &tip)).await;
+
+    let hash = ghostd.mine(0, |_| {});
+    // Subscribers miss whatever is published before they connect.
+    until("the announced block", || {
+        ghostd.announce(&hash);
+        indexed(&db, 3, &hash)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn listen_reloads_the_registry_while_idle() {
+    let ghostd = Ghostd::start(3);
+    ghostd.stake("rtstakeidle", POOL);
+    ghostd.mine(0, |block| coldstake(block, "rtstakeidle"));
+    let path = registry("Idle Pool", POOL);
+    let db = engine(ghostd.config(Some(path.clone())));
+    let tip = ghostd.hash(3);
+    until("the catch up", || indexed(&db, 3, &tip)).await;
+
+    std::fs::write(
+        &path,
+        format!(
+            "[[pool]]\npubkey = \"{}\"\nname = \"Renamed Pool\"\nactive = true\n",
+            POOL
+        ),
+    )
+    .unwrap();
+    until("the reloaded registry", || async {
+        let pool = db::getpool(&db, POOL).await.unwrap();
+        pool.is_some_and(|pool| pool.entry.name.as_deref() == Some("Renamed Pool"))
+    })
+    .await;
+    // The block keeps its label, reads go by the registry.
+    let block = db::getblockbyheight(&db, 3).await.unwrap().unwrap();
+    let mut pool = block.coldstaking.unwrap();
+    assert_eq!(pool.name.as_deref(), Some("Idle Pool"));
+    pools::Labels::load(&db).await.unwrap().relabel(&mut pool);
+    assert_eq!(pool.name.as_deref(), Some("Renamed Pool"));
+}
+
+#[tokio::test]
+async fn listen_rolls_back_reorganisations() {
+    let ghostd = Ghostd::start(5);
+    ghostd.mine(0, |block| vote(block, 9, 1));
+    let db = engine(ghostd.config(None));
+    let tip = ghostd.hash(5);
+    until("the catch up", || indexed(&db, 5, &tip)).await;
+    until("the statistics", || async {
+        !db::getrecords(&db, "network_stats")
+            .await
+            .unwrap()
+            .is_empty()
+    })
+    .await;
+    assert!(db::getproposal(&db, 9).await.unwrap().is_some());
+    assert_eq!(db::gettip(&db).await.unwrap(), Some(5));
+    let orphaned = format!("{:064x}", (1u64 << 63) | 5);
+    assert!(db::gettx(&db, &orphaned).await.unwrap().is_some());
+
+    // Blocks 4 and 5 are replaced by a longer branch.
+    ghostd.truncate(4);
+    for _ in 0..3 {
+        ghostd.mine(1, |_| {});
+    }
+    let tip = ghostd.hash(6);
+    until("the new tip", || {
+        ghostd.announce(&tip);
+        indexed(&db, 6, &tip)
+    })
+    .await;
+    assert_eq!(db::gettip(&db).await.unwrap(), Some(6));
+    let chain = db::getchain(&db, 0, 6).await.unwrap();
+    assert_eq!(chain.len(), 7);
+    for link in chain {
+        assert_eq!(link.hash, ghostd.hash(link.height));
+    }
+    // The vote only the orphaned block cast goes with it, and so do the
+    // statistics counting it until they are materialized again.
+    assert!(db::getrecords(&db, "votes").await.unwrap().is_empty());
+    assert!(db::getproposal(&db, 9).await.unwrap().is_none());
+    assert!(db::gettx(&db, &orphaned).await.unwrap().is_none());
+    // The new block 5 pays the same staker as the orphaned one.
+    let heights = db::getaddressheights(&db, "rtstaker5", 10, 0)
+        .await
+        .unwrap();
+    assert_eq!(heights, [5]);
+    assert!(db::getrecords(&db, "network_stats")
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn votes_register_proposals() {
+    let ghostd = Ghostd::start(1);
+    ghostd.stake("rtstakevoter", POOL);
+    for option in [1, 2, 1] {
+        ghostd.mine(0, |block| {
+            coldstake(block, "rtstakevoter");
+            vote(block, 7, option)
+        });
+    }
+    let db = engine(ghostd.config(None));
+    let tip = ghostd.hash(3);
+    until("the tip", || indexed(&db, 3, &tip)).await;
+
+    assert_eq!(db::getproposalids(&db).await.unwrap(), vec![7]);
+    let proposal = db::getproposal(&db, 7)
+        .await
+        .unwrap()
+        .expect("the proposal is recorded");
+    assert_eq!(proposal.stats["Option 1"], (2, 50.0));
+    assert_eq!(proposal.stats["Option 2"], (1, 25.0));
+    assert_eq!(db::getrecords(&db, "votes").await.unwrap().len(), 3);
+
+    let changes = db::getvotechanges(&db, 7).await.unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].stakeaddress, "rtstakevoter");
+    assert_eq!(changes[0].pool.as_deref(), Some(POOL));
+    assert_eq!(changes[0].history, vec![(1, 1), (2, 2), (3, 1)]);
+}
+
+#[tokio::test]
+async fn catchup_rides_out_node_failures() {
+    let ghostd = Ghostd::start(5);
+    // Used to end the catch up at the first refused call.
+    ghostd.fail(3);
+    let mut config = ghostd.config(None);
+    config.retry.initial_ms = 10;
+    let db = engine(config);
+    let tip = ghostd.hash(4);
+    until("the tip", || indexed(&db, 4, &tip)).await;
+}
+
+#[tokio::test]
+async fn rpc_fails_over_to_the_next_node() {
+    let ghostd = Ghostd::start(5);
+    // Nothing listens on a port just given back.
+    let dead = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let mut config = ghostd.config(None);
+    config.rpc.ip = Some(dead);
+    config.rpc.fallback = vec![ghostd.rpc.clone()];
+    config.retry.initial_ms = 10;
+    let db = engine(config);
+    let tip = ghostd.hash(4);
+    until("the tip", || indexed(&db, 4, &tip)).await;
+}
+
+#[tokio::test]
+async fn rpc_asks_the_next_node_for_blocks_one_lacks() {
+    let behind = Ghostd::start(3);
+    let ahead = Ghostd::start(5);
+    let mut config = behind.config(None);
+    config.rpc.fallback = vec![ahead.rpc.clone()];
+    let db = engine(config);
+    let tip = ahead.hash(4);
+    until("the blocks only one node has", || indexed(&db, 4, &tip)).await;
+}
+
+#[tokio::test]
+async fn probes_give_up_on_a_dead_node_at_once() {
+    let ghostd = Ghostd::start(1);
+    let dead = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let mut config = ghostd.config(None);
+    config.rpc.ip = Some(dead);
+    // Retrying calls would keep at it for minutes.
+    config.retry.retries = 1000;
+    let rpcurl = engine::rpcurl(&config).unwrap();
+    let started = std::time::Instant::now();
+    assert!(rpc::probe("getblockcount", &rpcurl).await.is_err());
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn crosscheck_holds_back_blocks_the_nodes_disagree_on() {
+    let ghostd = Ghostd::start(4);
+    let other = Ghostd::start(3);
+    other.mine(1, |_| {});
+    let mut config = ghostd.config(None);
+    config.rpc.fallback = vec![other.rpc.clone()];
+    config.rpc.crosscheck = true;
+    config.retry.retries = 1000;
+    config.retry.initial_ms = 10;
+    config.retry.max_ms = 50;
+    let db = engine(config);
+    let agreed = ghostd.hash(2);
+    until("the blocks both nodes have", || indexed(&db, 2, &agreed)).await;
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert!(db::gethash(&db, 3).await.unwrap().is_none());
+
+    // The other node reorganises onto the same chain.
+    other.truncate(3);
+    other.mine(0, |_| {});
+    let tip = ghostd.hash(3);
+    until("the tip", || indexed(&db, 3, &tip)).await;
+}
+
+#[tokio::test]
+async fn mempool_follows_transactions_until_they_confirm() {
+    let ghostd = Ghostd::start(3);
+    // Waiting before the parser starts, for the first sweep to find.
+    let waiting = blockhash(1, 9);
+    ghostd.broadcast(&waiting, 200, 0.0001, GENESIS_TIME);
+    let mut config = ghostd.config(None);
+    config.zmq.hashtx = Some(ghostd.zmq.clone());
+    let db = engine(config.clone());
+    until("the waiting transaction", || tracked(&db, &waiting)).await;
+
+    let announced = blockhash(2, 9);
+    ghostd.broadcast(&announced, 250, 0.0005, GENESIS_TIME + 120);
+    until("the announced transaction", || {
+        ghostd.announce_tx(&announced);
+        tracked(&db, &announced)
+    })
+    .await;
+    let stats = mempool::stats(&db).await.unwrap();
+    assert_eq!((stats.size, stats.vbytes), (2, 450));
+    let feerate = stats.feerate.unwrap();
+    assert_eq!((feerate.min, feerate.max), (50.0, 200.0));
+
+    // Block 3 is mined a minute after the transaction was first seen.
+    let hash = ghostd.confirm(&announced);
+    until("the confirming block", || {
+        ghostd.announce(&hash);
+        indexed(&db, 3, &hash)
+    })
+    .await;
+    assert!(!tracked(&db, &announced).await);
+
+    ghostd.evict(&waiting);
+    let rpcurl = engine::rpcurl(&config).unwrap();
+    mempool::sweep(&db, &rpcurl).await.unwrap();
+    assert!(!tracked(&db, &waiting).await);
+
+    let stats = mempool::stats(&db).await.unwrap();
+    assert_eq!((stats.size, stats.confirmed), (0, 1));
+    assert_eq!(stats.latency.unwrap().median, 60.0);
+}
@@ -0,0 +1,197 @@
+// Dumps of an indexed block in each format, read back the way a
+// notebook would.
+use ghostd_parser::{
+    config::{Config, Db},
+    console::{BlockData, Stakeaddress, VoteRecord},
+    db,
+    decode::{self, HeaderInfo},
+    export::{self, Sink},
+    network::Network,
+    pools::Pool,
+};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use std::{fs, path::PathBuf};
+use surrealdb::{engine::any::Any, Surreal};
+
+fn block() -> BlockData {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/raw/coldstake.hex");
+    let raw = decode::unhex(fs::read_to_string(path).unwrap().trim()).unwrap();
+    let info = HeaderInfo {
+        hash: "d46fc54c6b8653c089862f280dff1131fa12fe806e38b9ea6cd02294f084fc7f".to_string(),
+        height: 1234,
+        mediantime: 1_699_999_000,
+        chainwork: format!("{:064x}", 1235),
+        difficulty: 1.0,
+        ..Default::default()
+    };
+    let mut blockdata = decode::block(&raw, info, Network::Mainnet).unwrap();
+    blockdata.read_vote().unwrap();
+    blockdata
+}
+
+async fn indexed(blockdata: &BlockData) -> Surreal<Any> {
+    let config = Config {
+        db: Db {
+            address: Some("mem://".to_string()),
+            stage: Some("test".to_string()),
+        },
+        ..Default::default()
+    };
+    let db = db::init(&config).await.unwrap();
+    db::regblock(&db, blockdata).await.unwrap();
+    db
+}
+
+async fn dump(db: &Surreal<Any>, table: &str, format: &str) -> (PathBuf, u64) {
+    let path = std::env::temp_dir().join(format!(
+        "ghostd-parser-{}-{}.{}",
+        std::process::id(),
+        table,
+        format
+    ));
+    let mut sink = Sink::open(format, path.to_str(), table).unwrap();
+    let rows = export::export(db, table, None, None, &mut sink)
+        .await
+        .unwrap();
+    sink.finish().unwrap();
+    (path, rows)
+}
+
+#[tokio::test]
+async fn outputs_flatten_into_fixed_csv_columns() {
+    let blockdata = block();
+    let db = indexed(&blockdata).await;
+    let (path, rows) = dump(&db, "outputs", "csv").await;
+    let outputs: usize = blockdata.tx.iter().map(|tx| tx.vout.len()).sum();
+    assert_eq!(rows, outputs as u64);
+
+    let content = fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(
+        lines[0],
+        "height,txid,n,type,value,valuesat,script_type,address,stakeaddress,pubkey,value_commitment,data_hex,vote"
+    );
+    assert_eq!(lines.len(), outputs + 1);
+    let staked: Vec<&str> = lines[2].split(',').collect();
+    assert_eq!(
+        &staked[..5],
+        ["1234", &blockdata.tx[0].txid, "1", "standard", "1524.5441"]
+    );
+    assert_eq!(staked[8], "GLgd6dSLywi5NzxCaWU1ThLCSJQCJytQQY");
+    fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn blocks_read_back_from_parquet() {
+    let blockdata = block();
+    let db = indexed(&blockdata).await;
+    let (path, rows) = dump(&db, "blocks", "parquet").await;
+    assert_eq!(rows, 1);
+
+    let reader = SerializedFileReader::new(fs::File::open(&path).unwrap()).unwrap();
+    let metadata = reader.metadata().file_metadata();
+    assert_eq!(metadata.num_rows(), 1);
+    let names: Vec<&str> = metadata
+        .schema_descr()
+        .columns()
+        .iter()
+        .map(|column| column.name())
+        .collect();
+    assert_eq!(&names[..3], ["height", "hash", "time"]);
+    let row = reader.get_row_iter(None).unwrap().next().unwrap().unwrap();
+    let text = row.to_string();
+    assert!(text.contains("height: 1234"), "{}", text);
+    assert!(text.contains("proposal: 12"), "{}", text);
+    assert!(
+        text.contains("staker: \"GLgd6dSLywi5NzxCaWU1ThLCSJQCJytQQY\""),
+        "{}",
+        text
+    );
+    // Blocks staked without a pool leave it null.
+    assert!(text.contains("pool: null"), "{}", text);
+    fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn json_lines_hold_the_same_columns() {
+    let blockdata = block();
+    let db = indexed(&blockdata).await;
+    let (path, rows) = dump(&db, "blocks", "jsonl").await;
+    assert_eq!(rows, 1);
+    let record: serde_json::Value =
+        serde_json::from_str(fs::read_to_string(&path).unwrap().trim()).unwrap();
+    assert_eq!(record["height"], 1234);
+    assert_eq!(record["proposal"], 12);
+    assert!(record.get("tx").is_none() && record.get("id").is_none());
+    fs::remove_file(path).unwrap();
+
+    // Stored tables come out flat too, the pool label spread over columns.
+    let stakeaddress = Stakeaddress {
+        raw: "GLgd6dSLywi5NzxCaWU1ThLCSJQCJytQQY".to_string(),
+        pool: Pool::unknown("gcs1runz6dpmgfy4q467v4k8x75p3z8ed8dy0830fe"),
+    };
+    db::regstakeaddress(&db, &stakeaddress).await.unwrap();
+    let (path, rows) = dump(&db, "stakeaddresses", "jsonl").await;
+    assert_eq!(rows, 1);
+    let record: serde_json::Value =
+        serde_json::from_str(fs::read_to_string(&path).unwrap().trim()).unwrap();
+    assert_eq!(record["stakeaddress"], stakeaddress.raw.as_str());
+    assert_eq!(record["pubkey"], stakeaddress.pool.pubkey.as_str());
+    assert!(record.get("pool").is_none() && record.get("id").is_none());
+    fs::remove_file(path).unwrap();
+
+    let (path, rows) = dump(&db, "transactions", "jsonl").await;
+    assert_eq!(rows, blockdata.tx.len() as u64);
+    let content = fs::read_to_string(&path).unwrap();
+    let first: serde_json::Value = serde_json::from_str(content.lines().next().unwrap()).unwrap();
+    assert_eq!(first["coinstake"], true);
+    assert_eq!(first["txid"], blockdata.tx[0].txid.as_str());
+    fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn ranges_are_refused_for_tables_not_keyed_by_height() {
+    let blockdata = block();
+    let db = indexed(&blockdata).await;
+    let path =
+        std::env::temp_dir().join(format!("ghostd-parser-export-{}.jsonl", std::process::id()));
+    let mut sink = Sink::open("jsonl", path.to_str(), "pools").unwrap();
+    assert!(export::export(&db, "pools", Some(1), None, &mut sink)
+        .await
+        .is_err());
+    let rows = export::export(&db, "outputs", Some(1234), Some(1234), &mut sink)
+        .await
+        .unwrap();
+    assert!(rows > 0);
+    fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn ranges_page_through_heights_without_records() {
+    let blockdata = block();
+    let db = indexed(&blockdata).await;
+    for height in [3, 1500, 2999] {
+        let vote = VoteRecord {
+            height,
+            proposal: 1,
+            option: 2,
+            stakeaddress: None,
+            pool: None,
+        };
+        db::regvote(&db, &vote).await.unwrap();
+    }
+    let path = std::env::temp_dir().join(format!(
+        "ghostd-parser-export-{}-votes.jsonl",
+        std::process::id()
+    ));
+    let mut sink = Sink::open("jsonl", path.to_str(), "votes").unwrap();
+    let rows = export::export(&db, "votes", Some(0), Some(3000), &mut sink)
+        .await
+        .unwrap();
+    assert_eq!(rows, 3);
+    let rows = export::export(&db, "votes", Some(4), Some(2999), &mut sink)
+        .await
+        .unwrap();
+    assert_eq!(rows, 2);
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,142 @@
+// Deserialization regression tests over the getblock responses in
+// tests/fixtures/blocks. The files follow ghostd's `getblock <hash> 2 true`
+// output, one per kind of block the parser has to cope with. They were
+// put together by hand, with addresses that do match their scripts but
+// hashes, signatures and transaction hex that are made up; replace them
+// with captures from a node, `ghostd-parser record-fixture <height> -o <file>`.
+use ghostd_parser::{
+    config::{Config, Db},
+    console::{BlockData, Vin, Vout},
+    db,
+};
+use serde_json::Value;
+use std::{fs, path::PathBuf};
+
+fn dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/blocks")
+}
+
+fn raw(name: &str) -> Value {
+    let path = dir().join(format!("{}.json", name));
+    serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap()
+}
+
+fn parse(name: &str, raw: &Value) -> BlockData {
+    match serde_json::from_value(raw.clone()) {
+        Ok(blockdata) => blockdata,
+        Err(e) => panic!("{} does not deserialize: {}", name, e),
+    }
+}
+
+fn corpus() -> Vec<(String, Value)> {
+    let mut corpus: Vec<(String, Value)> = fs::read_dir(dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .map(|path| {
+            let name = path.file_stem().unwrap().to_string_lossy().to_string();
+            let raw = raw(&name);
+            (name, raw)
+        })
+        .collect();
+    corpus.sort_by(|a, b| a.0.cmp(&b.0));
+    assert!(!corpus.is_empty(), "No fixtures in {:?}", dir());
+    corpus
+}
+
+#[test]
+fn every_fixture_round_trips() {
+    for (name, raw) in corpus() {
+        let blockdata = parse(&name, &raw);
+        let serialized = serde_json::to_value(&blockdata).unwrap();
+        let again = serde_json::to_value(parse(&name, &serialized)).unwrap();
+        assert_eq!(serialized, again, "{} changes when read back", name);
+        assert_eq!(serialized["hash"], raw["hash"], "{}", name);
+        assert_eq!(blockdata.tx.len() as u64, blockdata.n_tx, "{}", name);
+    }
+}
+
+#[test]
+fn outputs_and_inputs_keep_their_type() {
+    for (name, raw) in corpus() {
+        let blockdata = parse(&name, &raw);
+        for (tx, rawtx) in blockdata.tx.iter().zip(raw["tx"].as_array().unwrap()) {
+            for (vout, rawvout) in tx.vout.iter().zip(rawtx["vout"].as_array().unwrap()) {
+                assert_eq!(vout.kind(), rawvout["type"], "{} {}", name, tx.txid);
+            }
+            for (vin, rawvin) in tx.vin.iter().zip(rawtx["vin"].as_array().unwrap()) {
+                let anon = matches!(vin, Vin::Anon { .. });
+                assert_eq!(anon, rawvin["type"] == "anon", "{} {}", name, tx.txid);
+            }
+        }
+    }
+}
+
+#[test]
+fn coldstaking_names_the_stakeaddress() {
+    let blockdata = parse("coldstaking", &raw("coldstaking"));
+    let coinstake = &blockdata.tx[0];
+    let Vout::Standard { scriptpubkey, .. } = &coinstake.vout[1] else {
+        panic!("The coinstake pays out through a standard output.");
+    };
+    assert!(scriptpubkey.stakeaddresses.is_some());
+    assert_eq!(blockdata.staker(), scriptpubkey.owner());
+    assert_eq!(
+        blockdata.staker(),
+        scriptpubkey
+            .stakeaddresses
+            .as_ref()
+            .unwrap()
+            .first()
+            .cloned()
+    );
+}
+
+#[test]
+fn votes_are_read_from_the_data_output() {
+    let mut blockdata = parse("vote", &raw("vote"));
+    blockdata.read_vote().unwrap();
+    let vote = blockdata.voting_info.expect("The block carries a vote.");
+    assert_eq!((vote.proposal_id, vote.voted_for_option), (12, 1));
+
+    let mut blockdata = parse("data", &raw("data"));
+    blockdata.read_vote().unwrap();
+    assert!(blockdata.voting_info.is_none());
+}
+
+#[test]
+fn treasury_carry_forward_is_kept() {
+    let blockdata = parse("treasury", &raw("treasury"));
+    match &blockdata.tx[0].vout[0] {
+        Vout::Data {
+            treasury_fund_cfwd, ..
+        } => assert_eq!(*treasury_fund_cfwd, Some(57.81)),
+        other => panic!("Expected the data output, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn fixtures_survive_the_db() {
+    let config = Config {
+        db: Db {
+            address: Some("mem://".to_string()),
+            stage: Some("test".to_string()),
+        },
+        ..Default::default()
+    };
+    let db = db::init(&config).await.unwrap();
+    for (name, raw) in corpus() {
+        let blockdata = parse(&name, &raw);
+        db::regblock(&db, &blockdata).await.unwrap();
+        let stored = db::getblockbyheight(&db, blockdata.height)
+            .await
+            .unwrap()
+            .unwrap_or_else(|| panic!("{} was not stored", name));
+        assert_eq!(
+            serde_json::to_value(&stored).unwrap(),
+            serde_json::to_value(&blockdata).unwrap(),
+            "{} changes in the DB",
+            name
+        );
+    }
+}
@@ -0,0 +1,102 @@
+// Queries against the GraphQL schema over an in-memory DB.
+use ghostd_parser::{
+    activity::{PoolActivity, PoolStatus},
+    config::{Config, Db},
+    console::Proposal,
+    db, graphql,
+    pools::PoolEntry,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use surrealdb::{engine::any::Any, Surreal};
+
+const POOL: &str = "gcs1qgraphqlpool";
+
+async fn memdb() -> Surreal<Any> {
+    let config = Config {
+        db: Db {
+            address: Some("mem://".to_string()),
+            stage: Some("test".to_string()),
+        },
+        ..Default::default()
+    };
+    db::init(&config).await.unwrap()
+}
+
+async fn query(db: &Surreal<Any>, query: &str) -> Value {
+    let response = graphql::schema(db.clone()).execute(query).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    response.data.into_json().unwrap()
+}
+
+#[tokio::test]
+async fn pools_carry_the_derived_status() {
+    let db = memdb().await;
+    // The registry still lists the pool as inactive.
+    let entry = PoolEntry {
+        pubkey: POOL.to_string(),
+        name: Some("GraphQL Pool".to_string()),
+        url: None,
+        active: false,
+        fee: None,
+        first_seen: None,
+        last_seen: None,
+    };
+    db::regpool(&db, &entry).await.unwrap();
+    let data = query(&db, "{ pools { pubkey active status } }").await;
+    assert_eq!(
+        data,
+        json!({ "pools": [{ "pubkey": POOL, "active": false, "status": null }] })
+    );
+
+    let activity = PoolActivity {
+        status: PoolStatus::Active,
+        last_height: Some(10),
+        last_time: Some(1_700_000_000),
+        recent_blocks: 2,
+        share: 0.5,
+        previous_share: 0.0,
+        trend: 0.5,
+    };
+    db::regpoolactivity(&db, POOL, &activity).await.unwrap();
+    let data = query(&db, "{ pools { pubkey active status } }").await;
+    assert_eq!(
+        data,
+        json!({ "pools": [{ "pubkey": POOL, "active": true, "status": "ACTIVE" }] })
+    );
+    let data = query(
+        &db,
+        &format!("{{ pool(pubkey: \"{}\") {{ active status }} }}", POOL),
+    )
+    .await;
+    assert_eq!(
+        data,
+        json!({ "pool": { "active": true, "status": "ACTIVE" } })
+    );
+    let data = query(&db, "{ pools { activity { status recentBlocks share } } }").await;
+    assert_eq!(
+        data,
+        json!({ "pools": [{ "activity": { "status": "ACTIVE", "recentBlocks": 2, "share": 0.5 } }] })
+    );
+}
+
+#[tokio::test]
+async fn proposal_stats_are_typed() {
+    let db = memdb().await;
+    let proposal = Proposal {
+        proposal_id: 9,
+        stats: HashMap::from([
+            ("Option 2".to_string(), (1, 25.0)),
+            ("Option 1".to_string(), (3, 75.0)),
+        ]),
+    };
+    db::regproposal(&db, &proposal).await.unwrap();
+    let data = query(&db, "{ proposal(id: 9) { stats { option votes share } } }").await;
+    assert_eq!(
+        data,
+        json!({ "proposal": { "stats": [
+            { "option": "Option 1", "votes": 3, "share": 75.0 },
+            { "option": "Option 2", "votes": 1, "share": 25.0 },
+        ] } })
+    );
+}
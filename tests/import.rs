@@ -0,0 +1,194 @@
+// Bulk import from blk*.dat files, written here the way ghostd stores
+// blocks: out of order, with a stale branch and preallocated zeros.
+mod common;
+
+use bitcoin_hashes::{sha256d, Hash};
+use common::registry;
+use ghostd_parser::{
+    config::{Config, Db},
+    db, import,
+    network::Network,
+    pools::Registry,
+};
+use std::path::PathBuf;
+use surrealdb::{engine::any::Any, Surreal};
+
+const MAGIC: [u8; 4] = [0xfb, 0xf2, 0xef, 0xb4];
+const POOL: &str = "gcs1runz6dpmgfy4q467v4k8x75p3z8ed8dy0830fe";
+
+fn stakekey() -> Vec<u8> {
+    (0..20u32).map(|i| (31 + i * 7) as u8).collect()
+}
+
+fn var(bytes: &[u8]) -> Vec<u8> {
+    let mut out = vec![bytes.len() as u8];
+    out.extend_from_slice(bytes);
+    out
+}
+
+// A coinstake paying to a plain key hash, or coldstaking through the pool.
+fn coinstake(height: u32, coldstake: bool) -> Vec<u8> {
+    let keyhash: Vec<u8> = (0..20).map(|i| height as u8 ^ i).collect();
+    let p2pkh = |hash: &[u8]| [&[0x76, 0xa9, 20], hash, &[0x88, 0xac]].concat();
+    let script = match coldstake {
+        true => [
+            &[0xb8, 0x63][..],
+            &p2pkh(&stakekey()),
+            &[0x67],
+            &p2pkh(&keyhash),
+            &[0x68],
+        ]
+        .concat(),
+        false => p2pkh(&keyhash),
+    };
+    let mut tx = vec![0xa0, 0x02, 0, 0, 0, 0, 1];
+    tx.extend_from_slice(&[height as u8; 32]);
+    tx.extend_from_slice(&[1, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+    tx.push(2);
+    tx.push(4);
+    tx.extend(var(&height.to_le_bytes()));
+    tx.push(1);
+    tx.extend_from_slice(&1_250_000_000i64.to_le_bytes());
+    tx.extend(var(&script));
+    // One empty witness stack.
+    tx.push(0);
+    tx
+}
+
+const BITS: u32 = 0x1f00_ffff;
+
+// A block on top of `prev`, and its hash in internal byte order.
+fn block(prev: [u8; 32], height: u32, time: u32, coldstake: bool) -> (Vec<u8>, [u8; 32]) {
+    mined(prev, height, time, coldstake, BITS)
+}
+
+fn mined(
+    prev: [u8; 32],
+    height: u32,
+    time: u32,
+    coldstake: bool,
+    bits: u32,
+) -> (Vec<u8>, [u8; 32]) {
+    let mut header = 0x2000_0000u32.to_le_bytes().to_vec();
+    header.extend_from_slice(&prev);
+    header.extend_from_slice(&[height as u8; 32]);
+    header.extend_from_slice(&[0; 32]);
+    header.extend_from_slice(&time.to_le_bytes());
+    header.extend_from_slice(&bits.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    let hash = sha256d::Hash::hash(&header).to_byte_array();
+    let mut block = header;
+    block.push(1);
+    block.extend(coinstake(height, coldstake));
+    block.extend(var(&[0x30; 8]));
+    (block, hash)
+}
+
+fn record(block: &[u8]) -> Vec<u8> {
+    [&MAGIC[..], &(block.len() as u32).to_le_bytes(), block].concat()
+}
+
+fn display(hash: &[u8; 32]) -> String {
+    hash.iter()
+        .rev()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// Blocks 0 to 3 with a stale block 2 beside the chain, and the hashes of the chain.
+fn blocksdir() -> (PathBuf, Vec<String>) {
+    let (b0, h0) = block([0; 32], 0, 1_700_000_000, false);
+    let (b1, h1) = block(h0, 1, 1_700_000_060, false);
+    let (b2, h2) = block(h1, 2, 1_700_000_120, false);
+    let (stale, _) = block(h1, 2, 1_700_000_130, true);
+    let (b3, h3) = block(h2, 3, 1_700_000_180, true);
+    let dir = blk("longest", &[vec![b2, b0, stale], vec![b3, b1]]);
+    (dir, [h0, h1, h2, h3].iter().map(display).collect())
+}
+
+// Files holding the given blocks, one file per list.
+fn blk(name: &str, files: &[Vec<Vec<u8>>]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "ghostd-parser-blocks-{}-{}",
+        name,
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (n, blocks) in files.iter().enumerate() {
+        let mut bytes: Vec<u8> = blocks.iter().flat_map(|block| record(block)).collect();
+        bytes.extend_from_slice(&[0; 64]);
+        std::fs::write(dir.join(format!("blk{:05}.dat", n)), bytes).unwrap();
+    }
+    dir
+}
+
+async fn memdb() -> Surreal<Any> {
+    let config = Config {
+        db: Db {
+            address: Some("mem://".to_string()),
+            stage: Some("test".to_string()),
+        },
+        ..Default::default()
+    };
+    db::init(&config).await.unwrap()
+}
+
+#[tokio::test]
+async fn import_loads_the_longest_chain_in_order() {
+    let (dir, hashes) = blocksdir();
+    let db = memdb().await;
+    let path = registry("Import Pool", POOL).to_string_lossy().to_string();
+    let mut registry = Registry::load(Some(&path), Network::Mainnet).unwrap();
+
+    let tip = import::load(&dir, &db, &mut registry, Network::Mainnet).await;
+    assert_eq!(tip.unwrap(), Some(3));
+    let chain = db::getchain(&db, 0, 3).await.unwrap();
+    let indexed: Vec<&str> = chain.iter().map(|link| link.hash.as_str()).collect();
+    assert_eq!(indexed, hashes);
+
+    let block = db::getblockbyheight(&db, 3).await.unwrap().unwrap();
+    let pool = block.coldstaking.expect("block 3 is coldstaked");
+    assert_eq!(pool.pubkey, POOL);
+    assert_eq!(pool.name.as_deref(), Some("Import Pool"));
+    assert_eq!(block.mediantime, 1_700_000_120);
+
+    // Running it again finds nothing new.
+    let tip = import::load(&dir, &db, &mut registry, Network::Mainnet).await;
+    assert_eq!(tip.unwrap(), Some(3));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn import_follows_the_most_work_not_the_most_blocks() {
+    let (b0, h0) = block([0; 32], 0, 1_700_000_000, false);
+    let (b1, h1) = block(h0, 1, 1_700_000_060, false);
+    let (long2, l2) = block(h1, 2, 1_700_000_120, false);
+    let (long3, _) = block(l2, 3, 1_700_000_180, false);
+    // A target a quarter as large counts four times the work.
+    let (heavy, hh) = mined(h1, 2, 1_700_000_130, false, 0x1e3f_ffff);
+    let dir = blk("work", &[vec![b0, b1, long2, long3, heavy]]);
+    let db = memdb().await;
+    let mut registry = Registry::load(None, Network::Mainnet).unwrap();
+
+    let tip = import::load(&dir, &db, &mut registry, Network::Mainnet).await;
+    assert_eq!(tip.unwrap(), Some(2));
+    assert_eq!(db::gethash(&db, 2).await.unwrap(), Some(display(&hh)));
+    assert!(db::gethash(&db, 3).await.unwrap().is_none());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn import_keeps_the_first_stored_of_equal_tips() {
+    let (b0, h0) = block([0; 32], 0, 1_700_000_000, false);
+    let (b1, h1) = block(h0, 1, 1_700_000_060, false);
+    let (one, h2) = block(h1, 2, 1_700_000_120, false);
+    let (other, _) = block(h1, 2, 1_700_000_130, true);
+    let dir = blk("tie", &[vec![b0, b1], vec![one, other]]);
+    let db = memdb().await;
+    let mut registry = Registry::load(None, Network::Mainnet).unwrap();
+
+    let tip = import::load(&dir, &db, &mut registry, Network::Mainnet).await;
+    assert_eq!(tip.unwrap(), Some(2));
+    assert_eq!(db::gethash(&db, 2).await.unwrap(), Some(display(&h2)));
+    std::fs::remove_dir_all(dir).unwrap();
+}
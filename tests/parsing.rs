@@ -0,0 +1,71 @@
+// Malformed node output must come back as errors, never as panics.
+// Cases found while fuzzing are kept here.
+use ghostd_parser::console::{parse_tallies, parse_tally, parse_vote, BlockData, ParseError};
+use serde_json::json;
+
+#[test]
+fn votes() {
+    let vote = parse_vote("12, 1").unwrap();
+    assert_eq!((vote.proposal_id, vote.voted_for_option), (12, 1));
+    for malformed in [
+        "", "12", "12,1", "12, ", ", 1", "12, 1, 3", "-1, 2", "12, x",
+    ] {
+        assert_eq!(
+            parse_vote(malformed).unwrap_err(),
+            ParseError::Vote(malformed.to_string())
+        );
+    }
+}
+
+#[test]
+fn tallies() {
+    assert_eq!(parse_tally("2, 66.67%").unwrap(), (2, 66.67));
+    for malformed in ["", "2", "2,66.67%", "x, 1%", "2, %", "-2, 1%"] {
+        assert!(parse_tally(malformed).is_err(), "{}", malformed);
+    }
+
+    let tallies = parse_tallies(json!({
+        "proposal": 7,
+        "height_start": 0,
+        "height_end": 2147483647,
+        "blocks_counted": 3,
+        "Option 1": "2, 66.67%",
+        "Option 2": "1, 33.33%",
+    }))
+    .unwrap();
+    assert_eq!(tallies.len(), 2);
+    assert_eq!(tallies["Option 2"], (1, 33.33));
+    assert!(parse_tallies(json!({ "Option 1": 2 })).is_err());
+    assert!(parse_tallies(json!(["Option 1", "2, 66.67%"])).is_err());
+}
+
+#[test]
+fn blocks_without_a_coinstake() {
+    let mut blockdata: BlockData = serde_json::from_value(json!({
+        "bits": "1a0f8c1e",
+        "chainwork": "00",
+        "difficulty": 1.0,
+        "hash": "00",
+        "height": 1,
+        "mediantime": 0,
+        "merkleroot": "00",
+        "nTx": 0,
+        "nonce": 0,
+        "size": 0,
+        "strippedsize": 0,
+        "time": 0,
+        "tx": [],
+        "version": 2,
+        "versionHex": "00000002",
+        "weight": 0,
+        "witnessmerkleroot": "00",
+    }))
+    .unwrap();
+    assert!(matches!(
+        blockdata.stakeaddress(),
+        Err(ParseError::Block(_))
+    ));
+    assert!(blockdata.read_vote().is_ok());
+    assert!(blockdata.voting_info.is_none());
+    assert!(blockdata.staker().is_none());
+}